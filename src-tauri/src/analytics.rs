@@ -0,0 +1,444 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::builder::{Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::NaiveDate;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::error::AppError;
+use crate::types::{AnalyticsFilter, TrackRecord};
+
+/// Rows per Parquet row group. Rows are buffered until a group is full, which
+/// bounds memory use however large the export is.
+const ROW_GROUP_SIZE: usize = 65_536;
+
+/// File format of an analytics export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableFormat {
+    Csv,
+    Parquet,
+}
+
+impl TableFormat {
+    pub fn parse(format: &str) -> Result<Self, AppError> {
+        match format.to_ascii_lowercase().as_str() {
+            "csv" => Ok(TableFormat::Csv),
+            "parquet" => Ok(TableFormat::Parquet),
+            other => Err(AppError::InvalidArgument(format!(
+                "Unknown analytics format: {}",
+                other
+            ))),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            TableFormat::Csv => "csv",
+            TableFormat::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnKind {
+    Int,
+    Float,
+    Text,
+}
+
+/// One value of an exported row; None is written as an empty CSV field or a Parquet null
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Int(Option<i64>),
+    Float(Option<f64>),
+    Text(Option<String>),
+}
+
+/// Columns of the activities table, in the order `repo::export_activity_rows` selects them
+pub const ACTIVITY_EXPORT_COLUMNS: &[(&str, ColumnKind)] = &[
+    ("id", ColumnKind::Int),
+    ("title", ColumnKind::Text),
+    ("filename", ColumnKind::Text),
+    ("activity_type", ColumnKind::Text),
+    ("activity_date", ColumnKind::Text),
+    ("start_time", ColumnKind::Text),
+    ("location", ColumnKind::Text),
+    ("total_duration", ColumnKind::Float),
+    ("total_distance", ColumnKind::Float),
+    ("zone1_seconds", ColumnKind::Float),
+    ("zone2_seconds", ColumnKind::Float),
+    ("zone3_seconds", ColumnKind::Float),
+    ("zone4_seconds", ColumnKind::Float),
+    ("zone5_seconds", ColumnKind::Float),
+    ("elevation_gain", ColumnKind::Float),
+    ("elevation_loss", ColumnKind::Float),
+    ("max_altitude", ColumnKind::Float),
+    ("min_altitude", ColumnKind::Float),
+    ("avg_grade_adjusted_speed", ColumnKind::Float),
+    ("effort_distance", ColumnKind::Float),
+    ("elevation_source", ColumnKind::Text),
+    ("route_id", ColumnKind::Int),
+    ("country_code", ColumnKind::Text),
+    ("admin1", ColumnKind::Text),
+    ("admin2", ColumnKind::Text),
+    ("start_end_distance", ColumnKind::Float),
+    ("tags", ColumnKind::Text),
+];
+
+/// Fixed columns of the records table, in the order `record_row` writes them.
+/// One float column per `extras` key follows.
+pub const RECORD_EXPORT_COLUMNS: &[(&str, ColumnKind)] = &[
+    ("activity_id", ColumnKind::Int),
+    ("timestamp", ColumnKind::Text),
+    ("elapsed_time", ColumnKind::Float),
+    ("heart_rate", ColumnKind::Int),
+    ("distance", ColumnKind::Float),
+    ("altitude", ColumnKind::Float),
+    ("speed", ColumnKind::Float),
+    ("temperature", ColumnKind::Float),
+    ("position_lat", ColumnKind::Float),
+    ("position_long", ColumnKind::Float),
+    ("clean_position_lat", ColumnKind::Float),
+    ("clean_position_long", ColumnKind::Float),
+    ("zone", ColumnKind::Text),
+    ("grade_adjusted_speed", ColumnKind::Float),
+    ("corrected_altitude", ColumnKind::Float),
+];
+
+/// Row of the records table for one record of an activity: the
+/// `RECORD_EXPORT_COLUMNS` followed by the values of `extra_keys`
+pub fn record_row(activity_id: i64, record: TrackRecord, extra_keys: &[String]) -> Vec<Cell> {
+    let mut cells = vec![
+        Cell::Int(Some(activity_id)),
+        Cell::Text(Some(record.timestamp)),
+        Cell::Float(Some(record.elapsed_time)),
+        Cell::Int(record.heart_rate.map(i64::from)),
+        Cell::Float(record.distance),
+        Cell::Float(record.altitude),
+        Cell::Float(record.speed),
+        Cell::Float(record.temperature),
+        Cell::Float(record.position_lat),
+        Cell::Float(record.position_long),
+        Cell::Float(record.clean_position_lat),
+        Cell::Float(record.clean_position_long),
+        Cell::Text(Some(record.zone)),
+        Cell::Float(record.grade_adjusted_speed),
+        Cell::Float(record.corrected_altitude),
+    ];
+    cells.extend(
+        extra_keys
+            .iter()
+            .map(|key| Cell::Float(record.extras.get(key).copied())),
+    );
+    cells
+}
+
+/// Check the date range of an analytics filter
+pub fn validate_filter(filter: &AnalyticsFilter) -> Result<(), AppError> {
+    let parse = |date: &Option<String>| {
+        date.as_deref()
+            .map(|d| {
+                NaiveDate::parse_from_str(d, "%Y-%m-%d")
+                    .map_err(|_| AppError::InvalidArgument(format!("Invalid date: {}", d)))
+            })
+            .transpose()
+    };
+    if let (Some(start), Some(end)) = (parse(&filter.start_date)?, parse(&filter.end_date)?) {
+        if end < start {
+            return Err(AppError::InvalidArgument(
+                "End date is before start date".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn export_error(e: impl std::fmt::Display) -> AppError {
+    AppError::Export(e.to_string())
+}
+
+/// Writes rows to a CSV or Parquet file as they are produced
+pub enum TableWriter {
+    Csv(csv::Writer<BufWriter<File>>),
+    Parquet(ParquetTable),
+}
+
+impl TableWriter {
+    /// Create the file at `path` with the given (name, kind) columns
+    pub fn create(
+        path: &Path,
+        format: TableFormat,
+        columns: &[(String, ColumnKind)],
+    ) -> Result<Self, AppError> {
+        let file = File::create(path)?;
+        match format {
+            TableFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(BufWriter::new(file));
+                writer
+                    .write_record(columns.iter().map(|(name, _)| name))
+                    .map_err(export_error)?;
+                Ok(TableWriter::Csv(writer))
+            }
+            TableFormat::Parquet => Ok(TableWriter::Parquet(ParquetTable::new(file, columns)?)),
+        }
+    }
+
+    pub fn write_row(&mut self, row: &[Cell]) -> Result<(), AppError> {
+        match self {
+            TableWriter::Csv(writer) => {
+                let fields = row.iter().map(|cell| match cell {
+                    Cell::Int(v) => v.map(|v| v.to_string()).unwrap_or_default(),
+                    Cell::Float(v) => v.map(|v| v.to_string()).unwrap_or_default(),
+                    Cell::Text(v) => v.clone().unwrap_or_default(),
+                });
+                writer.write_record(fields).map_err(export_error)
+            }
+            TableWriter::Parquet(table) => table.write_row(row),
+        }
+    }
+
+    /// Write any buffered rows and close the file
+    pub fn finish(self) -> Result<(), AppError> {
+        match self {
+            TableWriter::Csv(mut writer) => Ok(writer.flush()?),
+            TableWriter::Parquet(table) => table.finish(),
+        }
+    }
+}
+
+enum ColumnBuilder {
+    Int(Int64Builder),
+    Float(Float64Builder),
+    Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn new(kind: ColumnKind) -> Self {
+        match kind {
+            ColumnKind::Int => ColumnBuilder::Int(Int64Builder::with_capacity(ROW_GROUP_SIZE)),
+            ColumnKind::Float => {
+                ColumnBuilder::Float(Float64Builder::with_capacity(ROW_GROUP_SIZE))
+            }
+            ColumnKind::Text => ColumnBuilder::Text(StringBuilder::new()),
+        }
+    }
+
+    fn append(&mut self, cell: &Cell) {
+        match (self, cell) {
+            (ColumnBuilder::Int(b), Cell::Int(v)) => b.append_option(*v),
+            (ColumnBuilder::Float(b), Cell::Float(v)) => b.append_option(*v),
+            (ColumnBuilder::Float(b), Cell::Int(v)) => b.append_option(v.map(|v| v as f64)),
+            (ColumnBuilder::Text(b), Cell::Text(v)) => b.append_option(v.as_deref()),
+            (ColumnBuilder::Int(b), _) => b.append_null(),
+            (ColumnBuilder::Float(b), _) => b.append_null(),
+            (ColumnBuilder::Text(b), _) => b.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Int(b) => Arc::new(b.finish()),
+            ColumnBuilder::Float(b) => Arc::new(b.finish()),
+            ColumnBuilder::Text(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+/// Parquet file written one Snappy-compressed row group at a time
+pub struct ParquetTable {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    builders: Vec<ColumnBuilder>,
+    buffered: usize,
+}
+
+impl ParquetTable {
+    fn new(file: File, columns: &[(String, ColumnKind)]) -> Result<Self, AppError> {
+        let fields: Vec<Field> = columns
+            .iter()
+            .map(|(name, kind)| {
+                let data_type = match kind {
+                    ColumnKind::Int => DataType::Int64,
+                    ColumnKind::Float => DataType::Float64,
+                    ColumnKind::Text => DataType::Utf8,
+                };
+                Field::new(name, data_type, true)
+            })
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .build();
+        let writer =
+            ArrowWriter::try_new(file, schema.clone(), Some(properties)).map_err(export_error)?;
+
+        Ok(ParquetTable {
+            writer,
+            schema,
+            builders: columns
+                .iter()
+                .map(|(_, kind)| ColumnBuilder::new(*kind))
+                .collect(),
+            buffered: 0,
+        })
+    }
+
+    fn write_row(&mut self, row: &[Cell]) -> Result<(), AppError> {
+        for (builder, cell) in self.builders.iter_mut().zip(row) {
+            builder.append(cell);
+        }
+        self.buffered += 1;
+        if self.buffered == ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), AppError> {
+        if self.buffered == 0 {
+            return Ok(());
+        }
+        let arrays = self
+            .builders
+            .iter_mut()
+            .map(ColumnBuilder::finish)
+            .collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays).map_err(export_error)?;
+        self.writer.write(&batch).map_err(export_error)?;
+        self.writer.flush().map_err(export_error)?;
+        self.buffered = 0;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), AppError> {
+        self.flush()?;
+        self.writer.close().map_err(export_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Array, Float64Array, Int64Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn columns() -> Vec<(String, ColumnKind)> {
+        vec![
+            ("id".to_string(), ColumnKind::Int),
+            ("distance".to_string(), ColumnKind::Float),
+            ("title".to_string(), ColumnKind::Text),
+        ]
+    }
+
+    fn rows() -> Vec<Vec<Cell>> {
+        vec![
+            vec![
+                Cell::Int(Some(1)),
+                Cell::Float(Some(1250.5)),
+                Cell::Text(Some("Hut, \"high\" route".to_string())),
+            ],
+            vec![Cell::Int(Some(2)), Cell::Float(None), Cell::Text(None)],
+        ]
+    }
+
+    fn write_table(format: TableFormat, name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(name);
+        let mut writer = TableWriter::create(&path, format, &columns()).unwrap();
+        for row in rows() {
+            writer.write_row(&row).unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_validate_filter() {
+        let mut filter = AnalyticsFilter {
+            start_date: Some("2024-01-01".to_string()),
+            end_date: Some("2024-12-31".to_string()),
+            activity_types: None,
+        };
+        assert!(validate_filter(&filter).is_ok());
+        filter.end_date = Some("2023-12-31".to_string());
+        assert!(validate_filter(&filter).is_err());
+        filter.end_date = Some("31/12/2024".to_string());
+        assert!(validate_filter(&filter).is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let path = write_table(TableFormat::Csv, "analytics_test.csv");
+        let text = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            text,
+            "id,distance,title\n1,1250.5,\"Hut, \"\"high\"\" route\"\n2,,\n"
+        );
+    }
+
+    #[test]
+    fn test_parquet() {
+        let path = write_table(TableFormat::Parquet, "analytics_test.parquet");
+        let file = File::open(&path).unwrap();
+        let batches: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        let ids = batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        let distances = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap();
+        let titles = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(ids.value(1), 2);
+        assert_eq!(distances.value(0), 1250.5);
+        assert!(distances.is_null(1));
+        assert_eq!(titles.value(0), "Hut, \"high\" route");
+        assert!(titles.is_null(1));
+    }
+
+    #[test]
+    fn test_record_row() {
+        let record = TrackRecord {
+            timestamp: "2024-07-14T08:00:00+02:00".to_string(),
+            heart_rate: Some(120),
+            extras: std::collections::HashMap::from([("cadence".to_string(), 52.0)]),
+            ..TrackRecord::default()
+        };
+        let keys = ["cadence".to_string(), "power".to_string()];
+        let row = record_row(7, record, &keys);
+
+        assert_eq!(row.len(), RECORD_EXPORT_COLUMNS.len() + keys.len());
+        assert_eq!(row[0], Cell::Int(Some(7)));
+        assert_eq!(row[3], Cell::Int(Some(120)));
+        assert_eq!(row[4], Cell::Float(None));
+        assert_eq!(
+            row[RECORD_EXPORT_COLUMNS.len()..],
+            [Cell::Float(Some(52.0)), Cell::Float(None)]
+        );
+    }
+}
@@ -0,0 +1,421 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusqlite::backup::Backup;
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{open_db, schema_version, SCHEMA_VERSION};
+use crate::error::AppError;
+use crate::types::BackupInfo;
+
+/// Backups are named `fitness-YYYYMMDD-HHMMSS.db` in UTC, so they sort by age
+const BACKUP_PREFIX: &str = "fitness-";
+const BACKUP_EXTENSION: &str = "db";
+const BACKUP_TIME_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Marks the backup taken before a restore, so it can't collide with a
+/// scheduled or manual backup taken in the same second
+const PRE_RESTORE_SUFFIX: &str = "-pre-restore";
+
+/// Pages copied per step of the online backup. The steps run back to back
+/// because the app's only connection stays locked for the whole copy.
+const PAGES_PER_STEP: i32 = 1024;
+
+/// Automatic backup settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupSchedule {
+    pub enabled: bool,
+    /// Hours between automatic backups
+    pub interval_hours: u32,
+    /// Number of backups kept; older ones are deleted after each backup
+    pub keep: usize,
+    /// Why the last scheduled backup failed, if it did. Reported by the
+    /// backend only; ignored when the settings are saved.
+    #[serde(default, skip_deserializing)]
+    pub last_error: Option<String>,
+}
+
+impl Default for BackupSchedule {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_hours: 24,
+            keep: 7,
+            last_error: None,
+        }
+    }
+}
+
+pub fn validate_schedule(schedule: &BackupSchedule) -> Result<(), AppError> {
+    if schedule.interval_hours == 0 || schedule.keep == 0 {
+        return Err(AppError::InvalidArgument(
+            "Backup interval and number of backups kept must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub fn backup_file_name(time: DateTime<Utc>, suffix: &str) -> String {
+    format!(
+        "{}{}{}.{}",
+        BACKUP_PREFIX,
+        time.format(BACKUP_TIME_FORMAT),
+        suffix,
+        BACKUP_EXTENSION
+    )
+}
+
+/// Time a backup was taken, from its file name
+fn backup_time(path: &Path) -> Option<DateTime<Utc>> {
+    let stem = path
+        .file_name()?
+        .to_str()?
+        .strip_prefix(BACKUP_PREFIX)?
+        .strip_suffix(&format!(".{}", BACKUP_EXTENSION))?;
+    let stem = stem.strip_suffix(PRE_RESTORE_SUFFIX).unwrap_or(stem);
+    NaiveDateTime::parse_from_str(stem, BACKUP_TIME_FORMAT)
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+/// Backups in a directory, newest first
+pub fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>, AppError> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups: Vec<(DateTime<Utc>, BackupInfo)> = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if let Some(time) = backup_time(&path) {
+            backups.push((
+                time,
+                BackupInfo {
+                    path: path.to_string_lossy().to_string(),
+                    created_at: time.to_rfc3339(),
+                    size: entry.metadata()?.len(),
+                },
+            ));
+        }
+    }
+    backups.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+
+    Ok(backups.into_iter().map(|(_, info)| info).collect())
+}
+
+/// Backups in a directory other than those taken before a restore, newest
+/// first. A pre-restore backup may be the only way to undo a restore, so it is
+/// never pruned, and it doesn't postpone the next scheduled backup.
+fn regular_backups(dir: &Path) -> Result<Vec<BackupInfo>, AppError> {
+    let pre_restore = format!("{}.{}", PRE_RESTORE_SUFFIX, BACKUP_EXTENSION);
+    Ok(list_backups(dir)?
+        .into_iter()
+        .filter(|backup| !backup.path.ends_with(&pre_restore))
+        .collect())
+}
+
+/// Whether an automatic backup is due, given when the newest backup was taken
+pub fn backup_due(
+    schedule: &BackupSchedule,
+    latest: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> bool {
+    schedule.enabled
+        && latest.is_none_or(|latest| {
+            now - latest >= chrono::Duration::hours(schedule.interval_hours as i64)
+        })
+}
+
+/// Time of the newest regular backup in a directory
+pub fn latest_backup(dir: &Path) -> Result<Option<DateTime<Utc>>, AppError> {
+    Ok(regular_backups(dir)?
+        .first()
+        .and_then(|b| DateTime::parse_from_rfc3339(&b.created_at).ok())
+        .map(|t| t.with_timezone(&Utc)))
+}
+
+/// Copy a live database to a timestamped file in `dir` with SQLite's online
+/// backup API. The copy is written under a temporary name and renamed when
+/// complete, so an interrupted backup is never listed.
+pub fn backup_database(
+    conn: &Connection,
+    dir: &Path,
+    now: DateTime<Utc>,
+) -> Result<BackupInfo, AppError> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(backup_file_name(now, ""));
+    if path.exists() {
+        return Err(AppError::InvalidArgument(format!(
+            "Backup {} already exists",
+            path.display()
+        )));
+    }
+    write_backup(conn, &path, now)
+}
+
+/// Back up a live database before it is replaced by a restore. If a restore in
+/// the same second already left one, it is kept rather than overwritten, as it
+/// holds the data from before both restores.
+pub fn backup_before_restore(
+    conn: &Connection,
+    dir: &Path,
+    now: DateTime<Utc>,
+) -> Result<BackupInfo, AppError> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(backup_file_name(now, PRE_RESTORE_SUFFIX));
+    if path.exists() {
+        return Ok(BackupInfo {
+            path: path.to_string_lossy().to_string(),
+            created_at: now.to_rfc3339(),
+            size: std::fs::metadata(&path)?.len(),
+        });
+    }
+    write_backup(conn, &path, now)
+}
+
+fn write_backup(
+    conn: &Connection,
+    path: &Path,
+    now: DateTime<Utc>,
+) -> Result<BackupInfo, AppError> {
+    let partial = path.with_extension("partial");
+
+    {
+        let mut dest = Connection::open(&partial)?;
+        Backup::new(conn, &mut dest)?.run_to_completion(PAGES_PER_STEP, Duration::ZERO, None)?;
+    }
+    std::fs::rename(&partial, path)?;
+
+    Ok(BackupInfo {
+        path: path.to_string_lossy().to_string(),
+        created_at: now.to_rfc3339(),
+        size: std::fs::metadata(path)?.len(),
+    })
+}
+
+/// Delete all but the newest `keep` regular backups in a directory. Returns the deleted paths.
+pub fn prune_backups(dir: &Path, keep: usize) -> Result<Vec<PathBuf>, AppError> {
+    let mut deleted = Vec::new();
+    for backup in regular_backups(dir)?.into_iter().skip(keep) {
+        std::fs::remove_file(&backup.path)?;
+        deleted.push(PathBuf::from(backup.path));
+    }
+    Ok(deleted)
+}
+
+/// Open a backup for restoring, checking that it is an intact HikeIQ database
+/// no newer than this release understands
+pub fn open_backup(path: &Path) -> Result<Connection, AppError> {
+    if !path.exists() {
+        return Err(AppError::FileNotFound(path.display().to_string()));
+    }
+    let invalid = |reason: &str| {
+        AppError::InvalidArgument(format!(
+            "{} is not a valid backup: {}",
+            path.display(),
+            reason
+        ))
+    };
+
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let check: String = conn
+        .query_row("PRAGMA quick_check", [], |row| row.get(0))
+        .map_err(|_| invalid("not a SQLite database"))?;
+    if check != "ok" {
+        return Err(invalid(&check));
+    }
+
+    let has_activities: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'activities')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_activities {
+        return Err(invalid("no activities table"));
+    }
+
+    let version = schema_version(&conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(invalid(&format!(
+            "schema version {} is newer than this version of HikeIQ supports ({})",
+            version, SCHEMA_VERSION
+        )));
+    }
+
+    Ok(conn)
+}
+
+/// Where a backup being restored is copied, next to the database it replaces
+fn staged_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("restore")
+}
+
+/// Check a backup and copy it next to the database at `db_path`, returning a
+/// connection to the copy. The copy can be brought up to date there before
+/// `replace_database` swaps it in, leaving the live database untouched until then.
+pub fn stage_restore(backup: &Path, db_path: &Path) -> Result<Connection, AppError> {
+    drop(open_backup(backup)?);
+    let staged = staged_path(db_path);
+    std::fs::copy(backup, &staged)?;
+    Ok(open_db(&staged)?)
+}
+
+/// Move a staged restore over the database at `db_path` and point `conn` at
+/// it. `conn` is closed first so the file can be replaced, and reopened on
+/// whichever database is at the path if the move fails.
+pub fn replace_database(
+    conn: &mut Connection,
+    staged: Connection,
+    db_path: &Path,
+) -> Result<(), AppError> {
+    staged.close().map_err(|(_, e)| e)?;
+    drop(std::mem::replace(conn, Connection::open_in_memory()?));
+
+    let moved = std::fs::rename(staged_path(db_path), db_path);
+    *conn = open_db(db_path)?;
+    moved?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn database(value: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE activities (id INTEGER PRIMARY KEY, filename TEXT)")
+            .unwrap();
+        conn.execute("INSERT INTO activities (filename) VALUES (?)", [value])
+            .unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)
+            .unwrap();
+        conn
+    }
+
+    fn filename(conn: &Connection) -> String {
+        conn.query_row("SELECT filename FROM activities", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_backup_due() {
+        let schedule = BackupSchedule::default();
+        let now = Utc.with_ymd_and_hms(2024, 7, 14, 12, 0, 0).unwrap();
+        assert!(backup_due(&schedule, None, now));
+        assert!(!backup_due(
+            &schedule,
+            Some(now - chrono::Duration::hours(23)),
+            now
+        ));
+        assert!(backup_due(
+            &schedule,
+            Some(now - chrono::Duration::hours(24)),
+            now
+        ));
+        let disabled = BackupSchedule {
+            enabled: false,
+            ..schedule
+        };
+        assert!(!backup_due(&disabled, None, now));
+    }
+
+    #[test]
+    fn test_backup_and_prune() {
+        let dir = temp_dir("hikeiq_backup_test");
+        let conn = database("morning.fit");
+        let start = Utc.with_ymd_and_hms(2024, 7, 14, 12, 0, 0).unwrap();
+        for day in 0..3 {
+            backup_database(&conn, &dir, start + chrono::Duration::days(day)).unwrap();
+        }
+        assert!(
+            backup_database(&conn, &dir, start).is_err(),
+            "existing backups aren't overwritten"
+        );
+
+        let backups = list_backups(&dir).unwrap();
+        assert_eq!(backups.len(), 3);
+        assert!(backups[0].path.ends_with("fitness-20240716-120000.db"));
+        assert_eq!(
+            latest_backup(&dir).unwrap(),
+            Some(start + chrono::Duration::days(2))
+        );
+
+        let deleted = prune_backups(&dir, 2).unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert!(deleted[0].ends_with("fitness-20240714-120000.db"));
+        assert_eq!(list_backups(&dir).unwrap().len(), 2);
+
+        // Backups taken before a restore are neither the latest nor pruned
+        backup_before_restore(&conn, &dir, start + chrono::Duration::days(3)).unwrap();
+        assert_eq!(
+            latest_backup(&dir).unwrap(),
+            Some(start + chrono::Duration::days(2))
+        );
+        let deleted = prune_backups(&dir, 1).unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert!(deleted[0].ends_with("fitness-20240715-120000.db"));
+        assert_eq!(list_backups(&dir).unwrap().len(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_restore() {
+        let dir = temp_dir("hikeiq_restore_test");
+        let backup = backup_database(&database("backed-up.fit"), &dir, Utc::now()).unwrap();
+
+        let db_path = dir.join("fitness.db");
+        let mut live = open_db(&db_path).unwrap();
+        live.execute_batch(
+            "CREATE TABLE activities (id INTEGER PRIMARY KEY, filename TEXT);
+             INSERT INTO activities (filename) VALUES ('live.fit');",
+        )
+        .unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 7, 14, 12, 0, 0).unwrap();
+        backup_database(&live, &dir, now).unwrap();
+        let undo = backup_before_restore(&live, &dir, now).unwrap();
+        assert!(undo
+            .path
+            .ends_with("fitness-20240714-120000-pre-restore.db"));
+        assert_eq!(
+            backup_before_restore(&live, &dir, now).unwrap().path,
+            undo.path
+        );
+        assert_eq!(list_backups(&dir).unwrap().len(), 3);
+
+        let staged = stage_restore(Path::new(&backup.path), &db_path).unwrap();
+        assert_eq!(filename(&live), "live.fit");
+        replace_database(&mut live, staged, &db_path).unwrap();
+        assert_eq!(filename(&live), "backed-up.fit");
+        assert_eq!(filename(&open_db(&db_path).unwrap()), "backed-up.fit");
+        assert!(!dir.join("fitness.restore").exists());
+        let undone = open_backup(Path::new(&undo.path)).unwrap();
+        assert_eq!(filename(&undone), "live.fit");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_open_backup_checks_version() {
+        let dir = temp_dir("hikeiq_backup_version_test");
+        let conn = database("future.fit");
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        let backup = backup_database(&conn, &dir, Utc::now()).unwrap();
+        assert!(open_backup(Path::new(&backup.path)).is_err());
+
+        std::fs::write(dir.join("notes.db"), b"not a database").unwrap();
+        assert!(open_backup(&dir.join("notes.db")).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,10 +1,25 @@
-use rusqlite::{Connection, Result};
+use crate::record_store::{encode_records, extra_keys};
+use crate::sports::BUILTIN_TYPES;
+use crate::types::TrackRecord;
+use rusqlite::{params, Connection, OptionalExtension, Result};
 use std::path::Path;
 
 const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS profiles (
+    id              INTEGER PRIMARY KEY,
+    name            TEXT NOT NULL,
+    birthdate       TEXT,
+    sex             TEXT,
+    weight          REAL,
+    resting_hr      INTEGER,
+    max_hr          INTEGER,
+    zone_model      TEXT NOT NULL DEFAULT 'fixed',
+    created_at      TEXT DEFAULT CURRENT_TIMESTAMP
+);
+
 CREATE TABLE IF NOT EXISTS activities (
     id              INTEGER PRIMARY KEY,
-    filename        TEXT NOT NULL UNIQUE,
+    filename        TEXT NOT NULL,
     activity_type   TEXT NOT NULL DEFAULT 'Other',
     activity_date   TEXT NOT NULL,
     start_time      TEXT NOT NULL,
@@ -15,11 +30,54 @@ CREATE TABLE IF NOT EXISTS activities (
     total_distance  REAL,
     total_records   INTEGER NOT NULL,
     elevation_gain  REAL,
+    elevation_loss  REAL,
     max_altitude    REAL,
     min_altitude    REAL,
+    avg_grade_adjusted_speed REAL,
+    effort_distance REAL,
+    elevation_source TEXT NOT NULL DEFAULT 'device',
+    corrected_elevation_gain REAL,
+    corrected_elevation_loss REAL,
+    corrected_max_altitude REAL,
+    corrected_min_altitude REAL,
+    corrected_effort_distance REAL,
+    min_lat         REAL,
+    max_lat         REAL,
+    min_lon         REAL,
+    max_lon         REAL,
+    route_id        INTEGER REFERENCES routes(id),
+    start_location  TEXT,
+    end_location    TEXT,
+    high_point_location TEXT,
+    admin1          TEXT,
+    admin2          TEXT,
+    country_code    TEXT,
+    start_end_distance REAL,
+    title           TEXT,
+    description     TEXT,
+    notes           TEXT,
+    profile_id      INTEGER REFERENCES profiles(id),
     imported_at     TEXT DEFAULT CURRENT_TIMESTAMP
 );
 
+CREATE TABLE IF NOT EXISTS routes (
+    id              INTEGER PRIMARY KEY,
+    name            TEXT NOT NULL,
+    created_at      TEXT DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS trips (
+    id              INTEGER PRIMARY KEY,
+    name            TEXT NOT NULL,
+    profile_id      INTEGER REFERENCES profiles(id),
+    created_at      TEXT DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS trip_activities (
+    activity_id     INTEGER PRIMARY KEY REFERENCES activities(id),
+    trip_id         INTEGER NOT NULL REFERENCES trips(id)
+);
+
 CREATE TABLE IF NOT EXISTS activity_zones (
     activity_id     INTEGER PRIMARY KEY REFERENCES activities(id),
     zone1_seconds   REAL DEFAULT 0,
@@ -29,49 +87,418 @@ CREATE TABLE IF NOT EXISTS activity_zones (
     zone5_seconds   REAL DEFAULT 0
 );
 
-CREATE TABLE IF NOT EXISTS records (
+CREATE TABLE IF NOT EXISTS record_columns (
+    activity_id     INTEGER PRIMARY KEY REFERENCES activities(id),
+    record_count    INTEGER NOT NULL,
+    extra_keys      TEXT NOT NULL,
+    avg_heart_rate  REAL,
+    max_heart_rate  INTEGER,
+    data            BLOB NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS source_files (
+    activity_id     INTEGER PRIMARY KEY REFERENCES activities(id),
+    size            INTEGER NOT NULL,
+    data            BLOB NOT NULL
+);
+
+-- Files of activities merged into another, so importing them again is refused
+CREATE TABLE IF NOT EXISTS merged_files (
+    filename        TEXT NOT NULL,
+    activity_id     INTEGER NOT NULL REFERENCES activities(id),
+    PRIMARY KEY (activity_id, filename)
+);
+
+CREATE TABLE IF NOT EXISTS activity_tracks (
+    activity_id     INTEGER PRIMARY KEY REFERENCES activities(id),
+    tolerance       REAL NOT NULL,
+    points          TEXT NOT NULL
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS track_segments USING rtree(
+    id,
+    min_lat, max_lat,
+    min_lon, max_lon,
+    +activity_id INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS segments (
+    id              INTEGER PRIMARY KEY,
+    name            TEXT NOT NULL,
+    points          TEXT NOT NULL,
+    distance        REAL NOT NULL,
+    min_lat         REAL NOT NULL,
+    max_lat         REAL NOT NULL,
+    min_lon         REAL NOT NULL,
+    max_lon         REAL NOT NULL,
+    profile_id      INTEGER REFERENCES profiles(id),
+    created_at      TEXT DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS segment_efforts (
+    id              INTEGER PRIMARY KEY,
+    segment_id      INTEGER NOT NULL REFERENCES segments(id),
+    activity_id     INTEGER NOT NULL REFERENCES activities(id),
+    start_time      TEXT NOT NULL,
+    elapsed_time    REAL NOT NULL,
+    avg_heart_rate  REAL,
+    vertical_speed  REAL
+);
+
+CREATE TABLE IF NOT EXISTS pois (
+    id              INTEGER PRIMARY KEY,
+    name            TEXT NOT NULL,
+    kind            TEXT NOT NULL,
+    lat             REAL NOT NULL,
+    lon             REAL NOT NULL,
+    elevation       REAL,
+    UNIQUE (name, kind, lat, lon)
+);
+
+CREATE TABLE IF NOT EXISTS activity_pois (
+    activity_id     INTEGER NOT NULL REFERENCES activities(id),
+    poi_id          INTEGER NOT NULL REFERENCES pois(id),
+    visited_at      TEXT NOT NULL,
+    distance        REAL NOT NULL,
+    PRIMARY KEY (activity_id, poi_id)
+);
+
+CREATE TABLE IF NOT EXISTS tags (
+    id              INTEGER PRIMARY KEY,
+    name            TEXT NOT NULL UNIQUE COLLATE NOCASE
+);
+
+CREATE TABLE IF NOT EXISTS activity_tags (
+    activity_id     INTEGER NOT NULL REFERENCES activities(id),
+    tag_id          INTEGER NOT NULL REFERENCES tags(id),
+    PRIMARY KEY (activity_id, tag_id)
+);
+
+CREATE TABLE IF NOT EXISTS activity_types (
+    name            TEXT PRIMARY KEY COLLATE NOCASE,
+    icon            TEXT NOT NULL,
+    color           TEXT NOT NULL,
+    builtin         INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS gear (
     id              INTEGER PRIMARY KEY,
-    activity_id     INTEGER REFERENCES activities(id),
-    timestamp       TEXT NOT NULL,
-    elapsed_time    REAL,
-    heart_rate      INTEGER,
-    distance        REAL,
-    altitude        REAL,
-    speed           REAL,
-    temperature     REAL,
-    position_lat    REAL,
-    position_long   REAL,
-    zone            TEXT,
-    extras          TEXT
+    name            TEXT NOT NULL,
+    gear_type       TEXT NOT NULL,
+    brand           TEXT,
+    model           TEXT,
+    purchase_date   TEXT,
+    retired_date    TEXT,
+    distance_limit  REAL,
+    elevation_limit REAL,
+    hours_limit     REAL,
+    notes           TEXT,
+    profile_id      INTEGER REFERENCES profiles(id),
+    created_at      TEXT DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS activity_gear (
+    activity_id     INTEGER NOT NULL REFERENCES activities(id),
+    gear_id         INTEGER NOT NULL REFERENCES gear(id),
+    PRIMARY KEY (activity_id, gear_id)
+);
+
+CREATE TABLE IF NOT EXISTS gear_defaults (
+    activity_type   TEXT NOT NULL COLLATE NOCASE,
+    gear_id         INTEGER NOT NULL REFERENCES gear(id),
+    PRIMARY KEY (activity_type, gear_id)
+);
+
+CREATE TABLE IF NOT EXISTS settings (
+    key             TEXT PRIMARY KEY,
+    value           TEXT NOT NULL
 );
 
 CREATE INDEX IF NOT EXISTS idx_activities_week ON activities(week_start);
 CREATE INDEX IF NOT EXISTS idx_activities_month ON activities(month_start);
-CREATE INDEX IF NOT EXISTS idx_records_activity ON records(activity_id);
+CREATE INDEX IF NOT EXISTS idx_segment_efforts_segment ON segment_efforts(segment_id);
+CREATE INDEX IF NOT EXISTS idx_segment_efforts_activity ON segment_efforts(activity_id);
+CREATE INDEX IF NOT EXISTS idx_pois_lat ON pois(lat);
+CREATE INDEX IF NOT EXISTS idx_activity_pois_poi ON activity_pois(poi_id);
+CREATE INDEX IF NOT EXISTS idx_activity_tags_tag ON activity_tags(tag_id);
+CREATE INDEX IF NOT EXISTS idx_activity_gear_gear ON activity_gear(gear_id);
+CREATE INDEX IF NOT EXISTS idx_trip_activities_trip ON trip_activities(trip_id);
 "#;
 
+/// Schema version stored in `PRAGMA user_version`. Bump it whenever a release
+/// changes the schema, so restoring a backup from a newer release is refused.
+pub const SCHEMA_VERSION: i32 = 8;
+
 /// Initialize database connection and create schema
 pub fn init_db(db_path: &Path) -> Result<Connection> {
+    let conn = open_db(db_path)?;
+    prepare_db(&conn)?;
+    Ok(conn)
+}
+
+/// Open a database connection with foreign keys enforced
+pub fn open_db(db_path: &Path) -> Result<Connection> {
     let conn = Connection::open(db_path)?;
     conn.execute("PRAGMA foreign_keys = ON", [])?;
-    conn.execute_batch(SCHEMA)?;
-    migrate_db(&conn)?;
     Ok(conn)
 }
 
+/// Create missing tables, migrate older schemas and seed defaults. Also run
+/// on a backup before it is restored.
+pub fn prepare_db(conn: &Connection) -> Result<()> {
+    migrate_filename_unique(conn)?;
+    conn.execute_batch(SCHEMA)?;
+    migrate_db(conn)?;
+    migrate_records(conn)?;
+    seed_activity_types(conn)?;
+    seed_default_profile(conn)?;
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(())
+}
+
+/// Schema version of a database; 0 for databases from before versioning
+pub fn schema_version(conn: &Connection) -> Result<i32> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Create a first profile when there is none and give it any activities,
+/// gear and segments from before profiles existed
+fn seed_default_profile(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "INSERT INTO profiles (name) SELECT 'Me' WHERE NOT EXISTS (SELECT 1 FROM profiles)",
+        [],
+    )?;
+    conn.execute_batch(
+        r#"UPDATE activities SET profile_id = (SELECT MIN(id) FROM profiles) WHERE profile_id IS NULL;
+           UPDATE gear SET profile_id = (SELECT MIN(id) FROM profiles) WHERE profile_id IS NULL;
+           UPDATE segments SET profile_id = (SELECT MIN(id) FROM profiles) WHERE profile_id IS NULL;"#,
+    )?;
+
+    Ok(())
+}
+
+/// Add any built-in activity types missing from the database, keeping user changes to existing ones
+fn seed_activity_types(conn: &Connection) -> Result<()> {
+    let mut stmt = conn.prepare(
+        "INSERT OR IGNORE INTO activity_types (name, icon, color, builtin) VALUES (?, ?, ?, 1)",
+    )?;
+    for (name, icon, color) in BUILTIN_TYPES {
+        stmt.execute(params![name, icon, color])?;
+    }
+
+    Ok(())
+}
+
+/// Columns added to existing tables after their initial release, as (table, column, definition)
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    (
+        "activities",
+        "activity_type",
+        "TEXT NOT NULL DEFAULT 'Other'",
+    ),
+    ("activities", "avg_grade_adjusted_speed", "REAL"),
+    ("activities", "effort_distance", "REAL"),
+    ("activities", "elevation_loss", "REAL"),
+    (
+        "activities",
+        "elevation_source",
+        "TEXT NOT NULL DEFAULT 'device'",
+    ),
+    ("activities", "corrected_elevation_gain", "REAL"),
+    ("activities", "corrected_elevation_loss", "REAL"),
+    ("activities", "corrected_max_altitude", "REAL"),
+    ("activities", "corrected_min_altitude", "REAL"),
+    ("activities", "corrected_effort_distance", "REAL"),
+    ("activities", "min_lat", "REAL"),
+    ("activities", "max_lat", "REAL"),
+    ("activities", "min_lon", "REAL"),
+    ("activities", "max_lon", "REAL"),
+    ("activities", "route_id", "INTEGER REFERENCES routes(id)"),
+    ("activities", "start_location", "TEXT"),
+    ("activities", "end_location", "TEXT"),
+    ("activities", "high_point_location", "TEXT"),
+    ("activities", "admin1", "TEXT"),
+    ("activities", "admin2", "TEXT"),
+    ("activities", "country_code", "TEXT"),
+    ("activities", "start_end_distance", "REAL"),
+    ("activities", "title", "TEXT"),
+    ("activities", "description", "TEXT"),
+    ("activities", "notes", "TEXT"),
+    (
+        "activities",
+        "profile_id",
+        "INTEGER REFERENCES profiles(id)",
+    ),
+    ("gear", "profile_id", "INTEGER REFERENCES profiles(id)"),
+    ("segments", "profile_id", "INTEGER REFERENCES profiles(id)"),
+    ("record_columns", "avg_heart_rate", "REAL"),
+    ("record_columns", "max_heart_rate", "INTEGER"),
+    ("records", "grade_adjusted_speed", "REAL"),
+    ("records", "corrected_altitude", "REAL"),
+    ("records", "clean_position_lat", "REAL"),
+    ("records", "clean_position_long", "REAL"),
+];
+
 fn migrate_db(conn: &Connection) -> Result<()> {
-    let mut stmt = conn.prepare("PRAGMA table_info(activities)")?;
-    let cols: Vec<String> = stmt
-        .query_map([], |row| row.get(1))?
-        .collect::<Result<Vec<_>, _>>()?;
-
-    if !cols.iter().any(|c| c == "activity_type") {
-        conn.execute(
-            "ALTER TABLE activities ADD COLUMN activity_type TEXT NOT NULL DEFAULT 'Other'",
-            [],
+    for (table, column, definition) in ADDED_COLUMNS {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let cols: Vec<String> = stmt
+            .query_map([], |row| row.get(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Tables replaced since, like `records`, are only migrated while they still exist
+        if !cols.is_empty() && !cols.iter().any(|c| c == column) {
+            conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                [],
+            )?;
+        }
+    }
+
+    // Indexes on added columns can only be created once the columns exist
+    conn.execute_batch(
+        r#"CREATE INDEX IF NOT EXISTS idx_activities_route ON activities(route_id);
+           CREATE INDEX IF NOT EXISTS idx_activities_profile ON activities(profile_id);
+           CREATE UNIQUE INDEX IF NOT EXISTS idx_activities_filename ON activities(profile_id, filename);"#,
+    )?;
+
+    Ok(())
+}
+
+/// Make filenames unique per profile rather than across all profiles, so each
+/// profile can import a file another profile already has. Run before `SCHEMA`,
+/// which recreates the indexes dropped with the old tables.
+fn migrate_filename_unique(conn: &Connection) -> Result<()> {
+    rebuild_table(
+        conn,
+        "activities",
+        &[(
+            "filename        TEXT NOT NULL UNIQUE",
+            "filename        TEXT NOT NULL",
+        )],
+    )?;
+    rebuild_table(
+        conn,
+        "merged_files",
+        &[
+            (
+                "filename        TEXT PRIMARY KEY",
+                "filename        TEXT NOT NULL",
+            ),
+            (
+                "REFERENCES activities(id)\n)",
+                "REFERENCES activities(id),\n    PRIMARY KEY (activity_id, filename)\n)",
+            ),
+        ],
+    )
+}
+
+/// Rebuild a table whose stored definition contains each `(old, new)` pair's
+/// `old` text with `new` in its place, keeping its rows. SQLite can't change
+/// constraints of an existing table.
+fn rebuild_table(conn: &Connection, table: &str, changes: &[(&str, &str)]) -> Result<()> {
+    let sql: Option<String> = conn
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?",
+            [table],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(sql) = sql.filter(|sql| changes.iter().all(|(old, _)| sql.contains(old))) else {
+        return Ok(());
+    };
+
+    let rebuilt = format!("{}_rebuilt", table);
+    let definition = changes
+        .iter()
+        .fold(sql.replacen(table, &rebuilt, 1), |sql, (old, new)| {
+            sql.replacen(old, new, 1)
+        });
+
+    // Other tables keep referencing the table by name while it is replaced
+    let foreign_keys: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+    conn.pragma_update(None, "foreign_keys", false)?;
+    let result = conn.unchecked_transaction().and_then(|tx| {
+        tx.execute_batch(&format!(
+            r#"{};
+               INSERT INTO {} SELECT * FROM {};
+               DROP TABLE {};
+               ALTER TABLE {} RENAME TO {};"#,
+            definition, rebuilt, table, table, rebuilt, table
+        ))?;
+        tx.commit()
+    });
+    conn.pragma_update(None, "foreign_keys", foreign_keys)?;
+
+    result
+}
+
+/// Move track records from the row-per-record `records` table of schema
+/// version 1 into `record_columns`, then drop the table and reclaim its space
+fn migrate_records(conn: &Connection) -> Result<()> {
+    let has_records: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'records')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_records {
+        return Ok(());
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    {
+        let ids: Vec<i64> = tx
+            .prepare("SELECT DISTINCT activity_id FROM records WHERE activity_id IS NOT NULL")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>>>()?;
+        let mut select = tx.prepare(
+            r#"SELECT timestamp, elapsed_time, heart_rate, distance, altitude, speed, temperature, position_lat, position_long, zone, extras,
+                      grade_adjusted_speed, corrected_altitude, clean_position_lat, clean_position_long
+               FROM records
+               WHERE activity_id = ?
+               ORDER BY timestamp"#,
         )?;
+        let mut insert = tx.prepare(
+            "INSERT OR REPLACE INTO record_columns (activity_id, record_count, extra_keys, data) VALUES (?, ?, ?, ?)",
+        )?;
+
+        for id in ids {
+            let records = select
+                .query_map([id], |row| {
+                    let extras_json: Option<String> = row.get(10)?;
+                    Ok(TrackRecord {
+                        timestamp: row.get(0)?,
+                        elapsed_time: row.get::<_, Option<f64>>(1)?.unwrap_or_default(),
+                        heart_rate: row.get(2)?,
+                        distance: row.get(3)?,
+                        altitude: row.get(4)?,
+                        speed: row.get(5)?,
+                        temperature: row.get(6)?,
+                        position_lat: row.get(7)?,
+                        position_long: row.get(8)?,
+                        clean_position_lat: row.get(13)?,
+                        clean_position_long: row.get(14)?,
+                        zone: row.get::<_, Option<String>>(9)?.unwrap_or_default(),
+                        grade_adjusted_speed: row.get(11)?,
+                        corrected_altitude: row.get(12)?,
+                        extras: extras_json
+                            .and_then(|json| serde_json::from_str(&json).ok())
+                            .unwrap_or_default(),
+                    })
+                })?
+                .collect::<Result<Vec<_>>>()?;
+
+            let data = encode_records(&records)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            let keys =
+                serde_json::to_string(&extra_keys(&records)).unwrap_or_else(|_| "[]".to_string());
+            insert.execute(params![id, records.len() as i64, keys, data])?;
+        }
     }
+    tx.execute_batch("DROP TABLE records")?;
+    tx.commit()?;
 
+    conn.execute_batch("VACUUM")?;
     Ok(())
 }
 
@@ -101,7 +528,27 @@ mod tests {
 
         assert!(tables.contains(&"activities".to_string()));
         assert!(tables.contains(&"activity_zones".to_string()));
-        assert!(tables.contains(&"records".to_string()));
+        assert!(tables.contains(&"record_columns".to_string()));
+        assert!(!tables.contains(&"records".to_string()));
+        assert!(tables.contains(&"source_files".to_string()));
+        assert!(tables.contains(&"merged_files".to_string()));
+        assert!(tables.contains(&"activity_tracks".to_string()));
+        assert!(tables.contains(&"track_segments".to_string()));
+        assert!(tables.contains(&"routes".to_string()));
+        assert!(tables.contains(&"trips".to_string()));
+        assert!(tables.contains(&"trip_activities".to_string()));
+        assert!(tables.contains(&"segments".to_string()));
+        assert!(tables.contains(&"segment_efforts".to_string()));
+        assert!(tables.contains(&"pois".to_string()));
+        assert!(tables.contains(&"activity_pois".to_string()));
+        assert!(tables.contains(&"tags".to_string()));
+        assert!(tables.contains(&"activity_tags".to_string()));
+        assert!(tables.contains(&"activity_types".to_string()));
+        assert!(tables.contains(&"gear".to_string()));
+        assert!(tables.contains(&"activity_gear".to_string()));
+        assert!(tables.contains(&"gear_defaults".to_string()));
+        assert!(tables.contains(&"profiles".to_string()));
+        assert!(tables.contains(&"settings".to_string()));
 
         // Verify indexes exist
         let indexes: Vec<String> = conn
@@ -114,9 +561,233 @@ mod tests {
 
         assert!(indexes.contains(&"idx_activities_week".to_string()));
         assert!(indexes.contains(&"idx_activities_month".to_string()));
-        assert!(indexes.contains(&"idx_records_activity".to_string()));
+        assert!(indexes.contains(&"idx_activities_route".to_string()));
+        assert!(indexes.contains(&"idx_activities_profile".to_string()));
+
+        // Built-in activity types are seeded
+        let builtin: usize = conn
+            .query_row(
+                "SELECT COUNT(*) FROM activity_types WHERE builtin = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(builtin, BUILTIN_TYPES.len());
+
+        // A default profile is created
+        let profiles: usize = conn
+            .query_row("SELECT COUNT(*) FROM profiles", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(profiles, 1);
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
 
         // Clean up
         let _ = fs::remove_file(&db_path);
     }
+
+    #[test]
+    fn test_migrate_adds_missing_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE activities (id INTEGER PRIMARY KEY, filename TEXT NOT NULL);
+             CREATE TABLE records (id INTEGER PRIMARY KEY, activity_id INTEGER);
+             CREATE TABLE gear (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+             CREATE TABLE segments (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+             CREATE TABLE record_columns (activity_id INTEGER PRIMARY KEY, data BLOB NOT NULL);",
+        )
+        .unwrap();
+
+        migrate_db(&conn).expect("Failed to migrate db");
+
+        for (table, column, _) in ADDED_COLUMNS {
+            let cols: Vec<String> = conn
+                .prepare(&format!("PRAGMA table_info({})", table))
+                .unwrap()
+                .query_map([], |row| row.get(1))
+                .unwrap()
+                .filter_map(|r| r.ok())
+                .collect();
+            assert!(
+                cols.contains(&column.to_string()),
+                "{}.{} missing",
+                table,
+                column
+            );
+        }
+
+        // Running again is a no-op
+        migrate_db(&conn).expect("Failed to re-run migration");
+    }
+
+    #[test]
+    fn test_migrate_filename_unique() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("PRAGMA foreign_keys = ON", []).unwrap();
+        // Schema from before filenames were unique per profile
+        conn.execute_batch(
+            &SCHEMA
+                .replacen(
+                    "filename        TEXT NOT NULL,",
+                    "filename        TEXT NOT NULL UNIQUE,",
+                    1,
+                )
+                .replace(
+                    "filename        TEXT NOT NULL,\n    activity_id     INTEGER NOT NULL REFERENCES activities(id),\n    PRIMARY KEY (activity_id, filename)",
+                    "filename        TEXT PRIMARY KEY,\n    activity_id     INTEGER NOT NULL REFERENCES activities(id)",
+                ),
+        )
+        .unwrap();
+        migrate_db(&conn).unwrap();
+        conn.execute_batch(
+            r#"INSERT INTO profiles (id, name) VALUES (1, 'Me'), (2, 'Partner');
+               INSERT INTO activities (id, filename, activity_date, start_time, week_start, month_start,
+                                       total_duration, total_records, profile_id)
+               VALUES (1, 'walk.fit', '2024-07-14', '2024-07-14T06:00:00+00:00', '2024-07-08', '2024-07-01', 1, 0, 1);
+               INSERT INTO merged_files (filename, activity_id) VALUES ('resumed.fit', 1);"#,
+        )
+        .unwrap();
+
+        migrate_filename_unique(&conn).expect("Failed to migrate filenames");
+        conn.execute_batch(SCHEMA).unwrap();
+        migrate_db(&conn).expect("Failed to migrate db");
+
+        // Rows are kept, and another profile can have the same files
+        conn.execute_batch(
+            r#"INSERT INTO activities (id, filename, activity_date, start_time, week_start, month_start,
+                                       total_duration, total_records, profile_id)
+               SELECT 2, filename, activity_date, start_time, week_start, month_start,
+                      total_duration, total_records, 2
+               FROM activities WHERE id = 1;
+               INSERT INTO merged_files (filename, activity_id) VALUES ('resumed.fit', 2);"#,
+        )
+        .unwrap();
+        assert!(conn
+            .execute(
+                r#"INSERT INTO activities (id, filename, activity_date, start_time, week_start, month_start,
+                                           total_duration, total_records, profile_id)
+                   SELECT 3, filename, activity_date, start_time, week_start, month_start,
+                          total_duration, total_records, 1
+                   FROM activities WHERE id = 1"#,
+                [],
+            )
+            .is_err());
+        let merged: usize = conn
+            .query_row("SELECT COUNT(*) FROM merged_files", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(merged, 2);
+        let foreign_keys: bool = conn
+            .query_row("PRAGMA foreign_keys", [], |row| row.get(0))
+            .unwrap();
+        assert!(foreign_keys);
+        let week_index: bool = conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'idx_activities_week')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(week_index);
+
+        // Running again is a no-op
+        migrate_filename_unique(&conn).expect("Failed to re-run migration");
+    }
+
+    #[test]
+    fn test_migrate_records() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute_batch(
+            r#"CREATE TABLE records (id INTEGER PRIMARY KEY, activity_id INTEGER, timestamp TEXT NOT NULL,
+                                     elapsed_time REAL, heart_rate INTEGER, distance REAL, altitude REAL, speed REAL,
+                                     temperature REAL, position_lat REAL, position_long REAL, zone TEXT, extras TEXT);
+               INSERT INTO activities (id, filename, activity_date, start_time, week_start, month_start,
+                                       total_duration, total_records)
+               VALUES (1, 'walk.fit', '2024-07-14', '2024-07-14T06:00:00+00:00', '2024-07-08', '2024-07-01', 1, 2);
+               INSERT INTO records (activity_id, timestamp, elapsed_time, heart_rate, altitude, zone, extras)
+               VALUES (1, '2024-07-14T06:00:01+00:00', 1, 121, 1000.4, 'zone2', '{"cadence":50}'),
+                      (1, '2024-07-14T06:00:00+00:00', 0, 120, 1000.2, 'zone1', '{}');"#,
+        )
+        .unwrap();
+
+        prepare_db(&conn).expect("Failed to migrate records");
+
+        let (count, keys, data): (i64, String, Vec<u8>) = conn
+            .query_row(
+                "SELECT record_count, extra_keys, data FROM record_columns WHERE activity_id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(keys, r#"["cadence"]"#);
+        let records = crate::record_store::decode_records(&data).unwrap();
+        assert_eq!(records[0].timestamp, "2024-07-14T06:00:00+00:00");
+        assert_eq!(records[0].heart_rate, Some(120));
+        assert_eq!(records[1].altitude, Some(1000.4));
+        assert_eq!(records[1].extras.get("cadence"), Some(&50.0));
+
+        let has_records: bool = conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'records')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!has_records);
+    }
+
+    #[test]
+    fn test_restore_older_backup() {
+        use crate::backup::{backup_database, replace_database, stage_restore};
+
+        let dir = std::env::temp_dir().join("hikeiq_older_backup_test");
+        let _ = fs::remove_dir_all(&dir);
+        let old = Connection::open_in_memory().unwrap();
+        // Schema from before corrected totals were stored and filenames were
+        // unique per profile
+        old.execute_batch(
+            &SCHEMA
+                .replacen(
+                    "filename        TEXT NOT NULL,",
+                    "filename        TEXT NOT NULL UNIQUE,",
+                    1,
+                )
+                .replace(
+                    "filename        TEXT NOT NULL,\n    activity_id     INTEGER NOT NULL REFERENCES activities(id),\n    PRIMARY KEY (activity_id, filename)",
+                    "filename        TEXT PRIMARY KEY,\n    activity_id     INTEGER NOT NULL REFERENCES activities(id)",
+                )
+                .replace("    corrected_max_altitude REAL,\n", "")
+                .replace("    corrected_min_altitude REAL,\n", "")
+                .replace("    corrected_effort_distance REAL,\n", ""),
+        )
+        .unwrap();
+        old.execute_batch(
+            r#"INSERT INTO profiles (id, name) VALUES (1, 'Me');
+               INSERT INTO activities (id, filename, activity_date, start_time, week_start, month_start,
+                                       total_duration, total_records, profile_id)
+               VALUES (1, 'walk.fit', '2024-07-14', '2024-07-14T06:00:00+00:00', '2024-07-08', '2024-07-01', 1, 0, 1);"#,
+        )
+        .unwrap();
+        old.pragma_update(None, "user_version", SCHEMA_VERSION - 1)
+            .unwrap();
+        let backup = backup_database(&old, &dir, chrono::Utc::now()).unwrap();
+
+        let db_path = dir.join("fitness.db");
+        let mut conn = init_db(&db_path).unwrap();
+        let staged = stage_restore(Path::new(&backup.path), &db_path).unwrap();
+        prepare_db(&staged).expect("Failed to migrate backup");
+        replace_database(&mut conn, staged, &db_path).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        let (filename, effort): (String, Option<f64>) = conn
+            .query_row(
+                "SELECT filename, corrected_effort_distance FROM activities WHERE id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(filename, "walk.fit");
+        assert_eq!(effort, None);
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
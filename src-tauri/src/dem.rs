@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::error::AppError;
+
+/// Marker for missing data in SRTM tiles
+const HGT_VOID: i16 = -32768;
+
+/// One-degree SRTM elevation tile
+struct HgtTile {
+    /// Samples per row/column: 1201 for SRTM3, 3601 for SRTM1
+    size: usize,
+    /// Big-endian heights in metres, rows from north to south
+    data: Vec<i16>,
+}
+
+impl HgtTile {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, AppError> {
+        let size = match bytes.len() {
+            2_884_802 => 1201,
+            25_934_402 => 3601,
+            len => {
+                return Err(AppError::InvalidArgument(format!(
+                    "Unrecognised HGT tile size: {} bytes",
+                    len
+                )))
+            }
+        };
+
+        let data = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]))
+            .collect();
+
+        Ok(Self { size, data })
+    }
+
+    fn height(&self, row: usize, col: usize) -> Option<f64> {
+        let h = self.data[row * self.size + col];
+        (h != HGT_VOID).then_some(h as f64)
+    }
+
+    /// Bilinear sample at a position given as fractions of the tile
+    /// (`north` 0 at the southern edge, `east` 0 at the western edge)
+    fn sample(&self, north: f64, east: f64) -> Option<f64> {
+        let last = (self.size - 1) as f64;
+        let y = (1.0 - north) * last;
+        let x = east * last;
+
+        let row = (y.floor() as usize).min(self.size - 2);
+        let col = (x.floor() as usize).min(self.size - 2);
+        let fy = y - row as f64;
+        let fx = x - col as f64;
+
+        let corners = [
+            (self.height(row, col), (1.0 - fx) * (1.0 - fy)),
+            (self.height(row, col + 1), fx * (1.0 - fy)),
+            (self.height(row + 1, col), (1.0 - fx) * fy),
+            (self.height(row + 1, col + 1), fx * fy),
+        ];
+
+        // Skip void corners and renormalise the remaining weights
+        let (sum, weight) = corners
+            .iter()
+            .filter_map(|(h, w)| h.map(|h| (h * w, *w)))
+            .fold((0.0, 0.0), |(s, tw), (hw, w)| (s + hw, tw + w));
+
+        (weight > 0.0).then(|| sum / weight)
+    }
+}
+
+/// File name of the tile whose south-west corner is at the given whole degrees
+fn tile_name(lat: i32, lon: i32) -> String {
+    format!(
+        "{}{:02}{}{:03}.hgt",
+        if lat >= 0 { 'N' } else { 'S' },
+        lat.abs(),
+        if lon >= 0 { 'E' } else { 'W' },
+        lon.abs()
+    )
+}
+
+/// Local SRTM `.hgt` tile store used to correct recorded altitude
+pub struct DemStore {
+    dem_path: PathBuf,
+    /// Loaded tiles by south-west corner; `None` when no file covers that cell
+    tiles: HashMap<(i32, i32), Option<HgtTile>>,
+}
+
+impl DemStore {
+    pub fn new(app_data_dir: PathBuf) -> Self {
+        let dem_path = app_data_dir.join("dem");
+        std::fs::create_dir_all(&dem_path).ok();
+
+        Self {
+            dem_path,
+            tiles: HashMap::new(),
+        }
+    }
+
+    /// Terrain height (m) at a position, or `None` if no tile covers it
+    pub fn elevation(&mut self, lat: f64, lon: f64) -> Result<Option<f64>, AppError> {
+        let key = (lat.floor() as i32, lon.floor() as i32);
+
+        if !self.tiles.contains_key(&key) {
+            let path = self.dem_path.join(tile_name(key.0, key.1));
+            let tile = if path.exists() {
+                let bytes = std::fs::read(&path)
+                    .map_err(|e| AppError::NotFound(format!("{}: {}", path.display(), e)))?;
+                Some(HgtTile::from_bytes(&bytes)?)
+            } else {
+                None
+            };
+            self.tiles.insert(key, tile);
+        }
+
+        Ok(self.tiles[&key]
+            .as_ref()
+            .and_then(|tile| tile.sample(lat - key.0 as f64, lon - key.1 as f64)))
+    }
+
+    /// Terrain height for each position in a track
+    pub fn sample_track(
+        &mut self,
+        positions: &[(Option<f64>, Option<f64>)],
+    ) -> Result<Vec<Option<f64>>, AppError> {
+        positions
+            .iter()
+            .map(|pos| match pos {
+                (Some(lat), Some(lon)) => self.elevation(*lat, *lon),
+                _ => Ok(None),
+            })
+            .collect()
+    }
+
+    /// List available HGT tiles
+    pub fn list_available(&self) -> Vec<String> {
+        let mut files = Vec::new();
+
+        if let Ok(entries) = std::fs::read_dir(&self.dem_path) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.to_ascii_lowercase().ends_with(".hgt") {
+                    files.push(name);
+                }
+            }
+        }
+
+        files.sort();
+        files
+    }
+
+    /// Get the DEM directory path
+    pub fn get_dem_path(&self) -> PathBuf {
+        self.dem_path.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SRTM3 tile whose height rises 1 m per column eastwards
+    fn sloped_tile() -> HgtTile {
+        let size = 1201;
+        let data = (0..size * size).map(|i| (i % size) as i16).collect();
+        HgtTile { size, data }
+    }
+
+    #[test]
+    fn test_tile_name() {
+        assert_eq!(tile_name(46, 7), "N46E007.hgt");
+        assert_eq!(tile_name(-34, -71), "S34W071.hgt");
+    }
+
+    #[test]
+    fn test_bilinear_sample() {
+        let tile = sloped_tile();
+        assert_eq!(tile.sample(0.5, 0.0), Some(0.0));
+        assert_eq!(tile.sample(0.5, 1.0), Some(1200.0));
+        assert!((tile.sample(0.3, 0.25).unwrap() - 300.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_void_corners_ignored() {
+        let mut tile = sloped_tile();
+        tile.data[0] = HGT_VOID;
+        // Top-left cell: only the eastern corners remain
+        let h = tile.sample(1.0, 0.0001).unwrap();
+        assert!((h - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rejects_bad_size() {
+        assert!(HgtTile::from_bytes(&[0u8; 100]).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+/// Energy cost of walking/running on the flat (J/kg/m), from Minetti et al. (2002)
+const FLAT_COST: f64 = 3.6;
+
+/// Grade range over which the Minetti polynomial was fitted
+const MAX_GRADE: f64 = 0.45;
+
+/// Distance window (metres) over which the grade at a record is measured
+const GRADE_WINDOW: f64 = 30.0;
+
+/// Minimum horizontal run (metres) needed before a grade is trusted
+const MIN_GRADE_RUN: f64 = 10.0;
+
+/// Speed (m/s) below which a record is treated as stopped
+pub const MOVING_SPEED: f64 = 0.3;
+
+/// Metres of flat distance equivalent to one metre of ascent (100 m up = 1 km)
+const EFFORT_METRES_PER_METRE_CLIMB: f64 = 10.0;
+
+/// Minetti energy cost of locomotion (J/kg/m) for a grade given as rise/run
+pub fn minetti_cost(grade: f64) -> f64 {
+    let i = grade.clamp(-MAX_GRADE, MAX_GRADE);
+    155.4 * i.powi(5) - 30.4 * i.powi(4) - 43.3 * i.powi(3)
+        + 46.3 * i.powi(2)
+        + 19.5 * i
+        + FLAT_COST
+}
+
+/// Factor converting speed on a grade into the equivalent flat speed
+pub fn grade_adjustment(grade: f64) -> f64 {
+    minetti_cost(grade) / FLAT_COST
+}
+
+/// Grade at each record, measured over a centred distance window.
+///
+/// Records without distance or altitude, or without enough horizontal
+/// movement around them, get `None`.
+pub fn compute_grades(distance: &[Option<f64>], altitude: &[Option<f64>]) -> Vec<Option<f64>> {
+    // Only records with both distance and altitude take part in the window
+    let points: Vec<(usize, f64, f64)> = distance
+        .iter()
+        .zip(altitude)
+        .enumerate()
+        .filter_map(|(i, (d, a))| Some((i, (*d)?, (*a)?)))
+        .collect();
+
+    let mut grades = vec![None; distance.len()];
+    let half_window = GRADE_WINDOW / 2.0;
+    let mut back = 0;
+    let mut ahead = 0;
+
+    for (p, &(index, dist, _)) in points.iter().enumerate() {
+        while points[back].1 < dist - half_window {
+            back += 1;
+        }
+        if ahead < p {
+            ahead = p;
+        }
+        while ahead + 1 < points.len() && points[ahead + 1].1 <= dist + half_window {
+            ahead += 1;
+        }
+
+        let run = points[ahead].1 - points[back].1;
+        if run >= MIN_GRADE_RUN {
+            grades[index] = Some((points[ahead].2 - points[back].2) / run);
+        }
+    }
+
+    grades
+}
+
+/// Grade-adjusted speed (m/s) for each record; records without a grade are treated as flat
+pub fn grade_adjusted_speeds(speed: &[Option<f64>], grades: &[Option<f64>]) -> Vec<Option<f64>> {
+    speed
+        .iter()
+        .zip(grades)
+        .map(|(s, g)| s.map(|s| s * grade_adjustment(g.unwrap_or(0.0))))
+        .collect()
+}
+
+/// Time-weighted average grade-adjusted speed over moving intervals
+pub fn average_moving_speed(
+    speed: &[Option<f64>],
+    adjusted: &[Option<f64>],
+    time_deltas: &[f64],
+) -> Option<f64> {
+    let mut weighted = 0.0;
+    let mut moving_time = 0.0;
+
+    for ((s, gas), dt) in speed.iter().zip(adjusted).zip(time_deltas) {
+        if let (Some(s), Some(gas)) = (s, gas) {
+            if *s >= MOVING_SPEED {
+                weighted += gas * dt;
+                moving_time += dt;
+            }
+        }
+    }
+
+    if moving_time > 0.0 {
+        Some(weighted / moving_time)
+    } else {
+        None
+    }
+}
+
+/// Equivalent flat distance (metres): distance plus a km-effort allowance for climbing
+pub fn effort_distance(distance: f64, elevation_gain: f64) -> f64 {
+    distance + elevation_gain * EFFORT_METRES_PER_METRE_CLIMB
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_adjustment_is_one() {
+        assert!((grade_adjustment(0.0) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_uphill_costs_more_than_gentle_downhill() {
+        assert!(grade_adjustment(0.25) > 2.0);
+        assert!(grade_adjustment(-0.1) < 1.0);
+        // Steep descents cost more again than gentle ones
+        assert!(grade_adjustment(-0.4) > grade_adjustment(-0.1));
+    }
+
+    #[test]
+    fn test_grade_is_clamped() {
+        assert_eq!(minetti_cost(0.9), minetti_cost(MAX_GRADE));
+    }
+
+    #[test]
+    fn test_compute_grades_constant_slope() {
+        let distance: Vec<Option<f64>> = (0..20).map(|i| Some(i as f64 * 5.0)).collect();
+        let altitude: Vec<Option<f64>> = (0..20).map(|i| Some(i as f64 * 0.5)).collect();
+        let grades = compute_grades(&distance, &altitude);
+
+        for g in grades.iter().skip(2) {
+            assert!((g.unwrap() - 0.1).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_compute_grades_missing_altitude() {
+        let distance = vec![Some(0.0), Some(20.0), Some(40.0)];
+        let altitude = vec![Some(100.0), None, Some(104.0)];
+        let grades = compute_grades(&distance, &altitude);
+        assert_eq!(grades[1], None);
+    }
+
+    #[test]
+    fn test_average_moving_speed_ignores_stops() {
+        let speed = vec![Some(1.0), Some(0.0), Some(1.0)];
+        let adjusted = vec![Some(2.0), Some(0.0), Some(2.0)];
+        let avg = average_moving_speed(&speed, &adjusted, &[1.0, 100.0, 1.0]).unwrap();
+        assert!((avg - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_effort_distance() {
+        assert_eq!(effort_distance(10_000.0, 1_000.0), 20_000.0);
+    }
+}
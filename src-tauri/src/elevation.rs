@@ -0,0 +1,268 @@
+use serde::{Deserialize, Serialize};
+
+/// Smoothing applied to the altitude series before gain/loss is counted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "camelCase")]
+pub enum Smoothing {
+    None,
+    /// Centred rolling median over `window` samples
+    Median {
+        window: usize,
+    },
+    /// One-dimensional random-walk Kalman filter
+    #[serde(rename_all = "camelCase")]
+    Kalman {
+        /// Expected altitude variance per second of travel (m²/s)
+        process_noise: f64,
+        /// Variance of a single altitude reading (m²)
+        measurement_noise: f64,
+    },
+}
+
+/// Elevation cleanup settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ElevationFilter {
+    pub smoothing: Smoothing,
+    /// Readings implying a faster vertical speed than this (m/s) are dropped as spikes
+    pub max_vertical_speed: Option<f64>,
+    /// Minimum climb or descent (m) before it counts towards gain or loss
+    pub hysteresis: f64,
+}
+
+impl Default for ElevationFilter {
+    fn default() -> Self {
+        Self {
+            smoothing: Smoothing::Median { window: 5 },
+            max_vertical_speed: Some(5.0),
+            hysteresis: 3.0,
+        }
+    }
+}
+
+/// Elevation totals from a filtered altitude series
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ElevationStats {
+    pub gain: f64,
+    pub loss: f64,
+    pub max_altitude: Option<f64>,
+    pub min_altitude: Option<f64>,
+}
+
+/// Drop readings that jump faster than `max_vertical_speed` from the last accepted one
+pub fn reject_spikes(
+    times: &[f64],
+    altitudes: &[Option<f64>],
+    max_vertical_speed: f64,
+) -> Vec<Option<f64>> {
+    let mut last: Option<(f64, f64)> = None;
+
+    times
+        .iter()
+        .zip(altitudes)
+        .map(|(&t, alt)| {
+            let alt = (*alt)?;
+            if let Some((last_t, last_alt)) = last {
+                // Allow at least one second of travel so bursts of identical timestamps aren't rejected
+                let dt = (t - last_t).max(1.0);
+                if (alt - last_alt).abs() / dt > max_vertical_speed {
+                    return None;
+                }
+            }
+            last = Some((t, alt));
+            Some(alt)
+        })
+        .collect()
+}
+
+/// Centred rolling median over the available readings
+pub fn median_smooth(altitudes: &[Option<f64>], window: usize) -> Vec<Option<f64>> {
+    let half = window.max(1) / 2;
+    let mut buffer: Vec<f64> = Vec::with_capacity(window);
+
+    (0..altitudes.len())
+        .map(|i| {
+            altitudes[i]?;
+            let from = i.saturating_sub(half);
+            let to = (i + half + 1).min(altitudes.len());
+            buffer.clear();
+            buffer.extend(altitudes[from..to].iter().flatten());
+            buffer.sort_by(|a, b| a.total_cmp(b));
+            Some(buffer[buffer.len() / 2])
+        })
+        .collect()
+}
+
+/// Forward Kalman filter treating altitude as a random walk in time
+pub fn kalman_smooth(
+    times: &[f64],
+    altitudes: &[Option<f64>],
+    process_noise: f64,
+    measurement_noise: f64,
+) -> Vec<Option<f64>> {
+    let mut state: Option<(f64, f64, f64)> = None; // (time, estimate, variance)
+
+    times
+        .iter()
+        .zip(altitudes)
+        .map(|(&t, alt)| {
+            let alt = (*alt)?;
+            let (estimate, variance) = match state {
+                None => (alt, measurement_noise),
+                Some((last_t, estimate, variance)) => {
+                    let predicted = variance + process_noise * (t - last_t).max(0.0);
+                    let gain = predicted / (predicted + measurement_noise);
+                    (estimate + gain * (alt - estimate), (1.0 - gain) * predicted)
+                }
+            };
+            state = Some((t, estimate, variance));
+            Some(estimate)
+        })
+        .collect()
+}
+
+/// Apply spike rejection and smoothing to an altitude series
+pub fn filter_altitudes(
+    times: &[f64],
+    altitudes: &[Option<f64>],
+    filter: &ElevationFilter,
+) -> Vec<Option<f64>> {
+    let despiked = match filter.max_vertical_speed {
+        Some(max) => reject_spikes(times, altitudes, max),
+        None => altitudes.to_vec(),
+    };
+
+    match &filter.smoothing {
+        Smoothing::None => despiked,
+        Smoothing::Median { window } => median_smooth(&despiked, *window),
+        Smoothing::Kalman {
+            process_noise,
+            measurement_noise,
+        } => kalman_smooth(times, &despiked, *process_noise, *measurement_noise),
+    }
+}
+
+/// Gain and loss credited at each record.
+///
+/// A climb or descent only starts counting once it exceeds `threshold` from
+/// the last turning point; after that it is followed sample by sample until
+/// the direction reverses by more than `threshold`.
+pub fn hysteresis_steps(altitudes: &[Option<f64>], threshold: f64) -> Vec<(f64, f64)> {
+    let mut reference: Option<f64> = None;
+    let mut climbing: Option<bool> = None;
+
+    altitudes
+        .iter()
+        .map(|alt| {
+            let Some(alt) = *alt else {
+                return (0.0, 0.0);
+            };
+            let Some(base) = reference else {
+                reference = Some(alt);
+                return (0.0, 0.0);
+            };
+
+            let rise = alt - base;
+            let step = if rise >= threshold || (climbing == Some(true) && rise > 0.0) {
+                climbing = Some(true);
+                (rise, 0.0)
+            } else if -rise >= threshold || (climbing == Some(false) && rise < 0.0) {
+                climbing = Some(false);
+                (0.0, -rise)
+            } else {
+                return (0.0, 0.0);
+            };
+            reference = Some(alt);
+            step
+        })
+        .collect()
+}
+
+/// Gain, loss and altitude range of an already filtered series
+pub fn elevation_stats(filtered: &[Option<f64>], threshold: f64) -> ElevationStats {
+    let (gain, loss) = hysteresis_steps(filtered, threshold)
+        .into_iter()
+        .fold((0.0, 0.0), |(g, l), (dg, dl)| (g + dg, l + dl));
+
+    ElevationStats {
+        gain,
+        loss,
+        max_altitude: filtered.iter().flatten().copied().reduce(f64::max),
+        min_altitude: filtered.iter().flatten().copied().reduce(f64::min),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series(values: &[f64]) -> Vec<Option<f64>> {
+        values.iter().map(|v| Some(*v)).collect()
+    }
+
+    fn seconds(n: usize) -> Vec<f64> {
+        (0..n).map(|i| i as f64).collect()
+    }
+
+    #[test]
+    fn test_reject_spikes() {
+        let alts = series(&[100.0, 101.0, 160.0, 102.0, 103.0]);
+        let cleaned = reject_spikes(&seconds(5), &alts, 5.0);
+        assert_eq!(
+            cleaned,
+            vec![Some(100.0), Some(101.0), None, Some(102.0), Some(103.0)]
+        );
+    }
+
+    #[test]
+    fn test_median_removes_single_outlier() {
+        let alts = series(&[100.0, 100.0, 130.0, 100.0, 100.0]);
+        let smoothed = median_smooth(&alts, 3);
+        assert!(smoothed.iter().all(|a| *a == Some(100.0)));
+    }
+
+    #[test]
+    fn test_kalman_converges() {
+        let alts = series(&[100.0; 50]);
+        let smoothed = kalman_smooth(&seconds(50), &alts, 0.1, 4.0);
+        assert!((smoothed[49].unwrap() - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hysteresis_ignores_jitter() {
+        // Oscillating ±1 m noise on a flat walk yields no gain
+        let alts: Vec<Option<f64>> = (0..100).map(|i| Some(100.0 + (i % 2) as f64)).collect();
+        let stats = elevation_stats(&alts, 3.0);
+        assert_eq!(stats.gain, 0.0);
+        assert_eq!(stats.loss, 0.0);
+    }
+
+    #[test]
+    fn test_hysteresis_counts_real_climb() {
+        let mut values: Vec<f64> = (0..=50).map(|i| 100.0 + i as f64).collect();
+        values.extend((0..=20).map(|i| 150.0 - i as f64));
+        let stats = elevation_stats(&series(&values), 3.0);
+        assert_eq!(stats.gain, 50.0);
+        assert_eq!(stats.loss, 20.0);
+        assert_eq!(stats.max_altitude, Some(150.0));
+        assert_eq!(stats.min_altitude, Some(100.0));
+    }
+
+    #[test]
+    fn test_filter_settings_round_trip() {
+        let filter = ElevationFilter {
+            smoothing: Smoothing::Kalman {
+                process_noise: 0.5,
+                measurement_noise: 9.0,
+            },
+            max_vertical_speed: None,
+            hysteresis: 2.0,
+        };
+        let json = serde_json::to_string(&filter).unwrap();
+        assert!(json.contains("\"method\":\"kalman\""));
+        assert_eq!(
+            serde_json::from_str::<ElevationFilter>(&json).unwrap(),
+            filter
+        );
+    }
+}
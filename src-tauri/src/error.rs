@@ -19,6 +19,15 @@ pub enum AppError {
 
     #[error("Not found: {0}")]
     NotFound(String),
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    #[error("File error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Export error: {0}")]
+    Export(String),
 }
 
 // Implement serialization for Tauri commands
@@ -0,0 +1,447 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+
+use crate::error::AppError;
+use crate::fit_writer::encode_activity;
+use crate::types::{ActivityDetail, TrackRecord};
+
+/// Distance (m) at which TCX laps are cut, since FIT laps aren't stored
+const TCX_LAP_DISTANCE: f64 = 1000.0;
+
+/// File format activities can be exported to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Gpx,
+    Tcx,
+    GeoJson,
+    Fit,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Result<Self, AppError> {
+        match format.to_ascii_lowercase().as_str() {
+            "gpx" => Ok(ExportFormat::Gpx),
+            "tcx" => Ok(ExportFormat::Tcx),
+            "geojson" | "json" => Ok(ExportFormat::GeoJson),
+            "fit" => Ok(ExportFormat::Fit),
+            other => Err(AppError::InvalidArgument(format!(
+                "Unknown export format: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Render activities as the contents of a file in the given format. A FIT
+/// file holds a single activity.
+pub fn render_activities(
+    format: ExportFormat,
+    activities: &[ActivityDetail],
+) -> Result<Vec<u8>, AppError> {
+    let text = match format {
+        ExportFormat::Gpx => to_gpx(activities),
+        ExportFormat::Tcx => to_tcx(activities),
+        ExportFormat::GeoJson => to_geojson(activities).to_string(),
+        ExportFormat::Fit => match activities {
+            [activity] => return Ok(encode_activity(activity)),
+            _ => {
+                return Err(AppError::InvalidArgument(
+                    "A FIT file holds exactly one activity".to_string(),
+                ))
+            }
+        },
+    };
+    Ok(text.into_bytes())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Stored RFC 3339 timestamp as UTC with a Z suffix, as GPX and TCX readers expect
+fn utc_time(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| {
+            t.with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Secs, true)
+        })
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+fn position(record: &TrackRecord) -> Option<(f64, f64)> {
+    record
+        .clean_position_lat
+        .zip(record.clean_position_long)
+        .or(record.position_lat.zip(record.position_long))
+}
+
+/// GPX 1.1 with one track per activity and heart rate and temperature in
+/// Garmin TrackPointExtension elements
+pub fn to_gpx(activities: &[ActivityDetail]) -> String {
+    let mut gpx = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="Hike IQ" xmlns="http://www.topografix.com/GPX/1/1" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1" xsi:schemaLocation="http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd http://www.garmin.com/xmlschemas/TrackPointExtension/v1 http://www8.garmin.com/xmlschemas/TrackPointExtensionv1.xsd">
+"#,
+    );
+    if let Some(first) = activities.first() {
+        let time = utc_time(&first.activity.start_time);
+        gpx.push_str(&format!(
+            "  <metadata>\n    <time>{}</time>\n  </metadata>\n",
+            time
+        ));
+    }
+
+    for detail in activities {
+        let activity = &detail.activity;
+        gpx.push_str("  <trk>\n");
+        gpx.push_str(&format!(
+            "    <name>{}</name>\n",
+            escape_xml(&activity.title)
+        ));
+        if let Some(description) = &activity.description {
+            gpx.push_str(&format!("    <desc>{}</desc>\n", escape_xml(description)));
+        }
+        gpx.push_str(&format!(
+            "    <type>{}</type>\n",
+            escape_xml(&activity.activity_type)
+        ));
+        gpx.push_str("    <trkseg>\n");
+
+        for record in &detail.records {
+            let Some((lat, lon)) = position(record) else {
+                continue;
+            };
+            gpx.push_str(&format!(
+                "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\">\n",
+                lat, lon
+            ));
+            if let Some(altitude) = record.altitude {
+                gpx.push_str(&format!("        <ele>{:.1}</ele>\n", altitude));
+            }
+            gpx.push_str(&format!(
+                "        <time>{}</time>\n",
+                utc_time(&record.timestamp)
+            ));
+            if record.heart_rate.is_some() || record.temperature.is_some() {
+                gpx.push_str("        <extensions>\n          <gpxtpx:TrackPointExtension>\n");
+                if let Some(temperature) = record.temperature {
+                    gpx.push_str(&format!(
+                        "            <gpxtpx:atemp>{:.1}</gpxtpx:atemp>\n",
+                        temperature
+                    ));
+                }
+                if let Some(heart_rate) = record.heart_rate {
+                    gpx.push_str(&format!(
+                        "            <gpxtpx:hr>{}</gpxtpx:hr>\n",
+                        heart_rate
+                    ));
+                }
+                gpx.push_str("          </gpxtpx:TrackPointExtension>\n        </extensions>\n");
+            }
+            gpx.push_str("      </trkpt>\n");
+        }
+
+        gpx.push_str("    </trkseg>\n  </trk>\n");
+    }
+
+    gpx.push_str("</gpx>\n");
+    gpx
+}
+
+/// TCX only knows running and biking; everything else is "Other"
+fn tcx_sport(activity_type: &str) -> &'static str {
+    match activity_type {
+        "Run" | "Trail Run" => "Running",
+        "Ride" | "Mountain Bike" | "E-Bike Ride" => "Biking",
+        _ => "Other",
+    }
+}
+
+/// Split records into laps of `lap_distance` metres by recorded distance.
+/// Activities without distance form a single lap.
+pub fn lap_ranges(records: &[TrackRecord], lap_distance: f64) -> Vec<std::ops::Range<usize>> {
+    let mut laps = Vec::new();
+    let mut start = 0;
+    let mut next_boundary = lap_distance;
+
+    for (i, record) in records.iter().enumerate() {
+        if record.distance.is_some_and(|d| d >= next_boundary) {
+            laps.push(start..i + 1);
+            start = i + 1;
+            while record.distance.is_some_and(|d| d >= next_boundary) {
+                next_boundary += lap_distance;
+            }
+        }
+    }
+    if start < records.len() {
+        laps.push(start..records.len());
+    }
+
+    laps
+}
+
+/// TCX with 1 km laps, each holding its own trackpoints
+pub fn to_tcx(activities: &[ActivityDetail]) -> String {
+    let mut tcx = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2 http://www.garmin.com/xmlschemas/TrainingCenterDatabasev2.xsd">
+  <Activities>
+"#,
+    );
+
+    for detail in activities {
+        let activity = &detail.activity;
+        let records = &detail.records;
+        tcx.push_str(&format!(
+            "    <Activity Sport=\"{}\">\n",
+            tcx_sport(&activity.activity_type)
+        ));
+        tcx.push_str(&format!(
+            "      <Id>{}</Id>\n",
+            utc_time(&activity.start_time)
+        ));
+
+        for lap in lap_ranges(records, TCX_LAP_DISTANCE) {
+            let lap_records = &records[lap.clone()];
+            let first = &lap_records[0];
+            let last = &lap_records[lap_records.len() - 1];
+            // Time and distance run from the end of the previous lap
+            let previous = lap.start.checked_sub(1).map(|i| &records[i]);
+            let start_time = previous.map_or(first.elapsed_time, |r| r.elapsed_time);
+            let start_distance = previous
+                .and_then(|r| r.distance)
+                .or(first.distance)
+                .unwrap_or(0.0);
+            let distance = last.distance.map_or(0.0, |d| d - start_distance);
+            let heart_rates: Vec<i32> = lap_records.iter().filter_map(|r| r.heart_rate).collect();
+
+            tcx.push_str(&format!(
+                "      <Lap StartTime=\"{}\">\n",
+                utc_time(&first.timestamp)
+            ));
+            tcx.push_str(&format!(
+                "        <TotalTimeSeconds>{:.1}</TotalTimeSeconds>\n",
+                last.elapsed_time - start_time
+            ));
+            tcx.push_str(&format!(
+                "        <DistanceMeters>{:.1}</DistanceMeters>\n",
+                distance
+            ));
+            tcx.push_str("        <Calories>0</Calories>\n");
+            if !heart_rates.is_empty() {
+                let average = heart_rates.iter().sum::<i32>() as f64 / heart_rates.len() as f64;
+                let maximum = heart_rates.iter().max().copied().unwrap_or_default();
+                tcx.push_str(&format!(
+                    "        <AverageHeartRateBpm><Value>{}</Value></AverageHeartRateBpm>\n",
+                    average.round() as i32
+                ));
+                tcx.push_str(&format!(
+                    "        <MaximumHeartRateBpm><Value>{}</Value></MaximumHeartRateBpm>\n",
+                    maximum
+                ));
+            }
+            tcx.push_str("        <Intensity>Active</Intensity>\n");
+            tcx.push_str("        <TriggerMethod>Distance</TriggerMethod>\n");
+            tcx.push_str("        <Track>\n");
+
+            for record in lap_records {
+                tcx.push_str("          <Trackpoint>\n");
+                tcx.push_str(&format!(
+                    "            <Time>{}</Time>\n",
+                    utc_time(&record.timestamp)
+                ));
+                if let Some((lat, lon)) = position(record) {
+                    tcx.push_str(&format!(
+                        "            <Position><LatitudeDegrees>{:.7}</LatitudeDegrees><LongitudeDegrees>{:.7}</LongitudeDegrees></Position>\n",
+                        lat, lon
+                    ));
+                }
+                if let Some(altitude) = record.altitude {
+                    tcx.push_str(&format!(
+                        "            <AltitudeMeters>{:.1}</AltitudeMeters>\n",
+                        altitude
+                    ));
+                }
+                if let Some(distance) = record.distance {
+                    tcx.push_str(&format!(
+                        "            <DistanceMeters>{:.1}</DistanceMeters>\n",
+                        distance
+                    ));
+                }
+                if let Some(heart_rate) = record.heart_rate {
+                    tcx.push_str(&format!(
+                        "            <HeartRateBpm><Value>{}</Value></HeartRateBpm>\n",
+                        heart_rate
+                    ));
+                }
+                tcx.push_str("          </Trackpoint>\n");
+            }
+
+            tcx.push_str("        </Track>\n      </Lap>\n");
+        }
+
+        if let Some(notes) = &activity.notes {
+            tcx.push_str(&format!("      <Notes>{}</Notes>\n", escape_xml(notes)));
+        }
+        tcx.push_str("    </Activity>\n");
+    }
+
+    tcx.push_str("  </Activities>\n</TrainingCenterDatabase>\n");
+    tcx
+}
+
+/// GeoJSON FeatureCollection with a LineString per activity. Per-point
+/// values are in `coordinateProperties`, aligned with the coordinates.
+pub fn to_geojson(activities: &[ActivityDetail]) -> Value {
+    let features: Vec<Value> = activities
+        .iter()
+        .map(|detail| {
+            let activity = &detail.activity;
+            let points: Vec<(&TrackRecord, (f64, f64))> =
+                detail.records.iter().filter_map(|r| position(r).map(|p| (r, p))).collect();
+
+            let coordinates: Vec<Value> = points
+                .iter()
+                .map(|(record, (lat, lon))| match record.altitude {
+                    Some(altitude) => json!([lon, lat, altitude]),
+                    None => json!([lon, lat]),
+                })
+                .collect();
+
+            json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": coordinates,
+                },
+                "properties": {
+                    "id": activity.id,
+                    "name": activity.title,
+                    "activityType": activity.activity_type,
+                    "startTime": utc_time(&activity.start_time),
+                    "totalDuration": activity.total_duration,
+                    "totalDistance": activity.total_distance,
+                    "elevationGain": activity.elevation_gain,
+                    "elevationLoss": activity.elevation_loss,
+                    "coordinateProperties": {
+                        "times": points.iter().map(|(r, _)| utc_time(&r.timestamp)).collect::<Vec<_>>(),
+                        "heartRates": points.iter().map(|(r, _)| r.heart_rate).collect::<Vec<_>>(),
+                        "temperatures": points.iter().map(|(r, _)| r.temperature).collect::<Vec<_>>(),
+                        "distances": points.iter().map(|(r, _)| r.distance).collect::<Vec<_>>(),
+                    },
+                },
+            })
+        })
+        .collect();
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Activity;
+
+    fn record(i: usize) -> TrackRecord {
+        TrackRecord {
+            timestamp: format!("2024-07-14T08:00:{:02}+02:00", i),
+            elapsed_time: i as f64,
+            heart_rate: Some(120 + i as i32),
+            distance: Some(i as f64 * 400.0),
+            altitude: Some(1000.0 + i as f64),
+            temperature: if i == 0 { Some(12.0) } else { None },
+            position_lat: Some(46.0 + i as f64 * 0.001),
+            position_long: Some(7.0),
+            zone: "zone1".to_string(),
+            ..TrackRecord::default()
+        }
+    }
+
+    fn detail(n: usize) -> ActivityDetail {
+        let activity = Activity {
+            id: 1,
+            filename: "walk.fit".to_string(),
+            title: "Walk <to> the \"hut\"".to_string(),
+            activity_type: "Hike".to_string(),
+            activity_date: "2024-07-14".to_string(),
+            start_time: "2024-07-14T08:00:00+02:00".to_string(),
+            total_duration: (n - 1) as f64,
+            total_distance: Some((n - 1) as f64 * 400.0),
+            elevation_gain: Some((n - 1) as f64),
+            ..Activity::default()
+        };
+        ActivityDetail {
+            activity,
+            records: (0..n).map(record).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(ExportFormat::parse("GPX").unwrap(), ExportFormat::Gpx);
+        assert_eq!(
+            ExportFormat::parse("geojson").unwrap(),
+            ExportFormat::GeoJson
+        );
+        assert_eq!(ExportFormat::parse("fit").unwrap(), ExportFormat::Fit);
+        assert!(ExportFormat::parse("kml").is_err());
+    }
+
+    #[test]
+    fn test_fit_single_activity() {
+        assert!(render_activities(ExportFormat::Fit, &[detail(3)]).is_ok());
+        assert!(render_activities(ExportFormat::Fit, &[detail(3), detail(3)]).is_err());
+    }
+
+    #[test]
+    fn test_gpx() {
+        let gpx = to_gpx(&[detail(3)]);
+        assert!(gpx.contains("<name>Walk &lt;to&gt; the &quot;hut&quot;</name>"));
+        assert_eq!(gpx.matches("<trkpt ").count(), 3);
+        assert!(gpx.contains("<time>2024-07-14T06:00:01Z</time>"));
+        assert!(gpx.contains("<gpxtpx:hr>122</gpxtpx:hr>"));
+        assert!(gpx.contains("<gpxtpx:atemp>12.0</gpxtpx:atemp>"));
+    }
+
+    #[test]
+    fn test_lap_ranges() {
+        // Distances 0, 400, 800, 1200, 1600, 2000, 2400
+        let records: Vec<TrackRecord> = (0..7).map(record).collect();
+        assert_eq!(lap_ranges(&records, 1000.0), vec![0..4, 4..6, 6..7]);
+        assert_eq!(lap_ranges(&records[..2], 1000.0), vec![0..2]);
+    }
+
+    #[test]
+    fn test_tcx_laps() {
+        let tcx = to_tcx(&[detail(7)]);
+        assert_eq!(tcx.matches("<Lap ").count(), 3);
+        assert_eq!(tcx.matches("<Trackpoint>").count(), 7);
+        // The first lap runs to the record at 1200 m, the second from there to 2000 m
+        assert!(tcx.contains("<DistanceMeters>1200.0</DistanceMeters>\n        <Calories>"));
+        assert!(tcx.contains("<DistanceMeters>800.0</DistanceMeters>\n        <Calories>"));
+        assert!(tcx.contains("<Activity Sport=\"Other\">"));
+    }
+
+    #[test]
+    fn test_geojson() {
+        let geojson = to_geojson(&[detail(3)]);
+        let feature = &geojson["features"][0];
+        assert_eq!(feature["geometry"]["type"], "LineString");
+        assert_eq!(
+            feature["geometry"]["coordinates"][1],
+            json!([7.0, 46.001, 1001.0])
+        );
+        assert_eq!(
+            feature["properties"]["coordinateProperties"]["heartRates"],
+            json!([120, 121, 122])
+        );
+        assert_eq!(feature["properties"]["startTime"], "2024-07-14T06:00:00Z");
+    }
+}
@@ -0,0 +1,535 @@
+use chrono::{DateTime, Utc};
+
+use crate::sports::fit_sport_codes;
+use crate::types::{ActivityDetail, TrackRecord};
+
+/// Seconds between the Unix epoch and the FIT epoch, 1989-12-31T00:00:00Z
+const FIT_EPOCH_OFFSET: i64 = 631_065_600;
+
+/// FIT protocol 2.0
+const PROTOCOL_VERSION: u8 = 0x20;
+
+/// FIT profile 21.32
+const PROFILE_VERSION: u16 = 2132;
+
+/// Manufacturer id reserved for development
+const MANUFACTURER_DEVELOPMENT: i64 = 255;
+
+/// FIT base types used by the messages we write
+#[derive(Debug, Clone, Copy)]
+enum BaseType {
+    Enum,
+    SInt8,
+    UInt8,
+    UInt16,
+    SInt32,
+    UInt32,
+    UInt32z,
+}
+
+impl BaseType {
+    fn id(self) -> u8 {
+        match self {
+            BaseType::Enum => 0x00,
+            BaseType::SInt8 => 0x01,
+            BaseType::UInt8 => 0x02,
+            BaseType::UInt16 => 0x84,
+            BaseType::SInt32 => 0x85,
+            BaseType::UInt32 => 0x86,
+            BaseType::UInt32z => 0x8C,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            BaseType::Enum | BaseType::SInt8 | BaseType::UInt8 => 1,
+            BaseType::UInt16 => 2,
+            BaseType::SInt32 | BaseType::UInt32 | BaseType::UInt32z => 4,
+        }
+    }
+
+    /// Value meaning "no data"
+    fn invalid(self) -> i64 {
+        match self {
+            BaseType::Enum | BaseType::UInt8 => 0xFF,
+            BaseType::SInt8 => 0x7F,
+            BaseType::UInt16 => 0xFFFF,
+            BaseType::SInt32 => 0x7FFF_FFFF,
+            BaseType::UInt32 => 0xFFFF_FFFF,
+            BaseType::UInt32z => 0,
+        }
+    }
+
+    /// Whether a value fits the type without being mistaken for invalid
+    fn holds(self, value: i64) -> bool {
+        let range = match self {
+            BaseType::Enum | BaseType::UInt8 => 0..=0xFE,
+            BaseType::SInt8 => -0x7F..=0x7E,
+            BaseType::UInt16 => 0..=0xFFFE,
+            BaseType::SInt32 => -0x7FFF_FFFF..=0x7FFF_FFFE,
+            BaseType::UInt32 => 0..=0xFFFF_FFFE,
+            BaseType::UInt32z => 1..=0xFFFF_FFFF,
+        };
+        range.contains(&value)
+    }
+}
+
+/// A message type as (global message number, [(field number, base type)])
+type MessageDef = (u16, &'static [(u8, BaseType)]);
+
+const FILE_ID: MessageDef = (
+    0,
+    &[
+        (0, BaseType::Enum),    // type
+        (1, BaseType::UInt16),  // manufacturer
+        (2, BaseType::UInt16),  // product
+        (3, BaseType::UInt32z), // serial_number
+        (4, BaseType::UInt32),  // time_created
+    ],
+);
+
+const EVENT: MessageDef = (
+    21,
+    &[
+        (253, BaseType::UInt32), // timestamp
+        (0, BaseType::Enum),     // event
+        (1, BaseType::Enum),     // event_type
+    ],
+);
+
+const RECORD: MessageDef = (
+    20,
+    &[
+        (253, BaseType::UInt32), // timestamp
+        (0, BaseType::SInt32),   // position_lat
+        (1, BaseType::SInt32),   // position_long
+        (2, BaseType::UInt16),   // altitude
+        (3, BaseType::UInt8),    // heart_rate
+        (5, BaseType::UInt32),   // distance
+        (6, BaseType::UInt16),   // speed
+        (13, BaseType::SInt8),   // temperature
+    ],
+);
+
+const LAP: MessageDef = (
+    19,
+    &[
+        (254, BaseType::UInt16), // message_index
+        (253, BaseType::UInt32), // timestamp
+        (0, BaseType::Enum),     // event
+        (1, BaseType::Enum),     // event_type
+        (2, BaseType::UInt32),   // start_time
+        (3, BaseType::SInt32),   // start_position_lat
+        (4, BaseType::SInt32),   // start_position_long
+        (5, BaseType::SInt32),   // end_position_lat
+        (6, BaseType::SInt32),   // end_position_long
+        (7, BaseType::UInt32),   // total_elapsed_time
+        (8, BaseType::UInt32),   // total_timer_time
+        (9, BaseType::UInt32),   // total_distance
+        (15, BaseType::UInt8),   // avg_heart_rate
+        (16, BaseType::UInt8),   // max_heart_rate
+        (21, BaseType::UInt16),  // total_ascent
+        (22, BaseType::UInt16),  // total_descent
+        (25, BaseType::Enum),    // sport
+        (39, BaseType::Enum),    // sub_sport
+    ],
+);
+
+const SESSION: MessageDef = (
+    18,
+    &[
+        (254, BaseType::UInt16), // message_index
+        (253, BaseType::UInt32), // timestamp
+        (0, BaseType::Enum),     // event
+        (1, BaseType::Enum),     // event_type
+        (2, BaseType::UInt32),   // start_time
+        (3, BaseType::SInt32),   // start_position_lat
+        (4, BaseType::SInt32),   // start_position_long
+        (5, BaseType::Enum),     // sport
+        (6, BaseType::Enum),     // sub_sport
+        (7, BaseType::UInt32),   // total_elapsed_time
+        (8, BaseType::UInt32),   // total_timer_time
+        (9, BaseType::UInt32),   // total_distance
+        (16, BaseType::UInt8),   // avg_heart_rate
+        (17, BaseType::UInt8),   // max_heart_rate
+        (22, BaseType::UInt16),  // total_ascent
+        (23, BaseType::UInt16),  // total_descent
+        (25, BaseType::UInt16),  // first_lap_index
+        (26, BaseType::UInt16),  // num_laps
+    ],
+);
+
+const ACTIVITY: MessageDef = (
+    34,
+    &[
+        (253, BaseType::UInt32), // timestamp
+        (0, BaseType::UInt32),   // total_timer_time
+        (1, BaseType::UInt16),   // num_sessions
+        (2, BaseType::Enum),     // type
+        (3, BaseType::Enum),     // event
+        (4, BaseType::Enum),     // event_type
+    ],
+);
+
+// Values of the FIT `file`, `event` and `event_type` enums
+const FILE_ACTIVITY: i64 = 4;
+const EVENT_TIMER: i64 = 0;
+const EVENT_SESSION: i64 = 8;
+const EVENT_LAP: i64 = 9;
+const EVENT_ACTIVITY: i64 = 26;
+const EVENT_TYPE_START: i64 = 0;
+const EVENT_TYPE_STOP: i64 = 1;
+const EVENT_TYPE_STOP_ALL: i64 = 4;
+
+static CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401,
+    0x5000, 0x9C01, 0x8801, 0x4400,
+];
+
+/// FIT CRC-16 of `bytes`, continuing from `crc`
+pub fn fit_crc(mut crc: u16, bytes: &[u8]) -> u16 {
+    for &byte in bytes {
+        let tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ CRC_TABLE[(byte & 0xF) as usize];
+        let tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ CRC_TABLE[((byte >> 4) & 0xF) as usize];
+    }
+    crc
+}
+
+/// Builds the data section of a FIT file, one local message type per
+/// message definition
+struct FitWriter {
+    data: Vec<u8>,
+    defined: Vec<u16>,
+}
+
+impl FitWriter {
+    fn new() -> Self {
+        FitWriter {
+            data: Vec::new(),
+            defined: Vec::new(),
+        }
+    }
+
+    /// Write a data message, preceded by its definition the first time the
+    /// message type is used. Values are in field order; None is written as invalid.
+    fn write(&mut self, (global, fields): MessageDef, values: &[Option<i64>]) {
+        debug_assert_eq!(fields.len(), values.len());
+        let local = match self.defined.iter().position(|g| *g == global) {
+            Some(local) => local as u8,
+            None => {
+                let local = self.defined.len() as u8;
+                self.defined.push(global);
+                // Definition header, reserved byte, little-endian architecture
+                self.data.extend([0x40 | local, 0, 0]);
+                self.data.extend(global.to_le_bytes());
+                self.data.push(fields.len() as u8);
+                for (number, base) in fields {
+                    self.data.extend([*number, base.size() as u8, base.id()]);
+                }
+                local
+            }
+        };
+
+        self.data.push(local);
+        for ((_, base), value) in fields.iter().zip(values) {
+            let value = value
+                .filter(|v| base.holds(*v))
+                .unwrap_or_else(|| base.invalid());
+            self.data.extend(&value.to_le_bytes()[..base.size()]);
+        }
+    }
+
+    /// Complete file: header, data and CRC
+    fn finish(self) -> Vec<u8> {
+        let mut file = Vec::with_capacity(self.data.len() + 16);
+        file.push(14);
+        file.push(PROTOCOL_VERSION);
+        file.extend(PROFILE_VERSION.to_le_bytes());
+        file.extend((self.data.len() as u32).to_le_bytes());
+        file.extend(b".FIT");
+        let header_crc = fit_crc(0, &file);
+        file.extend(header_crc.to_le_bytes());
+        file.extend(&self.data);
+        let crc = fit_crc(0, &file);
+        file.extend(crc.to_le_bytes());
+        file
+    }
+}
+
+fn fit_time(time: DateTime<Utc>) -> i64 {
+    time.timestamp() - FIT_EPOCH_OFFSET
+}
+
+fn semicircles(degrees: Option<f64>) -> Option<i64> {
+    degrees.map(|d| (d * 2_147_483_648.0 / 180.0).round() as i64)
+}
+
+/// Value in FIT units: (value + offset) * scale
+fn scaled(value: Option<f64>, scale: f64, offset: f64) -> Option<i64> {
+    value.map(|v| ((v + offset) * scale).round() as i64)
+}
+
+fn record_time(record: &TrackRecord) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&record.timestamp)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Encode a stored activity as a FIT activity file with file_id, event,
+/// record, lap, session and activity messages. Positions are the device
+/// fixes, so cleaning isn't applied twice when the file is imported again.
+pub fn encode_activity(detail: &ActivityDetail) -> Vec<u8> {
+    let activity = &detail.activity;
+    let records: Vec<(DateTime<Utc>, &TrackRecord)> = detail
+        .records
+        .iter()
+        .filter_map(|r| record_time(r).map(|t| (t, r)))
+        .collect();
+
+    let start = records.first().map(|(t, _)| fit_time(*t));
+    let end = records.last().map(|(t, _)| fit_time(*t));
+    let start_fix = records
+        .iter()
+        .find_map(|(_, r)| r.position_lat.zip(r.position_long));
+    let end_fix = records
+        .iter()
+        .rev()
+        .find_map(|(_, r)| r.position_lat.zip(r.position_long));
+    let heart_rates: Vec<i64> = records
+        .iter()
+        .filter_map(|(_, r)| r.heart_rate)
+        .map(i64::from)
+        .collect();
+    let avg_heart_rate = (!heart_rates.is_empty()).then(|| {
+        (heart_rates.iter().sum::<i64>() as f64 / heart_rates.len() as f64).round() as i64
+    });
+    let max_heart_rate = heart_rates.iter().max().copied();
+    let elapsed_time = start.zip(end).map(|(s, e)| (e - s) as f64);
+    let timer_time = Some(activity.total_duration);
+    let (sport, sub_sport) = fit_sport_codes(&activity.activity_type);
+    let (sport, sub_sport) = (Some(sport as i64), Some(sub_sport as i64));
+
+    let mut writer = FitWriter::new();
+    writer.write(
+        FILE_ID,
+        &[
+            Some(FILE_ACTIVITY),
+            Some(MANUFACTURER_DEVELOPMENT),
+            Some(0),
+            Some(activity.id),
+            start,
+        ],
+    );
+    writer.write(EVENT, &[start, Some(EVENT_TIMER), Some(EVENT_TYPE_START)]);
+
+    for (time, record) in &records {
+        writer.write(
+            RECORD,
+            &[
+                Some(fit_time(*time)),
+                semicircles(record.position_lat),
+                semicircles(record.position_long),
+                scaled(record.altitude, 5.0, 500.0),
+                record.heart_rate.map(i64::from),
+                scaled(record.distance, 100.0, 0.0),
+                scaled(record.speed, 1000.0, 0.0),
+                record.temperature.map(|t| t.round() as i64),
+            ],
+        );
+    }
+
+    writer.write(EVENT, &[end, Some(EVENT_TIMER), Some(EVENT_TYPE_STOP_ALL)]);
+    writer.write(
+        LAP,
+        &[
+            Some(0),
+            end,
+            Some(EVENT_LAP),
+            Some(EVENT_TYPE_STOP),
+            start,
+            semicircles(start_fix.map(|f| f.0)),
+            semicircles(start_fix.map(|f| f.1)),
+            semicircles(end_fix.map(|f| f.0)),
+            semicircles(end_fix.map(|f| f.1)),
+            scaled(elapsed_time, 1000.0, 0.0),
+            scaled(timer_time, 1000.0, 0.0),
+            scaled(activity.total_distance, 100.0, 0.0),
+            avg_heart_rate,
+            max_heart_rate,
+            scaled(activity.elevation_gain, 1.0, 0.0),
+            scaled(activity.elevation_loss, 1.0, 0.0),
+            sport,
+            sub_sport,
+        ],
+    );
+    writer.write(
+        SESSION,
+        &[
+            Some(0),
+            end,
+            Some(EVENT_SESSION),
+            Some(EVENT_TYPE_STOP),
+            start,
+            semicircles(start_fix.map(|f| f.0)),
+            semicircles(start_fix.map(|f| f.1)),
+            sport,
+            sub_sport,
+            scaled(elapsed_time, 1000.0, 0.0),
+            scaled(timer_time, 1000.0, 0.0),
+            scaled(activity.total_distance, 100.0, 0.0),
+            avg_heart_rate,
+            max_heart_rate,
+            scaled(activity.elevation_gain, 1.0, 0.0),
+            scaled(activity.elevation_loss, 1.0, 0.0),
+            Some(0),
+            Some(1),
+        ],
+    );
+    writer.write(
+        ACTIVITY,
+        &[
+            end,
+            scaled(timer_time, 1000.0, 0.0),
+            Some(1),
+            Some(0),
+            Some(EVENT_ACTIVITY),
+            Some(EVENT_TYPE_STOP),
+        ],
+    );
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_fit_file, ParseOptions, ParsedActivity};
+    use crate::types::Activity;
+    use reverse_geocoder::ReverseGeocoder;
+
+    #[test]
+    fn test_crc() {
+        // Check value of the CRC-16/ARC parameters the FIT CRC uses
+        assert_eq!(fit_crc(0, b"123456789"), 0xBB3D);
+        let file = encode_activity(&stored(&[]));
+        assert_eq!(
+            fit_crc(0, &file),
+            0,
+            "a file followed by its CRC checks to zero"
+        );
+        assert_eq!(fit_crc(0, &file[..14]), 0);
+    }
+
+    fn activity(id: i64, activity_type: &str, total_duration: f64) -> Activity {
+        Activity {
+            id,
+            filename: "export.fit".to_string(),
+            activity_type: activity_type.to_string(),
+            total_duration,
+            ..Activity::default()
+        }
+    }
+
+    fn stored(records: &[TrackRecord]) -> ActivityDetail {
+        ActivityDetail {
+            activity: activity(1, "Hike", 0.0),
+            records: records.to_vec(),
+        }
+    }
+
+    /// A climbing walk recorded every 5 seconds, with a two minute pause after 100 records
+    fn device_records() -> Vec<TrackRecord> {
+        (0..200)
+            .map(|i| {
+                let seconds = i * 5 + if i >= 100 { 120 } else { 0 };
+                TrackRecord {
+                    timestamp: format!(
+                        "2024-07-14T06:{:02}:{:02}+00:00",
+                        seconds / 60,
+                        seconds % 60
+                    ),
+                    elapsed_time: seconds as f64,
+                    heart_rate: Some(110 + i % 40),
+                    distance: Some(i as f64 * 6.25),
+                    altitude: Some(1500.0 + i as f64 * 0.6),
+                    speed: Some(1.25),
+                    temperature: Some(14.0),
+                    position_lat: Some(46.0 + i as f64 * 0.00005),
+                    position_long: Some(7.7 + i as f64 * 0.00002),
+                    ..TrackRecord::default()
+                }
+            })
+            .collect()
+    }
+
+    /// The stored form of a parsed activity, as `get_activity` would return it
+    fn as_stored(parsed: &ParsedActivity) -> ActivityDetail {
+        let mut activity = activity(7, &parsed.activity_type, parsed.total_duration);
+        activity.total_distance = Some(parsed.total_distance);
+        activity.elevation_gain = Some(parsed.elevation_gain);
+        activity.elevation_loss = Some(parsed.elevation_loss);
+        let records = parsed
+            .records
+            .iter()
+            .map(|r| TrackRecord {
+                timestamp: r.timestamp.to_rfc3339(),
+                elapsed_time: r.elapsed_time,
+                heart_rate: r.heart_rate.map(i32::from),
+                distance: r.distance,
+                altitude: r.altitude,
+                speed: r.speed,
+                temperature: r.temperature.map(f64::from),
+                position_lat: r.position_lat,
+                position_long: r.position_long,
+                clean_position_lat: r.clean_position_lat,
+                clean_position_long: r.clean_position_long,
+                zone: r.zone.clone(),
+                grade_adjusted_speed: r.grade_adjusted_speed,
+                corrected_altitude: None,
+                extras: r.extras.clone(),
+            })
+            .collect();
+        ActivityDetail { activity, records }
+    }
+
+    fn import(bytes: &[u8], name: &str, geocoder: &ReverseGeocoder) -> ParsedActivity {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        let parsed =
+            parse_fit_file(&path, &ParseOptions::default(), geocoder).expect("export should parse");
+        let _ = std::fs::remove_file(&path);
+        parsed
+    }
+
+    #[test]
+    fn test_round_trip_totals() {
+        let geocoder = ReverseGeocoder::new();
+        // Import a device-style file, then export the stored activity and import it again
+        let original = import(
+            &encode_activity(&stored(&device_records())),
+            "fit_writer_device.fit",
+            &geocoder,
+        );
+        let exported = encode_activity(&as_stored(&original));
+        let reimported = import(&exported, "fit_writer_export.fit", &geocoder);
+
+        assert_eq!(reimported.activity_type, "Hike");
+        assert_eq!(reimported.records.len(), original.records.len());
+        assert_eq!(reimported.start_time, original.start_time);
+        assert_eq!(reimported.total_duration, original.total_duration);
+        assert!((reimported.total_distance - original.total_distance).abs() < 1e-6);
+        assert!((reimported.elevation_gain - original.elevation_gain).abs() < 1e-6);
+        assert!((reimported.elevation_loss - original.elevation_loss).abs() < 1e-6);
+        assert_eq!(reimported.max_altitude, original.max_altitude);
+        assert_eq!(reimported.zones.zone1, original.zones.zone1);
+        assert_eq!(reimported.zones.zone2, original.zones.zone2);
+        assert_eq!(reimported.zones.zone3, original.zones.zone3);
+        assert!(original.elevation_gain > 100.0);
+        // The pause is capped, as on the original import
+        assert_eq!(original.total_duration, 198.0 * 5.0 + 10.0);
+    }
+}
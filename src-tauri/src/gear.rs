@@ -0,0 +1,117 @@
+use chrono::NaiveDate;
+
+use crate::error::AppError;
+use crate::types::{GearAlert, GearDetails};
+
+fn parse_date(label: &str, value: &Option<String>) -> Result<Option<NaiveDate>, AppError> {
+    value
+        .as_deref()
+        .map(|v| {
+            NaiveDate::parse_from_str(v, "%Y-%m-%d")
+                .map_err(|_| AppError::InvalidArgument(format!("Invalid {} date: {}", label, v)))
+        })
+        .transpose()
+}
+
+/// Check a gear item's name, dates and limits before saving it
+pub fn validate_gear(gear: &GearDetails) -> Result<(), AppError> {
+    if gear.name.trim().is_empty() || gear.gear_type.trim().is_empty() {
+        return Err(AppError::InvalidArgument(
+            "Gear name and type are required".to_string(),
+        ));
+    }
+
+    let purchased = parse_date("purchase", &gear.purchase_date)?;
+    let retired = parse_date("retirement", &gear.retired_date)?;
+    if let (Some(purchased), Some(retired)) = (purchased, retired) {
+        if retired < purchased {
+            return Err(AppError::InvalidArgument(
+                "Gear cannot be retired before it was bought".to_string(),
+            ));
+        }
+    }
+
+    let limits = [gear.distance_limit, gear.elevation_limit, gear.hours_limit];
+    if limits.iter().flatten().any(|limit| *limit <= 0.0) {
+        return Err(AppError::InvalidArgument(
+            "Gear limits must be positive".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Limits a gear item has reached, given its cumulative distance (m),
+/// elevation gain (m) and hours of use
+pub fn limit_alerts(
+    gear: &GearDetails,
+    distance: f64,
+    elevation_gain: f64,
+    hours: f64,
+) -> Vec<GearAlert> {
+    [
+        ("distance", gear.distance_limit, distance),
+        ("elevation", gear.elevation_limit, elevation_gain),
+        ("hours", gear.hours_limit, hours),
+    ]
+    .into_iter()
+    .filter_map(|(metric, limit, value)| {
+        limit
+            .filter(|limit| value >= *limit)
+            .map(|limit| GearAlert {
+                metric: metric.to_string(),
+                limit,
+                value,
+            })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boots() -> GearDetails {
+        GearDetails {
+            name: "Trail boots".to_string(),
+            gear_type: "boots".to_string(),
+            brand: None,
+            model: None,
+            purchase_date: Some("2023-04-01".to_string()),
+            retired_date: None,
+            distance_limit: Some(1_000_000.0),
+            elevation_limit: None,
+            hours_limit: Some(300.0),
+            notes: None,
+            default_types: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate_gear() {
+        assert!(validate_gear(&boots()).is_ok());
+
+        let mut gear = boots();
+        gear.retired_date = Some("2022-12-31".to_string());
+        assert!(validate_gear(&gear).is_err());
+
+        let mut gear = boots();
+        gear.purchase_date = Some("01/04/2023".to_string());
+        assert!(validate_gear(&gear).is_err());
+
+        let mut gear = boots();
+        gear.hours_limit = Some(0.0);
+        assert!(validate_gear(&gear).is_err());
+    }
+
+    #[test]
+    fn test_limit_alerts() {
+        assert!(limit_alerts(&boots(), 500_000.0, 40_000.0, 120.0).is_empty());
+
+        let alerts = limit_alerts(&boots(), 1_200_000.0, 90_000.0, 120.0);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].metric, "distance");
+        assert_eq!(alerts[0].limit, 1_000_000.0);
+        assert_eq!(alerts[0].value, 1_200_000.0);
+    }
+}
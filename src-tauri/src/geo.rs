@@ -0,0 +1,154 @@
+/// Mean Earth radius in metres
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Great-circle distance in metres between two positions in degrees
+pub fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = (lat2 - lat1).to_radians();
+    let dlambda = (lon2 - lon1).to_radians();
+
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Equirectangular projection around an origin, accurate to well under a
+/// metre over the extent of a single activity
+#[derive(Debug, Clone, Copy)]
+pub struct LocalProjection {
+    origin_lat: f64,
+    origin_lon: f64,
+    cos_lat: f64,
+}
+
+impl LocalProjection {
+    pub fn new(origin_lat: f64, origin_lon: f64) -> Self {
+        Self {
+            origin_lat,
+            origin_lon,
+            cos_lat: origin_lat.to_radians().cos(),
+        }
+    }
+
+    /// Position in degrees to (east, north) metres from the origin
+    pub fn project(&self, lat: f64, lon: f64) -> (f64, f64) {
+        (
+            (lon - self.origin_lon).to_radians() * EARTH_RADIUS * self.cos_lat,
+            (lat - self.origin_lat).to_radians() * EARTH_RADIUS,
+        )
+    }
+
+    /// (east, north) metres from the origin back to degrees
+    pub fn unproject(&self, x: f64, y: f64) -> (f64, f64) {
+        (
+            self.origin_lat + (y / EARTH_RADIUS).to_degrees(),
+            self.origin_lon + (x / (EARTH_RADIUS * self.cos_lat)).to_degrees(),
+        )
+    }
+}
+
+/// Latitude/longitude bounding box in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub max_lat: f64,
+    pub min_lon: f64,
+    pub max_lon: f64,
+}
+
+impl BoundingBox {
+    /// Smallest box containing all points, or `None` for an empty track
+    pub fn from_points(points: &[(f64, f64)]) -> Option<Self> {
+        let (&(lat, lon), rest) = points.split_first()?;
+        Some(rest.iter().fold(
+            Self {
+                min_lat: lat,
+                max_lat: lat,
+                min_lon: lon,
+                max_lon: lon,
+            },
+            |b, &(lat, lon)| Self {
+                min_lat: b.min_lat.min(lat),
+                max_lat: b.max_lat.max(lat),
+                min_lon: b.min_lon.min(lon),
+                max_lon: b.max_lon.max(lon),
+            },
+        ))
+    }
+
+    /// Box around a point extended by `radius` metres in every direction
+    pub fn around(lat: f64, lon: f64, radius: f64) -> Self {
+        Self {
+            min_lat: lat,
+            max_lat: lat,
+            min_lon: lon,
+            max_lon: lon,
+        }
+        .expand(radius)
+    }
+
+    /// This box extended by `radius` metres in every direction
+    pub fn expand(&self, radius: f64) -> Self {
+        let dlat = (radius / EARTH_RADIUS).to_degrees();
+        // Degrees of longitude are shortest at the edge furthest from the equator
+        let widest_lat = self.min_lat.abs().max(self.max_lat.abs());
+        let dlon = dlat / widest_lat.to_radians().cos().max(1e-6);
+        Self {
+            min_lat: self.min_lat - dlat,
+            max_lat: self.max_lat + dlat,
+            min_lon: self.min_lon - dlon,
+            max_lon: self.max_lon + dlon,
+        }
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        lat >= self.min_lat && lat <= self.max_lat && lon >= self.min_lon && lon <= self.max_lon
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine_one_degree_latitude() {
+        let d = haversine(46.0, 7.0, 47.0, 7.0);
+        assert!((d - 111_195.0).abs() < 10.0);
+    }
+
+    #[test]
+    fn test_haversine_zero() {
+        assert_eq!(haversine(46.5, 7.5, 46.5, 7.5), 0.0);
+    }
+
+    #[test]
+    fn test_local_projection_round_trip() {
+        let proj = LocalProjection::new(46.5, 7.5);
+        let (x, y) = proj.project(46.51, 7.52);
+        let (lat, lon) = proj.unproject(x, y);
+        assert!((lat - 46.51).abs() < 1e-9);
+        assert!((lon - 7.52).abs() < 1e-9);
+        // Matches the great-circle distance closely at this scale
+        let d = haversine(46.5, 7.5, 46.51, 7.52);
+        assert!(((x * x + y * y).sqrt() - d).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let b = BoundingBox::from_points(&[(46.0, 7.0), (46.2, 6.9), (45.9, 7.1)]).unwrap();
+        assert_eq!(
+            (b.min_lat, b.max_lat, b.min_lon, b.max_lon),
+            (45.9, 46.2, 6.9, 7.1)
+        );
+        assert!(b.contains(46.1, 7.0));
+        assert!(!b.contains(46.3, 7.0));
+        assert!(BoundingBox::from_points(&[]).is_none());
+
+        let around = BoundingBox::around(46.0, 7.0, 1000.0);
+        assert!(around.contains(46.008, 7.0));
+        assert!(!around.contains(46.01, 7.0));
+
+        let expanded = b.expand(1000.0);
+        assert!(expanded.contains(46.208, 7.0));
+        assert!(!expanded.contains(46.21, 7.0));
+    }
+}
@@ -0,0 +1,70 @@
+use reverse_geocoder::ReverseGeocoder;
+
+use crate::geo::haversine;
+use crate::track::Fix;
+
+/// Straight-line distance (m) between start and end above which an activity
+/// counts as a point-to-point traverse rather than a loop or out-and-back
+pub const TRAVERSE_DISTANCE: f64 = 1000.0;
+
+/// Places along an activity, resolved with the offline geocoder
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActivityPlaces {
+    pub start_location: Option<String>,
+    pub end_location: Option<String>,
+    pub high_point_location: Option<String>,
+    /// First-level administrative region of the start (e.g. canton or state)
+    pub admin1: Option<String>,
+    /// Second-level administrative region of the start (e.g. district or county)
+    pub admin2: Option<String>,
+    pub country_code: Option<String>,
+    /// Straight-line distance between the first and last fix (m)
+    pub start_end_distance: Option<f64>,
+}
+
+/// "town, country" label of the nearest populated place
+pub fn place_label(geocoder: &ReverseGeocoder, lat: f64, lon: f64) -> String {
+    let record = geocoder.search((lat, lon)).record;
+    format!("{}, {}", record.name, record.cc)
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Resolve the start, end and highest point of a track. `altitudes` is
+/// aligned with `fixes`; the high point is the highest altitude with a fix.
+pub fn activity_places(
+    geocoder: &ReverseGeocoder,
+    fixes: &[Fix],
+    altitudes: &[Option<f64>],
+) -> ActivityPlaces {
+    let Some((start_lat, start_lon)) = fixes.iter().flatten().next().copied() else {
+        return ActivityPlaces::default();
+    };
+    let (end_lat, end_lon) = fixes
+        .iter()
+        .flatten()
+        .next_back()
+        .copied()
+        .unwrap_or((start_lat, start_lon));
+
+    let high_point = fixes
+        .iter()
+        .zip(altitudes)
+        .filter_map(|(fix, alt)| fix.zip(*alt))
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(pos, _)| pos);
+
+    let start = geocoder.search((start_lat, start_lon)).record;
+
+    ActivityPlaces {
+        start_location: Some(format!("{}, {}", start.name, start.cc)),
+        end_location: Some(place_label(geocoder, end_lat, end_lon)),
+        high_point_location: high_point.map(|(lat, lon)| place_label(geocoder, lat, lon)),
+        admin1: non_empty(&start.admin1),
+        admin2: non_empty(&start.admin2),
+        country_code: non_empty(&start.cc),
+        start_end_distance: Some(haversine(start_lat, start_lon, end_lat, end_lon)),
+    }
+}
@@ -1,23 +1,117 @@
+mod analytics;
+mod backup;
 mod db;
+mod dem;
+mod effort;
+mod elevation;
 mod error;
+mod export;
+mod fit_writer;
+mod gear;
+mod geo;
+mod geocode;
+mod merge;
+mod metadata;
 mod parser;
+mod pois;
+mod polyline;
+mod profiles;
+mod record_store;
 mod repo;
+mod routes;
+mod segments;
+mod sources;
+mod spatial;
+mod splits;
+mod sports;
+mod streams;
 mod tiles;
+mod track;
+mod trim;
+mod trips;
 mod types;
 mod zones;
 
-use crate::db::init_db;
+use crate::analytics::{
+    record_row, validate_filter, ColumnKind, TableFormat, TableWriter, ACTIVITY_EXPORT_COLUMNS,
+    RECORD_EXPORT_COLUMNS,
+};
+use crate::backup::{
+    backup_before_restore, backup_database as create_backup, backup_due, latest_backup,
+    list_backups as backups_in, prune_backups, replace_database, stage_restore, validate_schedule,
+    BackupSchedule,
+};
+use crate::db::{init_db, prepare_db};
+use crate::dem::DemStore;
+use crate::elevation::ElevationFilter;
 use crate::error::AppError;
-use crate::parser::parse_fit_file;
+use crate::export::{render_activities, ExportFormat};
+use crate::gear::validate_gear;
+use crate::geo::BoundingBox;
+use crate::merge::merge_records;
+use crate::parser::{build_activity, parse_fit_bytes, parse_fit_file};
+use crate::pois::{parse_poi_csv, PoiKind};
+use crate::polyline::encode;
+use crate::profiles::validate_profile;
 use crate::repo::{
-    delete_activity as repo_delete_activity, get_activity as repo_get_activity,
-    get_monthly_summary as repo_get_monthly_summary, get_weekly_summary as repo_get_weekly_summary,
+    analytics_activity_ids as repo_analytics_activity_ids, apply_elevation_source,
+    backfill_corrected_totals, backfill_heart_rates, backfill_places, backfill_routes,
+    backfill_spatial_index, backfill_titles, check_activity_profile, check_activity_unchanged,
+    create_segment as repo_create_segment, delete_activity as repo_delete_activity,
+    delete_activity_type as repo_delete_activity_type, delete_gear as repo_delete_gear,
+    delete_profile as repo_delete_profile, delete_segment as repo_delete_segment,
+    delete_trip as repo_delete_trip, export_activity_rows as repo_export_activity_rows,
+    find_activities_in_bbox as repo_find_activities_in_bbox,
+    find_activities_near as repo_find_activities_near,
+    get_active_profile as repo_get_active_profile, get_activity as repo_get_activity,
+    get_activity_gear as repo_get_activity_gear, get_activity_pois as repo_get_activity_pois,
+    get_activity_summary, get_activity_track, get_backup_schedule as repo_get_backup_schedule,
+    get_elevation_filter as repo_get_elevation_filter,
+    get_monthly_summary as repo_get_monthly_summary, get_parse_options,
+    get_poi_visits as repo_get_poi_visits, get_profile as repo_get_profile, get_records,
+    get_route_attempts as repo_get_route_attempts,
+    get_segment_leaderboard as repo_get_segment_leaderboard,
+    get_source_file as repo_get_source_file, get_track_cleaning as repo_get_track_cleaning,
+    get_track_section, get_trip as repo_get_trip, get_trip_track as repo_get_trip_track,
+    get_weekly_summary as repo_get_weekly_summary, import_pois as repo_import_pois,
     insert_activity, list_activities as repo_list_activities,
+    list_activity_types as repo_list_activity_types, list_bagged_pois as repo_list_bagged_pois,
+    list_gear as repo_list_gear, list_profiles as repo_list_profiles,
+    list_regions as repo_list_regions, list_routes as repo_list_routes,
+    list_segments as repo_list_segments, list_tags as repo_list_tags,
+    list_trip_suggestions as repo_list_trip_suggestions, list_trips as repo_list_trips,
+    recalculate_elevation as repo_recalculate_elevation,
+    record_extra_keys as repo_record_extra_keys, rename_route as repo_rename_route,
+    reparseable_activities as repo_reparseable_activities,
+    replace_parsed_activity as repo_replace_parsed_activity,
+    save_activity_type as repo_save_activity_type, save_corrected_elevation,
+    save_edited_activity as repo_save_edited_activity, save_gear as repo_save_gear,
+    save_merged_activity as repo_save_merged_activity, save_profile as repo_save_profile,
+    save_trip as repo_save_trip, set_active_profile as repo_set_active_profile,
+    set_activity_gear as repo_set_activity_gear, set_backup_schedule as repo_set_backup_schedule,
+    set_elevation_filter as repo_set_elevation_filter,
+    set_elevation_source as repo_set_elevation_source,
+    set_track_cleaning as repo_set_track_cleaning,
+    update_activity_metadata as repo_update_activity_metadata,
+    update_activity_type as repo_update_activity_type,
 };
+use crate::splits::{compute_splits, SplitUnit};
+use crate::streams::{activity_streams, StreamAxis, DEFAULT_MAX_POINTS};
 use crate::tiles::TileServer;
-use crate::types::{Activity, ActivityDetail, ZoneSummary};
+use crate::track::TrackCleaning;
+use crate::trim::{part_filename, raw_records, split_ranges, trim_range};
+use crate::types::{
+    Activity, ActivityDetail, ActivityFilter, ActivityMetadata, ActivityStreams, ActivityTrack,
+    ActivityTypeInfo, AnalyticsExport, AnalyticsFilter, BackupInfo, BaggedPoi, Gear, GearDetails,
+    GearUsage, PoiVisit, Profile, ProfileDetails, Region, ReparseFailure, ReparseSummary, Route,
+    RouteAttempt, Segment, SegmentEffort, Split, Tag, TrackRecord, Trip, TripDetail,
+    TripSuggestion, TripTrack, ZoneSummary,
+};
+use chrono::Utc;
+use reverse_geocoder::ReverseGeocoder;
 use rusqlite::Connection;
 use serde::Serialize;
+use std::ops::Range;
 use std::path::PathBuf;
 use std::sync::Mutex;
 use tauri::{Emitter, Manager, State};
@@ -26,58 +120,1096 @@ use tauri::{Emitter, Manager, State};
 pub struct AppState {
     db: Mutex<Connection>,
     tiles: Mutex<TileServer>,
+    dem: Mutex<DemStore>,
+    /// Offline geocoder, built once since loading its dataset is slow
+    geocoder: ReverseGeocoder,
+    /// Profile whose activities, gear and statistics commands work on
+    profile: Mutex<i64>,
+    /// File the database connection is opened on
+    db_path: PathBuf,
+    /// Directory automatic and manual backups are written to
+    backup_dir: PathBuf,
+    /// Error of the last scheduled backup, cleared when one succeeds
+    backup_error: Mutex<Option<String>>,
+}
+
+/// Id of the active profile. Read before locking the database so the two
+/// locks are never held in the opposite order.
+fn active_profile(state: &AppState) -> i64 {
+    *state.profile.lock().unwrap()
 }
 
 #[tauri::command]
 fn import_fit_file(path: String, state: State<AppState>) -> Result<Activity, AppError> {
-    let parsed = parse_fit_file(&PathBuf::from(&path))?;
+    let profile_id = active_profile(&state);
+    let options = get_parse_options(&state.db.lock().unwrap(), profile_id)?;
+    let parsed = parse_fit_file(&PathBuf::from(&path), &options, &state.geocoder)?;
+    let conn = state.db.lock().unwrap();
+    let id = insert_activity(&conn, profile_id, &parsed)?;
+
+    get_activity_summary(&conn, profile_id, id)
+}
+
+#[tauri::command]
+fn list_activities(
+    filter: Option<ActivityFilter>,
+    state: State<AppState>,
+) -> Result<Vec<Activity>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_activities(&conn, profile_id, &filter.unwrap_or_default())
+}
+
+/// Edit an activity's title, description, notes and tags, returning the updated activity
+#[tauri::command]
+fn update_activity_metadata(
+    id: i64,
+    metadata: ActivityMetadata,
+    state: State<AppState>,
+) -> Result<Activity, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_update_activity_metadata(&conn, profile_id, id, &metadata)?;
+    get_activity_summary(&conn, profile_id, id)
+}
+
+#[tauri::command]
+fn list_tags(state: State<AppState>) -> Result<Vec<Tag>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_tags(&conn, profile_id)
+}
+
+#[tauri::command]
+fn list_activity_types(state: State<AppState>) -> Result<Vec<ActivityTypeInfo>, AppError> {
+    let profile_id = active_profile(&state);
     let conn = state.db.lock().unwrap();
-    let id = insert_activity(&conn, &parsed)?;
+    repo_list_activity_types(&conn, profile_id)
+}
+
+/// Create a custom activity type or restyle an existing one
+#[tauri::command]
+fn save_activity_type(
+    name: String,
+    icon: String,
+    color: String,
+    state: State<AppState>,
+) -> Result<(), AppError> {
+    let name = name.trim();
+    if name.is_empty() || icon.trim().is_empty() {
+        return Err(AppError::InvalidArgument(
+            "Activity type name and icon are required".to_string(),
+        ));
+    }
+    let valid_color = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid_color {
+        return Err(AppError::InvalidArgument(format!(
+            "Invalid colour: {}",
+            color
+        )));
+    }
+    let conn = state.db.lock().unwrap();
+    repo_save_activity_type(&conn, name, icon.trim(), &color)
+}
+
+#[tauri::command]
+fn delete_activity_type(name: String, state: State<AppState>) -> Result<(), AppError> {
+    let conn = state.db.lock().unwrap();
+    repo_delete_activity_type(&conn, &name)
+}
+
+/// Correct an activity's type, returning the updated activity
+#[tauri::command]
+fn update_activity_type(
+    id: i64,
+    activity_type: String,
+    state: State<AppState>,
+) -> Result<Activity, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_update_activity_type(&conn, profile_id, id, &activity_type)?;
+    get_activity_summary(&conn, profile_id, id)
+}
+
+#[tauri::command]
+fn list_profiles(state: State<AppState>) -> Result<Vec<Profile>, AppError> {
+    let conn = state.db.lock().unwrap();
+    repo_list_profiles(&conn)
+}
 
-    Ok(Activity {
+#[tauri::command]
+fn get_active_profile(state: State<AppState>) -> Result<Profile, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_get_profile(&conn, profile_id)
+}
+
+/// Create a profile, or update it when `id` is given. Returns the profile id.
+/// Zone model changes apply to activities imported afterwards.
+#[tauri::command]
+fn save_profile(
+    id: Option<i64>,
+    profile: ProfileDetails,
+    state: State<AppState>,
+) -> Result<i64, AppError> {
+    validate_profile(&profile)?;
+    let conn = state.db.lock().unwrap();
+    repo_save_profile(&conn, id, &profile)
+}
+
+#[tauri::command]
+fn delete_profile(id: i64, state: State<AppState>) -> Result<(), AppError> {
+    if id == active_profile(&state) {
+        return Err(AppError::InvalidArgument(
+            "Switch to another profile before deleting this one".to_string(),
+        ));
+    }
+    let conn = state.db.lock().unwrap();
+    repo_delete_profile(&conn, id)
+}
+
+/// Make a profile the one all other commands work on
+#[tauri::command]
+fn switch_profile(id: i64, state: State<AppState>) -> Result<Profile, AppError> {
+    let profile = repo_set_active_profile(&state.db.lock().unwrap(), id)?;
+    *state.profile.lock().unwrap() = id;
+    Ok(profile)
+}
+
+/// All gear with cumulative use and any limits reached
+#[tauri::command]
+fn list_gear(state: State<AppState>) -> Result<Vec<GearUsage>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_gear(&conn, profile_id)
+}
+
+/// Create a gear item, or update it when `id` is given. Returns the gear id.
+#[tauri::command]
+fn save_gear(id: Option<i64>, gear: GearDetails, state: State<AppState>) -> Result<i64, AppError> {
+    validate_gear(&gear)?;
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_save_gear(&conn, profile_id, id, &gear)
+}
+
+#[tauri::command]
+fn delete_gear(id: i64, state: State<AppState>) -> Result<(), AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_delete_gear(&conn, profile_id, id)
+}
+
+#[tauri::command]
+fn get_activity_gear(activity_id: i64, state: State<AppState>) -> Result<Vec<Gear>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_get_activity_gear(&conn, profile_id, activity_id)
+}
+
+#[tauri::command]
+fn set_activity_gear(
+    activity_id: i64,
+    gear_ids: Vec<i64>,
+    state: State<AppState>,
+) -> Result<(), AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_set_activity_gear(&conn, profile_id, activity_id, &gear_ids)
+}
+
+/// Regions activities start in, for filtering the activity list
+#[tauri::command]
+fn list_regions(state: State<AppState>) -> Result<Vec<Region>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_regions(&conn, profile_id)
+}
+
+#[tauri::command]
+fn get_activity(id: i64, state: State<AppState>) -> Result<ActivityDetail, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_get_activity(&conn, profile_id, id)
+}
+
+/// Selected record fields of an activity for charts, each downsampled with
+/// LTTB to at most `max_points` along elapsed time or distance
+#[tauri::command]
+fn get_activity_streams(
+    id: i64,
+    fields: Vec<String>,
+    max_points: Option<usize>,
+    resolution_by: Option<String>,
+    state: State<AppState>,
+) -> Result<ActivityStreams, AppError> {
+    let axis = StreamAxis::parse(resolution_by.as_deref().unwrap_or("time"))?;
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    let activity = get_activity_summary(&conn, profile_id, id)?;
+    let mut records = get_records(&conn, id)?;
+    drop(conn);
+    apply_elevation_source(&mut records, &activity.elevation_source);
+    activity_streams(
         id,
-        filename: parsed.filename,
-        activity_type: parsed.activity_type,
-        activity_date: parsed.activity_date.to_string(),
-        start_time: parsed.start_time,
-        location: parsed.location,
-        total_duration: parsed.total_duration,
-        zones: parsed.zones,
-        elevation_gain: Some(parsed.elevation_gain),
-        max_altitude: parsed.max_altitude,
-        min_altitude: parsed.min_altitude,
-        total_distance: Some(parsed.total_distance),
+        &records,
+        &fields,
+        max_points.unwrap_or(DEFAULT_MAX_POINTS),
+        axis,
+    )
+}
+
+#[tauri::command]
+fn get_activity_splits(
+    id: i64,
+    unit: String,
+    state: State<AppState>,
+) -> Result<Vec<Split>, AppError> {
+    let unit = SplitUnit::parse(&unit)?;
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    let activity = get_activity_summary(&conn, profile_id, id)?;
+    let mut records = get_records(&conn, id)?;
+    apply_elevation_source(&mut records, &activity.elevation_source);
+    let filter = repo_get_elevation_filter(&conn)?;
+    Ok(compute_splits(&records, unit, &filter))
+}
+
+/// Export activities to a GPX, TCX, GeoJSON or FIT file: the given activities,
+/// or the active profile's activities matching `filter`. A FIT file takes a
+/// single activity. Returns the number written.
+#[tauri::command]
+fn export_activities(
+    ids: Option<Vec<i64>>,
+    filter: Option<ActivityFilter>,
+    format: String,
+    path: String,
+    state: State<AppState>,
+) -> Result<usize, AppError> {
+    let format = ExportFormat::parse(&format)?;
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    let ids = match ids {
+        Some(ids) => ids,
+        None => repo_list_activities(&conn, profile_id, &filter.unwrap_or_default())?
+            .into_iter()
+            .map(|a| a.id)
+            .collect(),
+    };
+    if ids.is_empty() {
+        return Err(AppError::InvalidArgument(
+            "No activities to export".to_string(),
+        ));
+    }
+
+    let activities = ids
+        .iter()
+        .map(|&id| {
+            let mut detail = repo_get_activity(&conn, profile_id, id)?;
+            apply_elevation_source(&mut detail.records, &detail.activity.elevation_source);
+            Ok(detail)
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+    drop(conn);
+
+    std::fs::write(&path, render_activities(format, &activities)?)?;
+    Ok(activities.len())
+}
+
+/// Export the active profile's activities and their track records for analysis,
+/// as `activities` and `records` CSV or Parquet files in `directory`. Records
+/// are read an activity at a time, so exports of any size run in bounded
+/// memory and other commands can use the database in between.
+#[tauri::command]
+fn export_analytics(
+    filter: Option<AnalyticsFilter>,
+    format: String,
+    directory: String,
+    state: State<AppState>,
+) -> Result<AnalyticsExport, AppError> {
+    let format = TableFormat::parse(&format)?;
+    let filter = filter.unwrap_or_default();
+    validate_filter(&filter)?;
+    std::fs::create_dir_all(&directory)?;
+    let directory = std::path::Path::new(&directory);
+    let activities_path = directory.join(format!("activities.{}", format.extension()));
+    let records_path = directory.join(format!("records.{}", format.extension()));
+
+    let profile_id = active_profile(&state);
+    let (activity_rows, ids, extra_keys) = {
+        let conn = state.db.lock().unwrap();
+        let mut rows = Vec::new();
+        repo_export_activity_rows(&conn, profile_id, &filter, |row| {
+            rows.push(row.to_vec());
+            Ok(())
+        })?;
+        let ids = repo_analytics_activity_ids(&conn, profile_id, &filter)?;
+        let extra_keys = repo_record_extra_keys(&conn, profile_id, &filter)?;
+        (rows, ids, extra_keys)
+    };
+
+    let columns: Vec<_> = ACTIVITY_EXPORT_COLUMNS
+        .iter()
+        .map(|(name, kind)| (name.to_string(), *kind))
+        .collect();
+    let mut writer = TableWriter::create(&activities_path, format, &columns)?;
+    for row in &activity_rows {
+        writer.write_row(row)?;
+    }
+    writer.finish()?;
+
+    let columns: Vec<_> = RECORD_EXPORT_COLUMNS
+        .iter()
+        .map(|(name, kind)| (name.to_string(), *kind))
+        .chain(
+            extra_keys
+                .iter()
+                .map(|key| (key.clone(), ColumnKind::Float)),
+        )
+        .collect();
+    let mut writer = TableWriter::create(&records_path, format, &columns)?;
+    let mut record_count = 0;
+    for id in ids {
+        let records = get_records(&state.db.lock().unwrap(), id)?;
+        record_count += records.len();
+        for record in records {
+            writer.write_row(&record_row(id, record, &extra_keys))?;
+        }
+    }
+    writer.finish()?;
+
+    Ok(AnalyticsExport {
+        activities_path: activities_path.to_string_lossy().to_string(),
+        records_path: records_path.to_string_lossy().to_string(),
+        activity_count: activity_rows.len(),
+        record_count,
     })
 }
 
+/// Default simplification tolerance (m) for map tracks
+const MAP_TRACK_TOLERANCE: f64 = 10.0;
+
+/// Simplified tracks for drawing many activities on the map
+#[tauri::command]
+fn get_activity_tracks(
+    ids: Vec<i64>,
+    tolerance: Option<f64>,
+    encoded: Option<bool>,
+    state: State<AppState>,
+) -> Result<Vec<ActivityTrack>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    let tolerance = tolerance.unwrap_or(MAP_TRACK_TOLERANCE);
+
+    ids.into_iter()
+        .map(|id| {
+            check_activity_profile(&conn, profile_id, id)?;
+            let points = get_activity_track(&conn, id, tolerance)?;
+            Ok(if encoded.unwrap_or(false) {
+                ActivityTrack {
+                    activity_id: id,
+                    points: None,
+                    polyline: Some(encode(&points)),
+                }
+            } else {
+                ActivityTrack {
+                    activity_id: id,
+                    points: Some(points.into_iter().map(|(lat, lon)| [lat, lon]).collect()),
+                    polyline: None,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Activities whose track passes through the given map area
 #[tauri::command]
-fn list_activities(state: State<AppState>) -> Result<Vec<Activity>, AppError> {
+fn find_activities_in_bbox(
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+    state: State<AppState>,
+) -> Result<Vec<Activity>, AppError> {
+    if min_lat > max_lat || min_lon > max_lon {
+        return Err(AppError::InvalidArgument(
+            "Bounding box minimum exceeds maximum".to_string(),
+        ));
+    }
+    let profile_id = active_profile(&state);
     let conn = state.db.lock().unwrap();
-    repo_list_activities(&conn)
+    repo_find_activities_in_bbox(
+        &conn,
+        profile_id,
+        &BoundingBox {
+            min_lat,
+            max_lat,
+            min_lon,
+            max_lon,
+        },
+    )
 }
 
+/// Activities whose track passes within `radius` metres of a position
 #[tauri::command]
-fn get_activity(id: i64, state: State<AppState>) -> Result<ActivityDetail, AppError> {
+fn find_activities_near(
+    lat: f64,
+    lon: f64,
+    radius: f64,
+    state: State<AppState>,
+) -> Result<Vec<Activity>, AppError> {
+    if radius <= 0.0 {
+        return Err(AppError::InvalidArgument(format!(
+            "Invalid radius: {}",
+            radius
+        )));
+    }
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_find_activities_near(&conn, profile_id, lat, lon, radius)
+}
+
+#[tauri::command]
+fn list_routes(state: State<AppState>) -> Result<Vec<Route>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_routes(&conn, profile_id)
+}
+
+#[tauri::command]
+fn rename_route(id: i64, name: String, state: State<AppState>) -> Result<(), AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::InvalidArgument(
+            "Route name cannot be empty".to_string(),
+        ));
+    }
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_rename_route(&conn, profile_id, id, name)
+}
+
+/// All attempts of a route with time and heart rate comparisons
+#[tauri::command]
+fn get_route_attempts(
+    route_id: i64,
+    state: State<AppState>,
+) -> Result<Vec<RouteAttempt>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_get_route_attempts(&conn, profile_id, route_id)
+}
+
+/// Create a trip of activities, or update its name and activities when `id` is given. Returns the trip id.
+#[tauri::command]
+fn save_trip(
+    id: Option<i64>,
+    name: String,
+    activity_ids: Vec<i64>,
+    state: State<AppState>,
+) -> Result<i64, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::InvalidArgument(
+            "Trip name cannot be empty".to_string(),
+        ));
+    }
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_save_trip(&conn, profile_id, id, name, &activity_ids)
+}
+
+#[tauri::command]
+fn delete_trip(id: i64, state: State<AppState>) -> Result<(), AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_delete_trip(&conn, profile_id, id)
+}
+
+/// All trips with distance, gain, time and zone totals
+#[tauri::command]
+fn list_trips(state: State<AppState>) -> Result<Vec<Trip>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_trips(&conn, profile_id)
+}
+
+/// A trip with its totals, day-by-day breakdown and activities
+#[tauri::command]
+fn get_trip(id: i64, state: State<AppState>) -> Result<TripDetail, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_get_trip(&conn, profile_id, id)
+}
+
+/// Combined track of a trip's activities for the map
+#[tauri::command]
+fn get_trip_track(
+    id: i64,
+    tolerance: Option<f64>,
+    encoded: Option<bool>,
+    state: State<AppState>,
+) -> Result<TripTrack, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    let points = repo_get_trip_track(
+        &conn,
+        profile_id,
+        id,
+        tolerance.unwrap_or(MAP_TRACK_TOLERANCE),
+    )?;
+
+    Ok(if encoded.unwrap_or(false) {
+        TripTrack {
+            trip_id: id,
+            points: None,
+            polyline: Some(encode(&points)),
+        }
+    } else {
+        TripTrack {
+            trip_id: id,
+            points: Some(points.into_iter().map(|(lat, lon)| [lat, lon]).collect()),
+            polyline: None,
+        }
+    })
+}
+
+/// Activities not yet in a trip that chain end-to-start on consecutive days
+#[tauri::command]
+fn suggest_trips(state: State<AppState>) -> Result<Vec<TripSuggestion>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_trip_suggestions(&conn, profile_id)
+}
+
+fn segment_name(name: &str) -> Result<&str, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::InvalidArgument(
+            "Segment name cannot be empty".to_string(),
+        ));
+    }
+    Ok(name)
+}
+
+/// Save a drawn segment, given as [lat, lon] pairs from start to end
+#[tauri::command]
+fn create_segment(
+    name: String,
+    points: Vec<[f64; 2]>,
+    state: State<AppState>,
+) -> Result<i64, AppError> {
+    let name = segment_name(&name)?;
+    let points: Vec<(f64, f64)> = points.into_iter().map(|[lat, lon]| (lat, lon)).collect();
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_create_segment(&conn, profile_id, name, &points)
+}
+
+/// Save the section of an activity between two record indices as a segment
+#[tauri::command]
+fn create_segment_from_activity(
+    activity_id: i64,
+    start_index: usize,
+    end_index: usize,
+    name: String,
+    state: State<AppState>,
+) -> Result<i64, AppError> {
+    let name = segment_name(&name)?;
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    let points = get_track_section(&conn, profile_id, activity_id, start_index, end_index)?;
+    repo_create_segment(&conn, profile_id, name, &points)
+}
+
+#[tauri::command]
+fn list_segments(state: State<AppState>) -> Result<Vec<Segment>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_segments(&conn, profile_id)
+}
+
+#[tauri::command]
+fn get_segment_leaderboard(
+    segment_id: i64,
+    state: State<AppState>,
+) -> Result<Vec<SegmentEffort>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_get_segment_leaderboard(&conn, profile_id, segment_id)
+}
+
+#[tauri::command]
+fn delete_segment(id: i64, state: State<AppState>) -> Result<(), AppError> {
+    let profile_id = active_profile(&state);
     let conn = state.db.lock().unwrap();
-    repo_get_activity(&conn, id)
+    repo_delete_segment(&conn, profile_id, id)
+}
+
+/// Import peaks, huts and passes from a CSV file and detect visits in stored
+/// activities. Returns the number of new POIs.
+#[tauri::command]
+fn import_pois(path: String, state: State<AppState>) -> Result<usize, AppError> {
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| AppError::FileNotFound(format!("{}: {}", path, e)))?;
+    let pois = parse_poi_csv(&text)?;
+    let conn = state.db.lock().unwrap();
+    repo_import_pois(&conn, &pois)
+}
+
+#[tauri::command]
+fn get_activity_pois(id: i64, state: State<AppState>) -> Result<Vec<PoiVisit>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_get_activity_pois(&conn, profile_id, id)
+}
+
+/// Visited POIs, optionally only of one kind ("peak", "hut" or "pass")
+#[tauri::command]
+fn list_bagged_pois(
+    kind: Option<String>,
+    state: State<AppState>,
+) -> Result<Vec<BaggedPoi>, AppError> {
+    let kind = kind
+        .map(|k| {
+            PoiKind::parse(&k)
+                .ok_or_else(|| AppError::InvalidArgument(format!("Unknown POI kind: {}", k)))
+        })
+        .transpose()?;
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_list_bagged_pois(&conn, profile_id, kind)
+}
+
+#[tauri::command]
+fn get_poi_visits(poi_id: i64, state: State<AppState>) -> Result<Vec<PoiVisit>, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_get_poi_visits(&conn, profile_id, poi_id)
 }
 
 #[tauri::command]
 fn get_weekly_summary(week_start: String, state: State<AppState>) -> Result<ZoneSummary, AppError> {
+    let profile_id = active_profile(&state);
     let conn = state.db.lock().unwrap();
-    repo_get_weekly_summary(&conn, &week_start)
+    repo_get_weekly_summary(&conn, profile_id, &week_start)
 }
 
 #[tauri::command]
 fn get_monthly_summary(month_start: String, state: State<AppState>) -> Result<ZoneSummary, AppError> {
+    let profile_id = active_profile(&state);
     let conn = state.db.lock().unwrap();
-    repo_get_monthly_summary(&conn, &month_start)
+    repo_get_monthly_summary(&conn, profile_id, &month_start)
 }
 
 #[tauri::command]
 fn delete_activity(id: i64, state: State<AppState>) -> Result<(), AppError> {
+    let profile_id = active_profile(&state);
     let conn = state.db.lock().unwrap();
-    repo_delete_activity(&conn, id)
+    repo_delete_activity(&conn, profile_id, id)
+}
+
+/// Parse an activity's stored original file again with the current parser and
+/// the settings of its profile, replacing its records and derived values. A DEM
+/// elevation correction is carried over, or sampled again if the new parse has
+/// different records. Returns false if the correction couldn't be re-applied
+/// because no DEM tiles cover the track any more.
+fn reparse(state: &AppState, profile_id: i64, id: i64) -> Result<bool, AppError> {
+    let (activity, records, source, options) = {
+        let conn = state.db.lock().unwrap();
+        let activity = get_activity_summary(&conn, profile_id, id)?;
+        let records = get_records(&conn, id)?;
+        let source = repo_get_source_file(&conn, profile_id, id)?
+            .ok_or_else(|| AppError::NotFound(format!("Original file of {}", activity.filename)))?;
+        let options = get_parse_options(&conn, profile_id)?;
+        (activity, records, source, options)
+    };
+
+    // Parse without holding the database lock
+    let parsed = parse_fit_bytes(&activity.filename, source, &options, &state.geocoder)?;
+    let conn = state.db.lock().unwrap();
+    check_activity_unchanged(&conn, profile_id, id, &activity.start_time, &records)?;
+    repo_replace_parsed_activity(&conn, id, &parsed)?;
+
+    if records.iter().all(|r| r.corrected_altitude.is_none()) {
+        return Ok(true);
+    }
+    let corrected: Vec<Option<f64>> = if parsed.records.len() == records.len() {
+        records.iter().map(|r| r.corrected_altitude).collect()
+    } else {
+        let positions: Vec<(Option<f64>, Option<f64>)> = parsed
+            .records
+            .iter()
+            .map(|r| (r.position_lat, r.position_long))
+            .collect();
+        state.dem.lock().unwrap().sample_track(&positions)?
+    };
+    if corrected.iter().all(|a| a.is_none()) {
+        return Ok(false);
+    }
+
+    save_corrected_elevation(&conn, id, &corrected)?;
+    repo_set_elevation_source(&conn, profile_id, id, &activity.elevation_source)?;
+    Ok(true)
+}
+
+#[tauri::command]
+fn reparse_activity(id: i64, state: State<AppState>) -> Result<Activity, AppError> {
+    let profile_id = active_profile(&state);
+    // A correction that couldn't be re-applied shows as device elevation
+    reparse(&state, profile_id, id)?;
+    let conn = state.db.lock().unwrap();
+    get_activity_summary(&conn, profile_id, id)
+}
+
+/// Rebuild an activity from ranges of its stored records, recomputing zones,
+/// track, elevation, places and dates. The first range stays the activity and
+/// any others become new activities.
+fn rebuild_activity(
+    state: &AppState,
+    id: i64,
+    ranges: impl FnOnce(&[TrackRecord]) -> Result<Vec<Range<usize>>, AppError>,
+) -> Result<Vec<Activity>, AppError> {
+    let profile_id = active_profile(state);
+    let (activity, records, options) = {
+        let conn = state.db.lock().unwrap();
+        let activity = get_activity_summary(&conn, profile_id, id)?;
+        let records = get_records(&conn, id)?;
+        let options = get_parse_options(&conn, profile_id)?;
+        (activity, records, options)
+    };
+
+    let ranges = ranges(&records)?;
+    let parts = ranges
+        .iter()
+        .enumerate()
+        .map(|(i, range)| {
+            let raw = raw_records(&records[range.clone()])?;
+            let filename = match i {
+                0 => activity.filename.clone(),
+                _ => part_filename(&activity.filename, raw[0].timestamp),
+            };
+            build_activity(
+                &filename,
+                activity.activity_type.clone(),
+                raw,
+                Vec::new(),
+                &options,
+                &state.geocoder,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // The database was unlocked while the parts were built
+    let conn = state.db.lock().unwrap();
+    check_activity_unchanged(&conn, profile_id, id, &activity.start_time, &records)?;
+    let ids = repo_save_edited_activity(&conn, profile_id, id, &parts)?;
+
+    // Rebuilt records keep the order of their range, so a DEM correction
+    // carries over to each part without sampling the DEM again
+    for (&part_id, range) in ids.iter().zip(&ranges) {
+        let corrected: Vec<Option<f64>> = records[range.clone()]
+            .iter()
+            .map(|r| r.corrected_altitude)
+            .collect();
+        if corrected.iter().any(|a| a.is_some()) {
+            save_corrected_elevation(&conn, part_id, &corrected)?;
+            repo_set_elevation_source(&conn, profile_id, part_id, &activity.elevation_source)?;
+        }
+    }
+
+    ids.into_iter()
+        .map(|id| get_activity_summary(&conn, profile_id, id))
+        .collect()
+}
+
+/// Keep only the records of an activity between `start` and `end` elapsed
+/// seconds, or metres when `by` is "distance"
+#[tauri::command]
+fn trim_activity(
+    id: i64,
+    start: f64,
+    end: f64,
+    by: Option<String>,
+    state: State<AppState>,
+) -> Result<Activity, AppError> {
+    let axis = StreamAxis::parse(by.as_deref().unwrap_or("time"))?;
+    let mut activities = rebuild_activity(&state, id, |records| {
+        Ok(vec![trim_range(records, axis, start, end)?])
+    })?;
+    Ok(activities.remove(0))
+}
+
+/// Split an activity into parts starting at each point of `at`, in elapsed
+/// seconds or metres. Returns the activity followed by the new parts.
+#[tauri::command]
+fn split_activity(
+    id: i64,
+    at: Vec<f64>,
+    by: Option<String>,
+    state: State<AppState>,
+) -> Result<Vec<Activity>, AppError> {
+    let axis = StreamAxis::parse(by.as_deref().unwrap_or("time"))?;
+    rebuild_activity(&state, id, |records| split_ranges(records, axis, &at))
+}
+
+/// Merge recordings of one outing, such as a file resumed after a watch
+/// battery died or a second device worn alongside, into the earliest of them.
+/// The others are deleted.
+#[tauri::command]
+fn merge_activities(mut ids: Vec<i64>, state: State<AppState>) -> Result<Activity, AppError> {
+    ids.sort_unstable();
+    ids.dedup();
+
+    let profile_id = active_profile(&state);
+    let (mut activities, options) = {
+        let conn = state.db.lock().unwrap();
+        let mut activities = Vec::new();
+        for &id in &ids {
+            let activity = get_activity_summary(&conn, profile_id, id)?;
+            activities.push((activity, get_records(&conn, id)?));
+        }
+        (activities, get_parse_options(&conn, profile_id)?)
+    };
+    activities.sort_by(|a, b| a.0.start_time.cmp(&b.0.start_time));
+    let (activities, recordings): (Vec<Activity>, Vec<Vec<TrackRecord>>) =
+        activities.into_iter().unzip();
+
+    let raw = merge_records(&recordings)?;
+    let first = &activities[0];
+    let parsed = build_activity(
+        &first.filename,
+        first.activity_type.clone(),
+        raw,
+        Vec::new(),
+        &options,
+        &state.geocoder,
+    )?;
+
+    let ids: Vec<i64> = activities.iter().map(|activity| activity.id).collect();
+    // The database was unlocked while the recordings were merged
+    let conn = state.db.lock().unwrap();
+    for (activity, records) in activities.iter().zip(&recordings) {
+        check_activity_unchanged(
+            &conn,
+            profile_id,
+            activity.id,
+            &activity.start_time,
+            records,
+        )?;
+    }
+    repo_save_merged_activity(&conn, profile_id, &ids, &parsed)?;
+    get_activity_summary(&conn, profile_id, ids[0])
+}
+
+/// Re-parse every activity whose original file is stored. Activities imported
+/// before originals were kept are skipped, and a failure doesn't stop the rest.
+#[tauri::command]
+fn reparse_all(state: State<AppState>) -> Result<ReparseSummary, AppError> {
+    let profile_id = active_profile(&state);
+    let (ids, without_source) = repo_reparseable_activities(&state.db.lock().unwrap(), profile_id)?;
+    let mut summary = ReparseSummary {
+        reparsed: 0,
+        without_source,
+        elevation_corrections_dropped: Vec::new(),
+        failures: Vec::new(),
+    };
+
+    for id in ids {
+        match reparse(&state, profile_id, id) {
+            Ok(corrected) => {
+                summary.reparsed += 1;
+                if !corrected {
+                    summary.elevation_corrections_dropped.push(id);
+                }
+            }
+            Err(e) => summary.failures.push(ReparseFailure {
+                activity_id: id,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Write the original file an activity was imported from to `path`
+#[tauri::command]
+fn export_source_file(id: i64, path: String, state: State<AppState>) -> Result<(), AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    let source = repo_get_source_file(&conn, profile_id, id)?
+        .ok_or_else(|| AppError::NotFound(format!("Original file of activity {}", id)))?;
+    drop(conn);
+
+    std::fs::write(&path, source)?;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_elevation_filter(state: State<AppState>) -> Result<ElevationFilter, AppError> {
+    let conn = state.db.lock().unwrap();
+    repo_get_elevation_filter(&conn)
+}
+
+#[tauri::command]
+fn set_elevation_filter(filter: ElevationFilter, state: State<AppState>) -> Result<(), AppError> {
+    let conn = state.db.lock().unwrap();
+    repo_set_elevation_filter(&conn, &filter)
+}
+
+/// Re-run the elevation calculation on the stored records of every activity
+/// of the active profile
+#[tauri::command]
+fn recalculate_elevation(state: State<AppState>) -> Result<usize, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    let filter = repo_get_elevation_filter(&conn)?;
+    repo_recalculate_elevation(&conn, profile_id, &filter)
+}
+
+#[tauri::command]
+fn get_track_cleaning(state: State<AppState>) -> Result<TrackCleaning, AppError> {
+    let conn = state.db.lock().unwrap();
+    repo_get_track_cleaning(&conn)
+}
+
+#[tauri::command]
+fn set_track_cleaning(cleaning: TrackCleaning, state: State<AppState>) -> Result<(), AppError> {
+    let conn = state.db.lock().unwrap();
+    repo_set_track_cleaning(&conn, &cleaning)
+}
+
+/// How often the scheduled backup thread checks whether a backup is due
+const BACKUP_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// Take a backup if the schedule says one is due, then delete backups beyond the retention count
+fn run_scheduled_backup(state: &AppState) -> Result<Option<BackupInfo>, AppError> {
+    let conn = state.db.lock().unwrap();
+    let schedule = repo_get_backup_schedule(&conn)?;
+    let now = Utc::now();
+    if !backup_due(&schedule, latest_backup(&state.backup_dir)?, now) {
+        return Ok(None);
+    }
+
+    let backup = create_backup(&conn, &state.backup_dir, now)?;
+    prune_backups(&state.backup_dir, schedule.keep)?;
+    Ok(Some(backup))
+}
+
+/// Back up the database to a timestamped file in `directory`, or in the
+/// backup directory when not given. A backup is a standalone SQLite file that
+/// can be copied to another machine and restored there.
+#[tauri::command]
+fn backup_database(
+    directory: Option<String>,
+    state: State<AppState>,
+) -> Result<BackupInfo, AppError> {
+    let directory = directory
+        .map(PathBuf::from)
+        .unwrap_or_else(|| state.backup_dir.clone());
+    let conn = state.db.lock().unwrap();
+    create_backup(&conn, &directory, Utc::now())
+}
+
+#[tauri::command]
+fn list_backups(state: State<AppState>) -> Result<Vec<BackupInfo>, AppError> {
+    backups_in(&state.backup_dir)
+}
+
+/// Replace the database with a backup. The backup is checked and brought up
+/// to the current schema on a copy, then the current data is backed up so the
+/// restore can be undone and the copy replaces the open database.
+#[tauri::command]
+fn restore_database(path: String, state: State<AppState>) -> Result<(), AppError> {
+    let staged = stage_restore(&PathBuf::from(&path), &state.db_path)?;
+    prepare_db(&staged)?;
+    backfill_spatial_index(&staged)?;
+    backfill_routes(&staged)?;
+    backfill_places(&staged, &state.geocoder)?;
+    backfill_titles(&staged)?;
+    backfill_heart_rates(&staged)?;
+    backfill_corrected_totals(&staged)?;
+    let profile = repo_get_active_profile(&staged)?;
+
+    let mut conn = state.db.lock().unwrap();
+    backup_before_restore(&conn, &state.backup_dir, Utc::now())?;
+    replace_database(&mut conn, staged, &state.db_path)?;
+    drop(conn);
+
+    *state.profile.lock().unwrap() = profile;
+    Ok(())
+}
+
+/// Automatic backup settings, with the error of the last scheduled backup if it failed
+#[tauri::command]
+fn get_backup_schedule(state: State<AppState>) -> Result<BackupSchedule, AppError> {
+    let schedule = repo_get_backup_schedule(&state.db.lock().unwrap())?;
+    Ok(BackupSchedule {
+        last_error: state.backup_error.lock().unwrap().clone(),
+        ..schedule
+    })
+}
+
+#[tauri::command]
+fn set_backup_schedule(schedule: BackupSchedule, state: State<AppState>) -> Result<(), AppError> {
+    validate_schedule(&schedule)?;
+    let conn = state.db.lock().unwrap();
+    repo_set_backup_schedule(&conn, &schedule)
+}
+
+// ============ DEM Elevation Commands ============
+
+#[tauri::command]
+fn list_dem_tiles(state: State<AppState>) -> Vec<String> {
+    let dem = state.dem.lock().unwrap();
+    dem.list_available()
+}
+
+#[tauri::command]
+fn get_dem_path(state: State<AppState>) -> String {
+    let dem = state.dem.lock().unwrap();
+    dem.get_dem_path().to_string_lossy().to_string()
+}
+
+/// Sample local DEM tiles along an activity's track, store the corrected
+/// altitude series and switch the activity to DEM elevation
+#[tauri::command]
+fn correct_elevation(id: i64, state: State<AppState>) -> Result<Activity, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    get_activity_summary(&conn, profile_id, id)?;
+    let records = get_records(&conn, id)?;
+
+    let positions: Vec<(Option<f64>, Option<f64>)> = records
+        .iter()
+        .map(|r| (r.position_lat, r.position_long))
+        .collect();
+    let corrected = state.dem.lock().unwrap().sample_track(&positions)?;
+    if corrected.iter().all(|a| a.is_none()) {
+        return Err(AppError::NotFound(
+            "No DEM tiles cover this activity".to_string(),
+        ));
+    }
+
+    save_corrected_elevation(&conn, id, &corrected)?;
+    repo_set_elevation_source(&conn, profile_id, id, "dem")?;
+
+    get_activity_summary(&conn, profile_id, id)
+}
+
+#[tauri::command]
+fn set_elevation_source(
+    id: i64,
+    source: String,
+    state: State<AppState>,
+) -> Result<Activity, AppError> {
+    let profile_id = active_profile(&state);
+    let conn = state.db.lock().unwrap();
+    repo_set_elevation_source(&conn, profile_id, id, &source)?;
+    get_activity_summary(&conn, profile_id, id)
 }
 
 /// Progress event payload for bulk import
@@ -100,6 +1232,10 @@ async fn import_fit_files(
 ) -> Result<Vec<Activity>, String> {
     let total = paths.len();
     let mut results: Vec<Activity> = Vec::new();
+    // Files are imported into the profile active when the import started
+    let profile_id = active_profile(&state);
+    let options =
+        get_parse_options(&state.db.lock().unwrap(), profile_id).map_err(|e| e.to_string())?;
 
     for (index, path) in paths.iter().enumerate() {
         let filename = PathBuf::from(path)
@@ -122,7 +1258,7 @@ async fn import_fit_files(
         );
 
         // Parse the file
-        let parsed = match parse_fit_file(&PathBuf::from(path)) {
+        let parsed = match parse_fit_file(&PathBuf::from(path), &options, &state.geocoder) {
             Ok(p) => p,
             Err(e) => {
                 let _ = app.emit(
@@ -155,26 +1291,12 @@ async fn import_fit_files(
 
         // Save to database
         let conn = state.db.lock().unwrap();
-        let result = insert_activity(&conn, &parsed);
+        let result = insert_activity(&conn, profile_id, &parsed)
+            .and_then(|id| get_activity_summary(&conn, profile_id, id));
         drop(conn); // Release lock
 
         match result {
-            Ok(id) => {
-                let activity = Activity {
-                    id,
-                    filename: parsed.filename.clone(),
-                    activity_type: parsed.activity_type.clone(),
-                    activity_date: parsed.activity_date.to_string(),
-                    start_time: parsed.start_time.clone(),
-                    location: parsed.location.clone(),
-                    total_duration: parsed.total_duration,
-                    zones: parsed.zones.clone(),
-                    elevation_gain: Some(parsed.elevation_gain),
-                    max_altitude: parsed.max_altitude,
-                    min_altitude: parsed.min_altitude,
-                    total_distance: Some(parsed.total_distance),
-                };
-
+            Ok(activity) => {
                 let _ = app.emit(
                     "import-progress",
                     ImportProgress {
@@ -257,10 +1379,37 @@ pub fn run() {
             std::fs::create_dir_all(&app_dir).expect("Failed to create app data dir");
             let db_path = app_dir.join("fitness.db");
             let conn = init_db(&db_path).expect("Failed to initialize database");
-            let tile_server = TileServer::new(app_dir);
+            backfill_spatial_index(&conn).expect("Failed to build spatial index");
+            backfill_routes(&conn).expect("Failed to match routes");
+            let geocoder = ReverseGeocoder::new();
+            backfill_places(&conn, &geocoder).expect("Failed to geocode activities");
+            backfill_titles(&conn).expect("Failed to title activities");
+            backfill_heart_rates(&conn).expect("Failed to summarize heart rates");
+            backfill_corrected_totals(&conn).expect("Failed to total corrected elevation");
+            let profile = repo_get_active_profile(&conn).expect("Failed to load active profile");
+            let tile_server = TileServer::new(app_dir.clone());
+            let backup_dir = app_dir.join("backups");
+            let dem_store = DemStore::new(app_dir);
             app.manage(AppState {
                 db: Mutex::new(conn),
                 tiles: Mutex::new(tile_server),
+                dem: Mutex::new(dem_store),
+                geocoder,
+                profile: Mutex::new(profile),
+                db_path,
+                backup_dir,
+                backup_error: Mutex::new(None),
+            });
+
+            let handle = app.handle().clone();
+            std::thread::spawn(move || loop {
+                let state = handle.state::<AppState>();
+                match run_scheduled_backup(&state) {
+                    Ok(None) => {}
+                    Ok(Some(_)) => *state.backup_error.lock().unwrap() = None,
+                    Err(e) => *state.backup_error.lock().unwrap() = Some(e.to_string()),
+                }
+                std::thread::sleep(BACKUP_CHECK_INTERVAL);
             });
             Ok(())
         })
@@ -268,10 +1417,72 @@ pub fn run() {
             import_fit_file,
             import_fit_files,
             list_activities,
+            list_regions,
+            update_activity_metadata,
+            list_tags,
+            list_activity_types,
+            save_activity_type,
+            delete_activity_type,
+            update_activity_type,
+            list_profiles,
+            get_active_profile,
+            save_profile,
+            delete_profile,
+            switch_profile,
+            list_gear,
+            save_gear,
+            delete_gear,
+            get_activity_gear,
+            set_activity_gear,
             get_activity,
+            get_activity_splits,
+            get_activity_streams,
+            export_activities,
+            export_analytics,
+            get_activity_tracks,
+            find_activities_in_bbox,
+            find_activities_near,
+            list_routes,
+            rename_route,
+            get_route_attempts,
+            save_trip,
+            delete_trip,
+            list_trips,
+            get_trip,
+            get_trip_track,
+            suggest_trips,
+            create_segment,
+            create_segment_from_activity,
+            list_segments,
+            get_segment_leaderboard,
+            delete_segment,
+            import_pois,
+            get_activity_pois,
+            list_bagged_pois,
+            get_poi_visits,
             get_weekly_summary,
             get_monthly_summary,
             delete_activity,
+            reparse_activity,
+            reparse_all,
+            merge_activities,
+            split_activity,
+            trim_activity,
+            export_source_file,
+            get_elevation_filter,
+            set_elevation_filter,
+            recalculate_elevation,
+            get_track_cleaning,
+            set_track_cleaning,
+            backup_database,
+            list_backups,
+            restore_database,
+            get_backup_schedule,
+            set_backup_schedule,
+            list_dem_tiles,
+            get_dem_path,
+            correct_elevation,
+            set_elevation_source,
             list_tile_files,
             load_tiles,
             get_tile,
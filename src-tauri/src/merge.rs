@@ -0,0 +1,246 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+
+use crate::error::AppError;
+use crate::parser::RawRecord;
+use crate::trim::record_time;
+use crate::types::TrackRecord;
+
+/// Furthest (ms) a value may be taken from a record of another recording
+/// than the one providing a point's time
+const MAX_FIELD_OFFSET_MS: i64 = 5_000;
+
+/// One recording being merged, with its record times
+struct Recording<'a> {
+    times: Vec<DateTime<Utc>>,
+    records: &'a [TrackRecord],
+}
+
+impl<'a> Recording<'a> {
+    fn new(records: &'a [TrackRecord]) -> Result<Self, AppError> {
+        let times = records
+            .iter()
+            .map(record_time)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { times, records })
+    }
+
+    fn covers(&self, time: DateTime<Utc>) -> bool {
+        match (self.times.first(), self.times.last()) {
+            (Some(first), Some(last)) => *first <= time && time <= *last,
+            _ => false,
+        }
+    }
+
+    /// Records per second, so the denser of two overlapping recordings sets the timeline
+    fn density(&self) -> f64 {
+        match (self.times.first(), self.times.last()) {
+            (Some(first), Some(last)) => {
+                self.times.len() as f64 / ((*last - *first).num_seconds().max(1) as f64)
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Value from the record closest to `time` that has one, within `MAX_FIELD_OFFSET_MS`
+    fn nearest<T>(
+        &self,
+        time: DateTime<Utc>,
+        value: &impl Fn(&TrackRecord) -> Option<T>,
+    ) -> Option<T> {
+        let index = self.times.partition_point(|t| *t < time);
+        let offset = |i: usize| (self.times[i] - time).num_milliseconds().abs();
+        let mut before = (0..index)
+            .rev()
+            .take_while(|&i| offset(i) <= MAX_FIELD_OFFSET_MS);
+        let mut after = (index..self.times.len()).take_while(|&i| offset(i) <= MAX_FIELD_OFFSET_MS);
+        let first_before = before.find_map(|i| value(&self.records[i]).map(|v| (offset(i), v)));
+        let first_after = after.find_map(|i| value(&self.records[i]).map(|v| (offset(i), v)));
+
+        match (first_before, first_after) {
+            (Some(b), Some(a)) => Some(if b.0 < a.0 { b.1 } else { a.1 }),
+            (b, a) => b.or(a).map(|(_, v)| v),
+        }
+    }
+}
+
+/// Recordings ordered by how often they have a field, so each field comes
+/// from the recording that measured it best
+fn ranking(recordings: &[Recording], has: impl Fn(&TrackRecord) -> bool) -> Vec<usize> {
+    let coverage: Vec<f64> = recordings
+        .iter()
+        .map(|r| {
+            r.records.iter().filter(|record| has(record)).count() as f64
+                / r.records.len().max(1) as f64
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..recordings.len()).collect();
+    order.sort_by(|a, b| coverage[*b].total_cmp(&coverage[*a]));
+    order
+}
+
+/// Value of a field at `time` from the best-ranked recording covering it,
+/// with the index of that recording
+fn pick<T>(
+    recordings: &[Recording],
+    order: &[usize],
+    time: DateTime<Utc>,
+    value: impl Fn(&TrackRecord) -> Option<T>,
+) -> Option<(usize, T)> {
+    order
+        .iter()
+        .filter(|&&i| recordings[i].covers(time))
+        .find_map(|&i| recordings[i].nearest(time, &value).map(|v| (i, v)))
+}
+
+/// Merge the records of several recordings of one outing into raw records in
+/// time order. Where recordings overlap, the denser one provides the record
+/// times and each field is taken from the recording that has it most often,
+/// e.g. heart rate from a chest strap and positions from a phone. Distances
+/// are summed from each recording's own increments.
+pub fn merge_records(recordings: &[Vec<TrackRecord>]) -> Result<Vec<RawRecord>, AppError> {
+    if recordings.len() < 2 {
+        return Err(AppError::InvalidArgument(
+            "Merging needs at least two activities".to_string(),
+        ));
+    }
+    let recordings = recordings
+        .iter()
+        .map(|r| Recording::new(r))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let hr_order = ranking(&recordings, |r| r.heart_rate.is_some());
+    let distance_order = ranking(&recordings, |r| r.distance.is_some());
+    let altitude_order = ranking(&recordings, |r| r.altitude.is_some());
+    let speed_order = ranking(&recordings, |r| r.speed.is_some());
+    let temperature_order = ranking(&recordings, |r| r.temperature.is_some());
+    let position_order = ranking(&recordings, |r| {
+        r.position_lat.is_some() && r.position_long.is_some()
+    });
+    let extra_keys: BTreeSet<&String> = recordings
+        .iter()
+        .flat_map(|r| r.records.iter().flat_map(|record| record.extras.keys()))
+        .collect();
+    let extra_orders: Vec<(&String, Vec<usize>)> = extra_keys
+        .into_iter()
+        .map(|key| (key, ranking(&recordings, |r| r.extras.contains_key(key))))
+        .collect();
+
+    // Each point of the timeline comes from the densest recording covering its time
+    let densities: Vec<f64> = recordings.iter().map(Recording::density).collect();
+    let mut timeline: Vec<DateTime<Utc>> = recordings
+        .iter()
+        .enumerate()
+        .flat_map(|(i, r)| r.times.iter().map(move |t| (*t, i)))
+        .filter(|(time, i)| {
+            // Ties go to the earlier recording
+            let base = (0..recordings.len())
+                .filter(|&j| recordings[j].covers(*time))
+                .fold(None, |best: Option<usize>, j| match best {
+                    Some(b) if densities[b] >= densities[j] => Some(b),
+                    _ => Some(j),
+                });
+            base == Some(*i)
+        })
+        .map(|(time, _)| time)
+        .collect();
+    timeline.sort();
+    timeline.dedup();
+
+    let mut total_distance = 0.0;
+    let mut last_distance: Option<(usize, f64)> = None;
+    let merged = timeline
+        .into_iter()
+        .map(|time| {
+            let distance = pick(&recordings, &distance_order, time, |r| r.distance);
+            if let (Some((source, d)), Some((last_source, last))) = (distance, last_distance) {
+                if source == last_source {
+                    total_distance += (d - last).max(0.0);
+                }
+            }
+            last_distance = distance.or(last_distance);
+            let position = pick(&recordings, &position_order, time, |r| {
+                r.position_lat.zip(r.position_long)
+            });
+
+            let extras: HashMap<String, f64> = extra_orders
+                .iter()
+                .filter_map(|(key, order)| {
+                    pick(&recordings, order, time, |r| r.extras.get(*key).copied())
+                        .map(|(_, v)| ((*key).clone(), v))
+                })
+                .collect();
+
+            RawRecord {
+                timestamp: time,
+                heart_rate: pick(&recordings, &hr_order, time, |r| r.heart_rate)
+                    .and_then(|(_, hr)| u8::try_from(hr).ok()),
+                distance: distance.map(|_| total_distance),
+                altitude: pick(&recordings, &altitude_order, time, |r| r.altitude).map(|(_, v)| v),
+                speed: pick(&recordings, &speed_order, time, |r| r.speed).map(|(_, v)| v),
+                temperature: pick(&recordings, &temperature_order, time, |r| r.temperature)
+                    .map(|(_, t)| t.round() as i8),
+                position_lat: position.map(|(_, (lat, _))| lat),
+                position_long: position.map(|(_, (_, lon))| lon),
+                extras,
+            }
+        })
+        .collect();
+
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn record(second: i64, heart_rate: Option<i32>, distance: f64, position: bool) -> TrackRecord {
+        let start = Utc.with_ymd_and_hms(2024, 7, 14, 6, 0, 0).unwrap();
+        TrackRecord {
+            timestamp: (start + chrono::Duration::seconds(second)).to_rfc3339(),
+            heart_rate,
+            distance: Some(distance),
+            altitude: Some(1000.0),
+            position_lat: position.then_some(46.0 + second as f64 * 1e-5),
+            position_long: position.then_some(7.0),
+            zone: "zone1".to_string(),
+            ..TrackRecord::default()
+        }
+    }
+
+    #[test]
+    fn test_resumed_recording() {
+        // The watch dies after ten minutes; the phone started a little earlier
+        let watch: Vec<TrackRecord> = (0..=600)
+            .map(|s| record(s, Some(130), s as f64 * 1.2, true))
+            .collect();
+        let phone: Vec<TrackRecord> = (500..=1200)
+            .map(|s| record(s, None, (s - 500) as f64 * 1.2, true))
+            .collect();
+        let merged = merge_records(&[watch, phone]).unwrap();
+
+        assert_eq!(merged.len(), 1201);
+        assert!(merged.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+        assert_eq!(merged[600].heart_rate, Some(130));
+        assert_eq!(merged[601].heart_rate, None);
+        let total = merged.last().unwrap().distance.unwrap();
+        assert!((total - (720.0 + 599.0 * 1.2)).abs() < 1e-6, "{}", total);
+    }
+
+    #[test]
+    fn test_best_source_per_field() {
+        let strap: Vec<TrackRecord> = (0..100)
+            .map(|s| record(s, Some(140), s as f64, false))
+            .collect();
+        let phone: Vec<TrackRecord> = (0..100).map(|s| record(s, None, s as f64, true)).collect();
+        let merged = merge_records(&[phone, strap]).unwrap();
+
+        assert_eq!(merged.len(), 100);
+        assert!(merged
+            .iter()
+            .all(|r| r.heart_rate == Some(140) && r.position_lat.is_some()));
+        assert!(merge_records(&[Vec::new()]).is_err());
+    }
+}
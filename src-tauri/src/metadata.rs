@@ -0,0 +1,75 @@
+use chrono::{DateTime, Timelike, Utc};
+
+/// Approximate local solar hour from UTC time and longitude. FIT records
+/// carry UTC timestamps, and this is close enough to pick a time of day.
+fn solar_hour(start: DateTime<Utc>, lon: Option<f64>) -> f64 {
+    let utc_hour = start.hour() as f64 + start.minute() as f64 / 60.0;
+    (utc_hour + lon.unwrap_or(0.0) / 15.0).rem_euclid(24.0)
+}
+
+fn time_of_day(hour: f64) -> &'static str {
+    match hour {
+        h if (5.0..12.0).contains(&h) => "Morning",
+        h if (12.0..17.0).contains(&h) => "Afternoon",
+        h if (17.0..21.0).contains(&h) => "Evening",
+        _ => "Night",
+    }
+}
+
+/// Title such as "Morning Hike in Zermatt" from the activity type, start
+/// time and start location ("town, country")
+pub fn default_title(
+    activity_type: &str,
+    start: DateTime<Utc>,
+    lon: Option<f64>,
+    location: Option<&str>,
+) -> String {
+    let title = format!("{} {}", time_of_day(solar_hour(start, lon)), activity_type);
+    match location
+        .and_then(|l| l.split(", ").next())
+        .filter(|town| !town.is_empty())
+    {
+        Some(town) => format!("{} in {}", title, town),
+        None => title,
+    }
+}
+
+/// Trim tags, collapse inner whitespace and drop empty and case-insensitive duplicates
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !tag.is_empty() && !normalized.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_default_title() {
+        let start = Utc.with_ymd_and_hms(2024, 7, 14, 6, 30, 0).unwrap();
+        assert_eq!(
+            default_title("Hike", start, Some(7.7), Some("Zermatt, CH")),
+            "Morning Hike in Zermatt"
+        );
+        // 06:30 UTC is still night at 120°W
+        assert_eq!(default_title("Run", start, Some(-120.0), None), "Night Run");
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        let tags = vec![
+            " Mont Blanc ".to_string(),
+            "mont  blanc".to_string(),
+            "".to_string(),
+            "hut to hut".to_string(),
+        ];
+        assert_eq!(normalize_tags(&tags), vec!["Mont Blanc", "hut to hut"]);
+    }
+}
@@ -1,12 +1,17 @@
+use crate::effort::{average_moving_speed, compute_grades, effort_distance, grade_adjusted_speeds};
+use crate::elevation::{elevation_stats, filter_altitudes, ElevationFilter, ElevationStats};
 use crate::error::AppError;
+use crate::geocode::{activity_places, ActivityPlaces};
+use crate::metadata::default_title;
+use crate::polyline::{simplify, CACHED_TOLERANCE};
+use crate::sports::{activity_type, normalize_sport, sport_name, sub_sport_name, DEFAULT_TYPE};
+use crate::track::{clean_track, cumulative_distance, TrackCleaning};
 use crate::types::ZoneTimes;
-use crate::zones::get_zone;
+use crate::zones::{get_zone, zone_from_thresholds};
 use chrono::{DateTime, Datelike, NaiveDate, Utc};
-use fitparser::{from_reader, profile::MesgNum, FitDataRecord, Value};
+use fitparser::{from_bytes, profile::MesgNum, FitDataRecord, Value};
 use reverse_geocoder::ReverseGeocoder;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
 use std::path::Path;
 
 /// Core field names we extract to dedicated columns
@@ -23,6 +28,21 @@ const CORE_FIELDS: &[&str] = &[
     "position_long",
 ];
 
+/// A record as read from a device, before zones, track cleaning and grade
+/// adjustment are applied. Positions are in degrees.
+#[derive(Debug, Clone)]
+pub struct RawRecord {
+    pub timestamp: DateTime<Utc>,
+    pub heart_rate: Option<u8>,
+    pub distance: Option<f64>,
+    pub altitude: Option<f64>,
+    pub speed: Option<f64>,
+    pub temperature: Option<i8>,
+    pub position_lat: Option<f64>,
+    pub position_long: Option<f64>,
+    pub extras: HashMap<String, f64>,
+}
+
 /// Parsed record from FIT file with all available data
 #[derive(Debug, Clone)]
 pub struct ParsedRecord {
@@ -35,7 +55,12 @@ pub struct ParsedRecord {
     pub temperature: Option<i8>,
     pub position_lat: Option<f64>,
     pub position_long: Option<f64>,
+    /// Position after outlier rejection and smoothing
+    pub clean_position_lat: Option<f64>,
+    pub clean_position_long: Option<f64>,
     pub zone: String,
+    /// Speed adjusted to its flat-ground equivalent using the Minetti cost curve (m/s)
+    pub grade_adjusted_speed: Option<f64>,
     pub extras: HashMap<String, f64>,
 }
 
@@ -43,19 +68,30 @@ pub struct ParsedRecord {
 #[derive(Debug, Clone)]
 pub struct ParsedActivity {
     pub filename: String,
+    pub title: String,
     pub activity_type: String,
     pub activity_date: NaiveDate,
     pub start_time: String,
     pub location: Option<String>,
+    pub places: ActivityPlaces,
     pub week_start: NaiveDate,
     pub month_start: NaiveDate,
     pub total_duration: f64,
     pub total_distance: f64,
     pub zones: ZoneTimes,
     pub records: Vec<ParsedRecord>,
+    /// Cleaned track simplified for map display, as (lat, lon)
+    pub simplified_track: Vec<(f64, f64)>,
     pub elevation_gain: f64,
+    pub elevation_loss: f64,
     pub max_altitude: Option<f64>,
     pub min_altitude: Option<f64>,
+    /// Average moving grade-adjusted speed (m/s)
+    pub avg_grade_adjusted_speed: Option<f64>,
+    /// Distance plus a km-effort allowance for ascent (m)
+    pub effort_distance: f64,
+    /// Contents of the original file, kept so the activity can be parsed again
+    pub source: Vec<u8>,
 }
 
 /// Get Monday of the week containing the given date
@@ -91,93 +127,130 @@ fn extract_f64(value: &Value) -> Option<f64> {
     }
 }
 
-fn map_sport_code_to_type(code: u16) -> Option<&'static str> {
-    match code {
-        1 => Some("Run"),
-        5 => Some("Swimming"),
-        11 => Some("Walk"),
-        17 => Some("Hike"),
-        // Common "training" / strength-like codes sometimes appear as 7/8/9 depending on device.
-        7 | 8 | 9 => Some("Strength"),
+/// Profile name of a `sport` or `sub_sport` field value, which fitparser
+/// decodes to a string when the value is known to its profile
+fn sport_field_name(value: &Value, name_of: fn(u16) -> Option<&'static str>) -> Option<String> {
+    match value {
+        Value::String(s) => Some(normalize_sport(s)),
+        Value::Enum(v) | Value::UInt8(v) => name_of(*v as u16).map(str::to_string),
+        Value::UInt16(v) => name_of(*v).map(str::to_string),
         _ => None,
     }
 }
 
-fn map_sport_string_to_type(raw: &str) -> Option<&'static str> {
-    let s = raw.to_ascii_lowercase();
-    if s.contains("hike") || s.contains("hiking") {
-        Some("Hike")
-    } else if s.contains("walk") || s.contains("walking") {
-        Some("Walk")
-    } else if s.contains("swim") || s.contains("swimming") {
-        Some("Swimming")
-    } else if s.contains("run") || s.contains("running") {
-        Some("Run")
-    } else if s.contains("strength") || s.contains("training") {
-        Some("Strength")
-    } else {
-        None
-    }
-}
-
+/// Activity type from the sport and sub-sport of the session, sport or activity messages
 fn extract_activity_type(records: &[FitDataRecord]) -> String {
     for record in records {
-        if record.kind() != MesgNum::Session && record.kind() != MesgNum::Activity {
+        if !matches!(
+            record.kind(),
+            MesgNum::Session | MesgNum::Sport | MesgNum::Activity
+        ) {
             continue;
         }
 
-        let mut sport_str: Option<String> = None;
-        let mut sport_code: Option<u16> = None;
+        let mut sport: Option<String> = None;
+        let mut sub_sport: Option<String> = None;
 
         for field in record.fields() {
-            let name = field.name();
-            let value = field.value();
-
-            if name == "sport" || name == "sub_sport" {
-                match value {
-                    Value::Enum(v) => sport_code = Some(*v as u16),
-                    Value::UInt8(v) => sport_code = Some(*v as u16),
-                    Value::UInt16(v) => sport_code = Some(*v),
-                    Value::String(s) => sport_str = Some(s.clone()),
-                    _ => {}
-                }
+            match field.name() {
+                "sport" => sport = sport_field_name(field.value(), sport_name),
+                "sub_sport" => sub_sport = sport_field_name(field.value(), sub_sport_name),
+                _ => {}
             }
         }
 
-        if let Some(raw) = sport_str {
-            if let Some(mapped) = map_sport_string_to_type(&raw) {
-                return mapped.to_string();
-            }
+        if sport.is_none() && sub_sport.is_none() {
+            continue;
         }
-        if let Some(code) = sport_code {
-            if let Some(mapped) = map_sport_code_to_type(code) {
-                return mapped.to_string();
-            }
+        let sport = sport.unwrap_or_else(|| "generic".to_string());
+        if let Some(mapped) = activity_type(&sport, sub_sport.as_deref()) {
+            return mapped.to_string();
         }
     }
 
-    "Other".to_string()
+    DEFAULT_TYPE.to_string()
+}
+
+/// Time credited to each record for zone and moving-time totals: the gap to
+/// the next record, capped so pauses in recording aren't counted
+pub fn interval_deltas(elapsed: &[f64]) -> Vec<f64> {
+    (0..elapsed.len())
+        .map(|i| match elapsed.get(i + 1) {
+            Some(next) => (next - elapsed[i]).clamp(0.0, 10.0),
+            None => 0.0,
+        })
+        .collect()
+}
+
+/// Metrics derived from the altitude, distance and speed streams
+#[derive(Debug, Clone)]
+pub struct DerivedMetrics {
+    pub elevation: ElevationStats,
+    pub grade_adjusted_speeds: Vec<Option<f64>>,
+    pub avg_grade_adjusted_speed: Option<f64>,
+}
+
+/// Filter the altitude series and compute elevation totals and grade-adjusted pace from it
+pub fn derive_metrics(
+    elapsed: &[f64],
+    distance: &[Option<f64>],
+    altitude: &[Option<f64>],
+    speed: &[Option<f64>],
+    time_deltas: &[f64],
+    filter: &ElevationFilter,
+) -> DerivedMetrics {
+    let filtered = filter_altitudes(elapsed, altitude, filter);
+    let grades = compute_grades(distance, &filtered);
+    let adjusted = grade_adjusted_speeds(speed, &grades);
+
+    DerivedMetrics {
+        elevation: elevation_stats(&filtered, filter.hysteresis),
+        avg_grade_adjusted_speed: average_moving_speed(speed, &adjusted, time_deltas),
+        grade_adjusted_speeds: adjusted,
+    }
+}
+
+/// User settings that affect derived values during parsing
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub elevation_filter: ElevationFilter,
+    pub track_cleaning: TrackCleaning,
+    /// Lowest heart rate of zones 2 to 5 for the importing profile; None uses the built-in zones
+    pub zone_thresholds: Option<[u8; 4]>,
 }
 
 /// Parse a FIT file and extract all available record data
-pub fn parse_fit_file(path: &Path) -> Result<ParsedActivity, AppError> {
+pub fn parse_fit_file(
+    path: &Path,
+    options: &ParseOptions,
+    geocoder: &ReverseGeocoder,
+) -> Result<ParsedActivity, AppError> {
     let filename = path
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or("unknown")
         .to_string();
 
-    let file = File::open(path)
+    let source = std::fs::read(path)
         .map_err(|e| AppError::FitParse(format!("Failed to open file: {}", e)))?;
-    let mut reader = BufReader::new(file);
 
-    let fit_records = from_reader(&mut reader)
-        .map_err(|e| AppError::FitParse(format!("FIT parse error: {}", e)))?;
+    parse_fit_bytes(&filename, source, options, geocoder)
+}
+
+/// Parse the contents of a FIT file, such as a stored original being re-parsed
+pub fn parse_fit_bytes(
+    filename: &str,
+    source: Vec<u8>,
+    options: &ParseOptions,
+    geocoder: &ReverseGeocoder,
+) -> Result<ParsedActivity, AppError> {
+    let fit_records =
+        from_bytes(&source).map_err(|e| AppError::FitParse(format!("FIT parse error: {}", e)))?;
 
     let activity_type = extract_activity_type(&fit_records);
 
     // Collect all record data
-    let mut raw_records: Vec<(DateTime<Utc>, Option<u8>, Option<f64>, Option<f64>, Option<f64>, Option<i8>, Option<i32>, Option<i32>, HashMap<String, f64>)> = Vec::new();
+    let mut raw_records: Vec<RawRecord> = Vec::new();
 
     for record in fit_records {
         if record.kind() != MesgNum::Record {
@@ -258,81 +331,86 @@ pub fn parse_fit_file(path: &Path) -> Result<ParsedActivity, AppError> {
             let final_altitude = enhanced_altitude.or(altitude);
             let final_speed = enhanced_speed.or(speed);
 
-            raw_records.push((
-                ts,
+            raw_records.push(RawRecord {
+                timestamp: ts,
                 heart_rate,
                 distance,
-                final_altitude,
-                final_speed,
+                altitude: final_altitude,
+                speed: final_speed,
                 temperature,
-                position_lat,
-                position_long,
+                position_lat: position_lat.map(semicircles_to_degrees),
+                position_long: position_long.map(semicircles_to_degrees),
                 extras,
-            ));
+            });
         }
     }
 
+    build_activity(
+        filename,
+        activity_type,
+        raw_records,
+        source,
+        options,
+        geocoder,
+    )
+}
+
+/// Compute an activity's records, track and summary from raw records. Used
+/// for parsed files and for activities rebuilt from stored records after an edit.
+pub fn build_activity(
+    filename: &str,
+    activity_type: String,
+    mut raw_records: Vec<RawRecord>,
+    source: Vec<u8>,
+    options: &ParseOptions,
+    geocoder: &ReverseGeocoder,
+) -> Result<ParsedActivity, AppError> {
     if raw_records.is_empty() {
         return Err(AppError::FitParse("No records found".to_string()));
     }
 
     // Sort by timestamp
-    raw_records.sort_by_key(|(ts, ..)| *ts);
+    raw_records.sort_by_key(|r| r.timestamp);
 
-    let first_timestamp = raw_records[0].0;
+    let first_timestamp = raw_records[0].timestamp;
     let mut parsed_records: Vec<ParsedRecord> = Vec::new();
     let mut zones = ZoneTimes::default();
     let mut total_duration = 0.0;
-    let mut elevation_gain = 0.0;
-    let mut max_altitude: Option<f64> = None;
-    let mut min_altitude: Option<f64> = None;
-    let mut last_altitude: Option<f64> = None;
+    let elapsed: Vec<f64> = raw_records
+        .iter()
+        .map(|r| (r.timestamp - first_timestamp).num_milliseconds() as f64 / 1000.0)
+        .collect();
+    // Time deltas for zone accumulation
+    let time_deltas = interval_deltas(&elapsed);
 
-    for i in 0..raw_records.len() {
-        let (timestamp, heart_rate, distance, altitude, speed, temperature, position_lat, position_long, extras) = raw_records[i].clone();
+    for (i, raw) in raw_records.into_iter().enumerate() {
+        let RawRecord {
+            timestamp,
+            heart_rate,
+            distance,
+            altitude,
+            speed,
+            temperature,
+            position_lat,
+            position_long,
+            extras,
+        } = raw;
 
-        let elapsed_time = (timestamp - first_timestamp).num_milliseconds() as f64 / 1000.0;
+        let elapsed_time = elapsed[i];
+        let time_delta = time_deltas[i];
         let zone = heart_rate
-            .map(|hr| get_zone(hr).to_string())
+            .map(|hr| match &options.zone_thresholds {
+                Some(thresholds) => zone_from_thresholds(hr, thresholds).to_string(),
+                None => get_zone(hr).to_string(),
+            })
             .unwrap_or_else(|| "zone1".to_string());
 
-        // Calculate time delta for zone accumulation
-        let time_delta = if i + 1 < raw_records.len() {
-            let next_ts = raw_records[i + 1].0;
-            let delta = (next_ts - timestamp).num_milliseconds() as f64 / 1000.0;
-            delta.min(10.0).max(0.0)
-        } else {
-            0.0
-        };
-
         // Accumulate zone times
         if heart_rate.is_some() {
-            match zone.as_str() {
-                "zone1" => zones.zone1 += time_delta,
-                "zone2" => zones.zone2 += time_delta,
-                "zone3" => zones.zone3 += time_delta,
-                "zone4" => zones.zone4 += time_delta,
-                "zone5" => zones.zone5 += time_delta,
-                _ => {}
-            }
+            zones.add(&zone, time_delta);
         }
         total_duration += time_delta;
 
-        // Calculate elevation stats
-        if let Some(alt) = altitude {
-            // Min/Max
-            min_altitude = Some(min_altitude.map_or(alt, |m| m.min(alt)));
-            max_altitude = Some(max_altitude.map_or(alt, |m| m.max(alt)));
-
-            // Gain
-            if let Some(last) = last_altitude {
-                if alt > last {
-                    elevation_gain += alt - last;
-                }
-            }
-            last_altitude = Some(alt);
-        }
-
         parsed_records.push(ParsedRecord {
             timestamp,
             elapsed_time,
@@ -341,13 +419,64 @@ pub fn parse_fit_file(path: &Path) -> Result<ParsedActivity, AppError> {
             altitude,
             speed,
             temperature,
-            position_lat: position_lat.map(semicircles_to_degrees),
-            position_long: position_long.map(semicircles_to_degrees),
+            position_lat,
+            position_long,
+            clean_position_lat: None,
+            clean_position_long: None,
             zone,
+            grade_adjusted_speed: None,
             extras,
         });
     }
 
+    // GPS track cleaning
+    let fixes: Vec<Option<(f64, f64)>> = parsed_records
+        .iter()
+        .map(|r| r.position_lat.zip(r.position_long))
+        .collect();
+    let cleaned = clean_track(&elapsed, &fixes, &options.track_cleaning);
+    // Fall back to distance along the cleaned track when the device recorded none
+    let device_distance = parsed_records.iter().any(|r| r.distance.is_some());
+    let track_distance = if device_distance {
+        Vec::new()
+    } else {
+        cumulative_distance(&cleaned)
+    };
+    for (i, record) in parsed_records.iter_mut().enumerate() {
+        record.clean_position_lat = cleaned[i].map(|(lat, _)| lat);
+        record.clean_position_long = cleaned[i].map(|(_, lon)| lon);
+        if !device_distance {
+            record.distance = track_distance[i];
+        }
+    }
+
+    let simplified_track = simplify(
+        &cleaned.iter().flatten().copied().collect::<Vec<_>>(),
+        CACHED_TOLERANCE,
+    );
+
+    // Elevation and grade-adjusted pace
+    let metrics = derive_metrics(
+        &elapsed,
+        &parsed_records
+            .iter()
+            .map(|r| r.distance)
+            .collect::<Vec<_>>(),
+        &parsed_records
+            .iter()
+            .map(|r| r.altitude)
+            .collect::<Vec<_>>(),
+        &parsed_records.iter().map(|r| r.speed).collect::<Vec<_>>(),
+        &time_deltas,
+        &options.elevation_filter,
+    );
+    for (record, gas) in parsed_records
+        .iter_mut()
+        .zip(&metrics.grade_adjusted_speeds)
+    {
+        record.grade_adjusted_speed = *gas;
+    }
+
     let activity_date = parsed_records[0].timestamp.date_naive();
     let start_time = parsed_records[0].timestamp.to_rfc3339();
     // Get max distance from records or 0.0
@@ -356,33 +485,49 @@ pub fn parse_fit_file(path: &Path) -> Result<ParsedActivity, AppError> {
         .filter_map(|r| r.distance)
         .fold(0.0, f64::max);
 
-    // Reverse Geocode
-    let location = parsed_records
-        .iter()
-        .find(|r| r.position_lat.is_some() && r.position_long.is_some())
-        .and_then(|r| {
-            let lat = r.position_lat?;
-            let lon = r.position_long?;
-            let geocoder = ReverseGeocoder::new();
-            let search_result = geocoder.search((lat, lon));
-            Some(format!("{}, {}", search_result.record.name, search_result.record.cc))
-        });
+    // Reverse geocode the start, end and high point of the cleaned track
+    let places = activity_places(
+        geocoder,
+        &cleaned,
+        &filter_altitudes(
+            &elapsed,
+            &parsed_records
+                .iter()
+                .map(|r| r.altitude)
+                .collect::<Vec<_>>(),
+            &options.elevation_filter,
+        ),
+    );
+    let location = places.start_location.clone();
+    let title = default_title(
+        &activity_type,
+        parsed_records[0].timestamp,
+        cleaned.iter().flatten().next().map(|(_, lon)| *lon),
+        location.as_deref(),
+    );
 
     Ok(ParsedActivity {
-        filename,
+        filename: filename.to_string(),
+        title,
         activity_type,
         activity_date,
         start_time,
         location,
+        places,
         week_start: week_start(activity_date),
         month_start: month_start(activity_date),
         total_duration,
         total_distance,
         zones,
         records: parsed_records,
-        elevation_gain,
-        max_altitude,
-        min_altitude,
+        simplified_track,
+        elevation_gain: metrics.elevation.gain,
+        elevation_loss: metrics.elevation.loss,
+        max_altitude: metrics.elevation.max_altitude,
+        min_altitude: metrics.elevation.min_altitude,
+        avg_grade_adjusted_speed: metrics.avg_grade_adjusted_speed,
+        effort_distance: effort_distance(total_distance, metrics.elevation.gain),
+        source,
     })
 }
 
@@ -410,6 +555,12 @@ mod tests {
         assert_eq!(month_start(date), expected);
     }
 
+    #[test]
+    fn test_interval_deltas_capped() {
+        let deltas = interval_deltas(&[0.0, 1.0, 61.0, 62.0]);
+        assert_eq!(deltas, vec![1.0, 10.0, 1.0, 0.0]);
+    }
+
     #[test]
     fn test_semicircles_to_degrees() {
         // 0 semicircles = 0 degrees
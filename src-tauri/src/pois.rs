@@ -0,0 +1,199 @@
+use crate::error::AppError;
+use crate::geo::haversine;
+use crate::track::Fix;
+
+/// Kind of point of interest detected along tracks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoiKind {
+    Peak,
+    Hut,
+    Pass,
+}
+
+impl PoiKind {
+    /// Parse a kind from our own names or the equivalent OSM tag values
+    pub fn parse(kind: &str) -> Option<Self> {
+        let kind = kind.trim().to_ascii_lowercase();
+        let value = kind.rsplit('=').next().unwrap_or(&kind);
+        match value {
+            "peak" | "summit" | "volcano" => Some(PoiKind::Peak),
+            "hut" | "alpine_hut" | "wilderness_hut" | "shelter" => Some(PoiKind::Hut),
+            "pass" | "saddle" | "mountain_pass" => Some(PoiKind::Pass),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PoiKind::Peak => "peak",
+            PoiKind::Hut => "hut",
+            PoiKind::Pass => "pass",
+        }
+    }
+
+    /// How close (m) a track must pass to count as a visit. Huts are larger
+    /// than the mapped point and their surroundings are often walked around.
+    pub fn visit_radius(self) -> f64 {
+        match self {
+            PoiKind::Peak => 50.0,
+            PoiKind::Hut => 75.0,
+            PoiKind::Pass => 50.0,
+        }
+    }
+}
+
+/// Largest visit radius of any kind, for prefiltering candidates
+pub const MAX_VISIT_RADIUS: f64 = 75.0;
+
+/// Point of interest read from an import file
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewPoi {
+    pub name: String,
+    pub kind: PoiKind,
+    pub lat: f64,
+    pub lon: f64,
+    pub elevation: Option<f64>,
+}
+
+/// Split one CSV line into fields, honouring double-quoted fields
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+/// Parse a POI CSV with a header row naming at least `name`, `kind` (or
+/// `type`), `lat` and `lon` columns, plus optional `ele`. Rows with no name
+/// or an unrecognised kind are skipped, so OSM extracts can be imported as-is.
+pub fn parse_poi_csv(text: &str) -> Result<Vec<NewPoi>, AppError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty());
+    let header: Vec<String> = match lines.next() {
+        Some((_, line)) => split_csv_line(line)
+            .into_iter()
+            .map(|h| h.to_ascii_lowercase())
+            .collect(),
+        None => return Ok(Vec::new()),
+    };
+
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let missing =
+        |name: &str| AppError::InvalidArgument(format!("POI file has no {} column", name));
+    let name_col = column(&["name"]).ok_or_else(|| missing("name"))?;
+    let kind_col = column(&["kind", "type"]).ok_or_else(|| missing("kind"))?;
+    let lat_col = column(&["lat", "latitude"]).ok_or_else(|| missing("lat"))?;
+    let lon_col = column(&["lon", "lng", "longitude"]).ok_or_else(|| missing("lon"))?;
+    let ele_col = column(&["ele", "elevation"]);
+
+    let mut pois = Vec::new();
+    for (index, line) in lines {
+        let fields = split_csv_line(line);
+        let field = |col: usize| fields.get(col).map(String::as_str).unwrap_or("");
+        let number = |col: usize| -> Result<f64, AppError> {
+            field(col).parse().map_err(|_| {
+                AppError::InvalidArgument(format!(
+                    "Line {}: invalid number '{}'",
+                    index + 1,
+                    field(col)
+                ))
+            })
+        };
+
+        let (name, Some(kind)) = (field(name_col), PoiKind::parse(field(kind_col))) else {
+            continue;
+        };
+        if name.is_empty() {
+            continue;
+        }
+
+        // OSM elevations are free text such as "4478 m"
+        let elevation =
+            ele_col.and_then(|col| field(col).trim_end_matches('m').trim().parse().ok());
+
+        pois.push(NewPoi {
+            name: name.to_string(),
+            kind,
+            lat: number(lat_col)?,
+            lon: number(lon_col)?,
+            elevation,
+        });
+    }
+
+    Ok(pois)
+}
+
+/// Index of the fix closest to a position and its distance (m)
+pub fn closest_approach(fixes: &[Fix], lat: f64, lon: f64) -> Option<(usize, f64)> {
+    fixes
+        .iter()
+        .enumerate()
+        .filter_map(|(i, fix)| fix.map(|(flat, flon)| (i, haversine(flat, flon, lat, lon))))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kind() {
+        assert_eq!(PoiKind::parse("peak"), Some(PoiKind::Peak));
+        assert_eq!(PoiKind::parse("tourism=alpine_hut"), Some(PoiKind::Hut));
+        assert_eq!(PoiKind::parse("Mountain_Pass"), Some(PoiKind::Pass));
+        assert_eq!(PoiKind::parse("restaurant"), None);
+    }
+
+    #[test]
+    fn test_parse_poi_csv() {
+        let csv = "id,name,type,lat,lon,ele\n\
+                   1,Matterhorn,peak,45.9763,7.6586,4478 m\n\
+                   2,\"Hörnlihütte, Zermatt\",alpine_hut,45.9823,7.6743,\n\
+                   3,,peak,46.0,7.0,3000\n\
+                   4,Café,restaurant,46.0,7.0,\n";
+        let pois = parse_poi_csv(csv).unwrap();
+        assert_eq!(pois.len(), 2);
+        assert_eq!(pois[0].name, "Matterhorn");
+        assert_eq!(pois[0].elevation, Some(4478.0));
+        assert_eq!(pois[1].name, "Hörnlihütte, Zermatt");
+        assert_eq!(pois[1].kind, PoiKind::Hut);
+        assert_eq!(pois[1].elevation, None);
+    }
+
+    #[test]
+    fn test_parse_poi_csv_errors() {
+        assert!(parse_poi_csv("name,lat,lon\nA,1,2\n").is_err());
+        assert!(parse_poi_csv("name,kind,lat,lon\nA,peak,north,2\n").is_err());
+    }
+
+    #[test]
+    fn test_closest_approach() {
+        let fixes = vec![
+            Some((46.0, 7.0)),
+            None,
+            Some((46.001, 7.0)),
+            Some((46.002, 7.0)),
+        ];
+        let (index, distance) = closest_approach(&fixes, 46.0012, 7.0).unwrap();
+        assert_eq!(index, 2);
+        assert!((distance - 22.2).abs() < 0.5);
+        assert!(closest_approach(&[None], 46.0, 7.0).is_none());
+    }
+}
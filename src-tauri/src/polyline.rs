@@ -0,0 +1,112 @@
+use crate::geo::LocalProjection;
+
+/// Tolerance (m) of the simplified track cached for each activity at import
+pub const CACHED_TOLERANCE: f64 = 2.0;
+
+/// Distance (m) from `p` to the segment `a`–`b`, all in a local metric frame
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (cx, cy) = (a.0 + t * dx, a.1 + t * dy);
+    ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt()
+}
+
+/// Douglas–Peucker simplification of a (lat, lon) polyline, keeping every
+/// point that deviates more than `tolerance` metres from the simplified line
+pub fn simplify(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+
+    let projection = LocalProjection::new(points[0].0, points[0].1);
+    let local: Vec<(f64, f64)> = points
+        .iter()
+        .map(|(lat, lon)| projection.project(*lat, *lon))
+        .collect();
+
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    // Iterative to avoid deep recursion on long tracks
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = stack.pop() {
+        let mut max_distance = 0.0;
+        let mut max_index = start;
+        for i in start + 1..end {
+            let d = segment_distance(local[i], local[start], local[end]);
+            if d > max_distance {
+                max_distance = d;
+                max_index = i;
+            }
+        }
+
+        if max_distance > tolerance {
+            keep[max_index] = true;
+            stack.push((start, max_index));
+            stack.push((max_index, end));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(p, k)| k.then_some(*p))
+        .collect()
+}
+
+/// Encode (lat, lon) points in the Google encoded polyline format (5 decimal places)
+pub fn encode(points: &[(f64, f64)]) -> String {
+    fn push_value(out: &mut String, value: i64) {
+        let mut v = if value < 0 { !(value << 1) } else { value << 1 };
+        while v >= 0x20 {
+            out.push((((v & 0x1f) | 0x20) as u8 + 63) as char);
+            v >>= 5;
+        }
+        out.push((v as u8 + 63) as char);
+    }
+
+    let mut out = String::new();
+    let (mut last_lat, mut last_lon) = (0i64, 0i64);
+    for (lat, lon) in points {
+        let lat = (lat * 1e5).round() as i64;
+        let lon = (lon * 1e5).round() as i64;
+        push_value(&mut out, lat - last_lat);
+        push_value(&mut out, lon - last_lon);
+        last_lat = lat;
+        last_lon = lon;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simplify_straight_line() {
+        let points: Vec<(f64, f64)> = (0..100).map(|i| (46.0 + i as f64 * 0.0001, 7.0)).collect();
+        assert_eq!(simplify(&points, 1.0), vec![points[0], points[99]]);
+    }
+
+    #[test]
+    fn test_simplify_keeps_corner() {
+        let mut points: Vec<(f64, f64)> =
+            (0..50).map(|i| (46.0 + i as f64 * 0.0001, 7.0)).collect();
+        points.extend((1..50).map(|i| (46.0049, 7.0 + i as f64 * 0.0001)));
+        let simplified = simplify(&points, 1.0);
+        assert_eq!(simplified, vec![points[0], points[49], points[98]]);
+    }
+
+    #[test]
+    fn test_encode_reference() {
+        // Example from the format documentation
+        let points = [(38.5, -120.2), (40.7, -120.95), (43.252, -126.453)];
+        assert_eq!(encode(&points), "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+    }
+}
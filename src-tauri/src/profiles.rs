@@ -0,0 +1,105 @@
+use chrono::NaiveDate;
+
+use crate::error::AppError;
+use crate::types::ProfileDetails;
+use crate::zones::ZoneModel;
+
+/// Check a profile's name, birthdate, weight and heart rates before saving it
+pub fn validate_profile(profile: &ProfileDetails) -> Result<(), AppError> {
+    if profile.name.trim().is_empty() {
+        return Err(AppError::InvalidArgument(
+            "Profile name is required".to_string(),
+        ));
+    }
+    if let Some(birthdate) = &profile.birthdate {
+        NaiveDate::parse_from_str(birthdate, "%Y-%m-%d")
+            .map_err(|_| AppError::InvalidArgument(format!("Invalid birthdate: {}", birthdate)))?;
+    }
+    if profile.weight.is_some_and(|w| w <= 0.0) {
+        return Err(AppError::InvalidArgument(
+            "Weight must be positive".to_string(),
+        ));
+    }
+
+    for hr in [profile.resting_hr, profile.max_hr].into_iter().flatten() {
+        if !(25..=250).contains(&hr) {
+            return Err(AppError::InvalidArgument(format!(
+                "Heart rate out of range: {}",
+                hr
+            )));
+        }
+    }
+    if let (Some(resting), Some(max)) = (profile.resting_hr, profile.max_hr) {
+        if resting >= max {
+            return Err(AppError::InvalidArgument(
+                "Resting heart rate must be below maximum heart rate".to_string(),
+            ));
+        }
+    }
+
+    let missing = match profile.zone_model {
+        ZoneModel::Fixed => None,
+        ZoneModel::MaxHr => profile.max_hr.is_none().then_some("maximum"),
+        ZoneModel::HeartRateReserve => match (profile.resting_hr, profile.max_hr) {
+            (None, _) => Some("resting"),
+            (_, None) => Some("maximum"),
+            _ => None,
+        },
+    };
+    if let Some(missing) = missing {
+        return Err(AppError::InvalidArgument(format!(
+            "The {} zone model needs a {} heart rate",
+            profile.zone_model.as_str(),
+            missing
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> ProfileDetails {
+        ProfileDetails {
+            name: "Sam".to_string(),
+            birthdate: Some("1988-02-29".to_string()),
+            sex: None,
+            weight: Some(68.5),
+            resting_hr: Some(48),
+            max_hr: Some(188),
+            zone_model: ZoneModel::HeartRateReserve,
+        }
+    }
+
+    #[test]
+    fn test_validate_profile() {
+        assert!(validate_profile(&profile()).is_ok());
+
+        let mut p = profile();
+        p.name = "  ".to_string();
+        assert!(validate_profile(&p).is_err());
+
+        let mut p = profile();
+        p.resting_hr = Some(190);
+        assert!(validate_profile(&p).is_err());
+
+        let mut p = profile();
+        p.birthdate = Some("1988-02-30".to_string());
+        assert!(validate_profile(&p).is_err());
+    }
+
+    #[test]
+    fn test_zone_model_needs_heart_rates() {
+        let mut p = profile();
+        p.resting_hr = None;
+        assert!(validate_profile(&p).is_err());
+        p.zone_model = ZoneModel::MaxHr;
+        assert!(validate_profile(&p).is_ok());
+        p.max_hr = None;
+        assert!(validate_profile(&p).is_err());
+        p.zone_model = ZoneModel::Fixed;
+        assert!(validate_profile(&p).is_ok());
+    }
+}
@@ -0,0 +1,409 @@
+//! Compact storage of an activity's track records as one compressed blob of
+//! columns. Timestamps and elapsed times are delta-encoded in milliseconds and
+//! the other numeric columns are quantized to fixed precision and
+//! delta-encoded as zigzag varints, so smooth series shrink to a byte or two
+//! per sample before compression.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::error::AppError;
+use crate::sources::{compress, decompress};
+use crate::types::TrackRecord;
+
+/// Layout version written at the start of every blob
+const FORMAT_VERSION: u8 = 1;
+
+/// Quantization steps per unit: millimetres for distances and altitudes, mm/s
+/// for speeds, 1e-7° (about 1 cm) for coordinates and 0.1 °C for temperature
+const TIME_SCALE: f64 = 1e3;
+const METRE_SCALE: f64 = 1e3;
+const SPEED_SCALE: f64 = 1e3;
+const DEGREE_SCALE: f64 = 1e7;
+const TEMPERATURE_SCALE: f64 = 1e1;
+const EXTRA_SCALE: f64 = 1e3;
+
+fn corrupt(reason: &str) -> AppError {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Corrupt record data: {}", reason),
+    )
+    .into()
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+fn quantize(value: f64, scale: f64) -> i64 {
+    (value * scale).round() as i64
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    /// A column without gaps, as differences from the previous value
+    fn deltas(&mut self, values: impl Iterator<Item = i64>) {
+        let mut previous = 0i64;
+        for value in values {
+            self.varint(zigzag(value.wrapping_sub(previous)));
+            previous = value;
+        }
+    }
+
+    /// A column with gaps: 0 for a missing value, otherwise one more than the
+    /// zigzagged difference from the previous present value
+    fn optional(&mut self, values: impl Iterator<Item = Option<f64>>, scale: f64) {
+        let mut previous = 0i64;
+        for value in values {
+            match value {
+                Some(value) => {
+                    let value = quantize(value, scale);
+                    self.varint(zigzag(value.wrapping_sub(previous)).wrapping_add(1));
+                    previous = value;
+                }
+                None => self.varint(0),
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Decoder<'_> {
+    fn byte(&mut self) -> Result<u8, AppError> {
+        let byte = *self
+            .bytes
+            .get(self.pos)
+            .ok_or_else(|| corrupt("truncated"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, AppError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(corrupt("varint too long"))
+    }
+
+    fn length(&mut self) -> Result<usize, AppError> {
+        let len = self.varint()? as usize;
+        // Every encoded item takes at least a byte, so longer lengths can't be valid
+        if len > self.bytes.len() - self.pos {
+            return Err(corrupt("length out of range"));
+        }
+        Ok(len)
+    }
+
+    fn string(&mut self) -> Result<String, AppError> {
+        let len = self.length()?;
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        String::from_utf8(bytes.to_vec()).map_err(|_| corrupt("invalid text"))
+    }
+
+    fn deltas(&mut self, count: usize) -> Result<Vec<i64>, AppError> {
+        let mut previous = 0i64;
+        (0..count)
+            .map(|_| {
+                previous = previous.wrapping_add(unzigzag(self.varint()?));
+                Ok(previous)
+            })
+            .collect()
+    }
+
+    fn optional(&mut self, count: usize, scale: f64) -> Result<Vec<Option<f64>>, AppError> {
+        let mut previous = 0i64;
+        (0..count)
+            .map(|_| match self.varint()? {
+                0 => Ok(None),
+                tag => {
+                    previous = previous.wrapping_add(unzigzag(tag - 1));
+                    Ok(Some(previous as f64 / scale))
+                }
+            })
+            .collect()
+    }
+}
+
+/// Sorted keys of the `extras` of a set of records
+pub fn extra_keys(records: &[TrackRecord]) -> Vec<String> {
+    let keys: BTreeSet<&String> = records.iter().flat_map(|r| r.extras.keys()).collect();
+    keys.into_iter().cloned().collect()
+}
+
+/// Encode and compress the records of an activity. All timestamps take the UTC
+/// offset of the first, which is how the parser stores them.
+pub fn encode_records(records: &[TrackRecord]) -> Result<Vec<u8>, AppError> {
+    let times = records
+        .iter()
+        .map(|r| {
+            DateTime::parse_from_rfc3339(&r.timestamp).map_err(|_| {
+                AppError::InvalidArgument(format!("Invalid record timestamp: {}", r.timestamp))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let offset = times.first().map_or(0, |t| t.offset().local_minus_utc());
+
+    let mut out = Encoder::default();
+    out.bytes.push(FORMAT_VERSION);
+    out.varint(records.len() as u64);
+    out.varint(zigzag(offset as i64));
+
+    out.deltas(times.iter().map(|t| t.timestamp_millis()));
+    out.deltas(records.iter().map(|r| quantize(r.elapsed_time, TIME_SCALE)));
+    out.optional(records.iter().map(|r| r.heart_rate.map(f64::from)), 1.0);
+    out.optional(records.iter().map(|r| r.distance), METRE_SCALE);
+    out.optional(records.iter().map(|r| r.altitude), METRE_SCALE);
+    out.optional(records.iter().map(|r| r.speed), SPEED_SCALE);
+    out.optional(records.iter().map(|r| r.temperature), TEMPERATURE_SCALE);
+    out.optional(records.iter().map(|r| r.position_lat), DEGREE_SCALE);
+    out.optional(records.iter().map(|r| r.position_long), DEGREE_SCALE);
+    out.optional(records.iter().map(|r| r.clean_position_lat), DEGREE_SCALE);
+    out.optional(records.iter().map(|r| r.clean_position_long), DEGREE_SCALE);
+    out.optional(records.iter().map(|r| r.grade_adjusted_speed), SPEED_SCALE);
+    out.optional(records.iter().map(|r| r.corrected_altitude), METRE_SCALE);
+
+    // Zones repeat a handful of names, so store each once and index into them
+    let mut zones: Vec<&str> = Vec::new();
+    let indices: Vec<u64> = records
+        .iter()
+        .map(|r| match zones.iter().position(|z| *z == r.zone) {
+            Some(i) => i as u64,
+            None => {
+                zones.push(&r.zone);
+                (zones.len() - 1) as u64
+            }
+        })
+        .collect();
+    out.varint(zones.len() as u64);
+    for zone in zones {
+        out.string(zone);
+    }
+    for index in indices {
+        out.varint(index);
+    }
+
+    let keys = extra_keys(records);
+    out.varint(keys.len() as u64);
+    for key in &keys {
+        out.string(key);
+        out.optional(
+            records.iter().map(|r| r.extras.get(key).copied()),
+            EXTRA_SCALE,
+        );
+    }
+
+    compress(&out.bytes)
+}
+
+/// Decode records stored with `encode_records`
+pub fn decode_records(data: &[u8]) -> Result<Vec<TrackRecord>, AppError> {
+    let bytes = decompress(data)?;
+    let mut input = Decoder {
+        bytes: &bytes,
+        pos: 0,
+    };
+    if input.byte()? != FORMAT_VERSION {
+        return Err(corrupt("unknown format version"));
+    }
+    let count = input.length()?;
+    let offset = i32::try_from(unzigzag(input.varint()?))
+        .ok()
+        .and_then(FixedOffset::east_opt)
+        .ok_or_else(|| corrupt("invalid UTC offset"))?;
+
+    let timestamps = input
+        .deltas(count)?
+        .into_iter()
+        .map(|ms| {
+            DateTime::from_timestamp_millis(ms)
+                .map(|t| t.with_timezone(&offset).to_rfc3339())
+                .ok_or_else(|| corrupt("timestamp out of range"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let elapsed = input.deltas(count)?;
+    let heart_rate = input.optional(count, 1.0)?;
+    let distance = input.optional(count, METRE_SCALE)?;
+    let altitude = input.optional(count, METRE_SCALE)?;
+    let speed = input.optional(count, SPEED_SCALE)?;
+    let temperature = input.optional(count, TEMPERATURE_SCALE)?;
+    let position_lat = input.optional(count, DEGREE_SCALE)?;
+    let position_long = input.optional(count, DEGREE_SCALE)?;
+    let clean_position_lat = input.optional(count, DEGREE_SCALE)?;
+    let clean_position_long = input.optional(count, DEGREE_SCALE)?;
+    let grade_adjusted_speed = input.optional(count, SPEED_SCALE)?;
+    let corrected_altitude = input.optional(count, METRE_SCALE)?;
+
+    let zones = (0..input.length()?)
+        .map(|_| input.string())
+        .collect::<Result<Vec<_>, _>>()?;
+    let zone_indices = (0..count)
+        .map(|_| input.varint())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut extras: Vec<HashMap<String, f64>> = vec![HashMap::new(); count];
+    for _ in 0..input.length()? {
+        let key = input.string()?;
+        for (record, value) in extras.iter_mut().zip(input.optional(count, EXTRA_SCALE)?) {
+            if let Some(value) = value {
+                record.insert(key.clone(), value);
+            }
+        }
+    }
+
+    timestamps
+        .into_iter()
+        .zip(extras)
+        .enumerate()
+        .map(|(i, (timestamp, extras))| {
+            Ok(TrackRecord {
+                timestamp,
+                elapsed_time: elapsed[i] as f64 / TIME_SCALE,
+                heart_rate: heart_rate[i].map(|hr| hr as i32),
+                distance: distance[i],
+                altitude: altitude[i],
+                speed: speed[i],
+                temperature: temperature[i],
+                position_lat: position_lat[i],
+                position_long: position_long[i],
+                clean_position_lat: clean_position_lat[i],
+                clean_position_long: clean_position_long[i],
+                zone: zones
+                    .get(zone_indices[i] as usize)
+                    .cloned()
+                    .ok_or_else(|| corrupt("zone out of range"))?,
+                grade_adjusted_speed: grade_adjusted_speed[i],
+                corrected_altitude: corrected_altitude[i],
+                extras,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn record(i: usize) -> TrackRecord {
+        let t = i as f64;
+        TrackRecord {
+            timestamp: (Utc.with_ymd_and_hms(2024, 7, 14, 6, 0, 0).unwrap()
+                + chrono::Duration::seconds(i as i64))
+            .to_rfc3339(),
+            elapsed_time: t,
+            heart_rate: if i % 50 == 7 {
+                None
+            } else {
+                Some(110 + (i % 40) as i32)
+            },
+            distance: Some(t * 1.21),
+            altitude: Some(1200.0 + (t / 60.0).sin() * 80.0),
+            speed: Some(1.21 + (t / 17.0).cos() * 0.3),
+            temperature: Some(14.0),
+            position_lat: Some(46.5 + t * 0.000_011),
+            position_long: Some(7.9 + (t / 300.0).sin() * 0.01),
+            zone: format!("zone{}", 1 + i / 1000 % 3),
+            grade_adjusted_speed: Some(1.3 + (t / 23.0).sin() * 0.2),
+            extras: HashMap::from([("cadence".to_string(), 52.0 + (i % 5) as f64)]),
+            ..TrackRecord::default()
+        }
+    }
+
+    fn close(a: Option<f64>, b: Option<f64>, step: f64) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => (a - b).abs() <= step / 2.0 + 1e-9,
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let records: Vec<TrackRecord> = (0..5000).map(record).collect();
+        let decoded = decode_records(&encode_records(&records).unwrap()).unwrap();
+
+        assert_eq!(decoded.len(), records.len());
+        for (a, b) in records.iter().zip(&decoded) {
+            assert_eq!(a.timestamp, b.timestamp);
+            assert_eq!(a.elapsed_time, b.elapsed_time);
+            assert_eq!(a.heart_rate, b.heart_rate);
+            assert_eq!(a.zone, b.zone);
+            assert_eq!(a.extras, b.extras);
+            assert!(close(a.distance, b.distance, 1e-3));
+            assert!(close(a.altitude, b.altitude, 1e-3));
+            assert!(close(a.speed, b.speed, 1e-3));
+            assert!(close(a.position_lat, b.position_lat, 1e-7));
+            assert!(close(a.position_long, b.position_long, 1e-7));
+            assert!(close(a.grade_adjusted_speed, b.grade_adjusted_speed, 1e-3));
+            assert_eq!(b.clean_position_lat, None);
+            assert_eq!(b.corrected_altitude, None);
+        }
+    }
+
+    #[test]
+    fn test_keeps_utc_offset() {
+        let mut records = vec![record(0), record(1)];
+        records[0].timestamp = "2024-07-14T08:00:00+02:00".to_string();
+        records[1].timestamp = "2024-07-14T08:00:01.500+02:00".to_string();
+        let decoded = decode_records(&encode_records(&records).unwrap()).unwrap();
+        assert_eq!(decoded[0].timestamp, records[0].timestamp);
+        assert_eq!(decoded[1].timestamp, records[1].timestamp);
+    }
+
+    #[test]
+    fn test_empty_and_corrupt() {
+        assert!(decode_records(&encode_records(&[]).unwrap())
+            .unwrap()
+            .is_empty());
+
+        let mut bytes = decompress(&encode_records(&[record(0)]).unwrap()).unwrap();
+        bytes.truncate(bytes.len() - 3);
+        assert!(decode_records(&compress(&bytes).unwrap()).is_err());
+        assert!(decode_records(b"not records").is_err());
+    }
+
+    #[test]
+    fn test_compact() {
+        let records: Vec<TrackRecord> = (0..3600).map(record).collect();
+        let encoded = encode_records(&records).unwrap();
+        // An hour at one record per second fits in a few bytes per record
+        assert!(
+            encoded.len() < records.len() * 12,
+            "{} bytes",
+            encoded.len()
+        );
+    }
+}
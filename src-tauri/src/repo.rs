@@ -1,209 +1,2519 @@
+use crate::analytics::{Cell, ColumnKind, ACTIVITY_EXPORT_COLUMNS};
+use crate::backup::BackupSchedule;
+use crate::effort::effort_distance;
+use crate::elevation::{elevation_stats, filter_altitudes, ElevationFilter};
 use crate::error::AppError;
-use crate::parser::ParsedActivity;
-use crate::types::{Activity, ActivityDetail, TrackRecord, ZoneSummary, ZoneTimes};
-use rusqlite::{params, Connection};
+use crate::gear::limit_alerts;
+use crate::geo::BoundingBox;
+use crate::geocode::{activity_places, TRAVERSE_DISTANCE};
+use crate::metadata::{default_title, normalize_tags};
+use crate::parser::{derive_metrics, interval_deltas, ParseOptions, ParsedActivity, ParsedRecord};
+use crate::pois::{closest_approach, NewPoi, PoiKind, MAX_VISIT_RADIUS};
+use crate::polyline::{simplify, CACHED_TOLERANCE};
+use crate::record_store::{decode_records, encode_records, extra_keys};
+use crate::routes::{same_route, ROUTE_TRACK_TOLERANCE};
+use crate::segments::{match_segment, polyline_length};
+use crate::sources::{compress, decompress};
+use crate::spatial::{distance_to_track, track_crosses, track_segments};
+use crate::sports::DEFAULT_TYPE;
+use crate::track::{record_fixes, record_positions, TrackCleaning};
+use crate::trips::{suggest_trips, suggested_name, trip_days, trip_summary, TripCandidate};
+use crate::types::{
+    Activity, ActivityDetail, ActivityFilter, ActivityMetadata, ActivityTypeInfo, AnalyticsFilter,
+    BaggedPoi, Gear, GearDetails, GearUsage, Poi, PoiVisit, Profile, ProfileDetails, Region, Route,
+    RouteAttempt, Segment, SegmentEffort, Tag, TrackRecord, Trip, TripDetail, TripSuggestion,
+    ZoneSummary, ZoneTimes,
+};
+use crate::zones::{zone_thresholds, ZoneModel};
+use chrono::{DateTime, Utc};
+use reverse_geocoder::ReverseGeocoder;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::collections::HashMap;
 
-/// Insert a parsed activity into the database
-pub fn insert_activity(conn: &Connection, activity: &ParsedActivity) -> Result<i64, AppError> {
-    // Check for duplicate
+/// Insert a parsed activity into the database, owned by a profile
+pub fn insert_activity(
+    conn: &Connection,
+    profile_id: i64,
+    activity: &ParsedActivity,
+) -> Result<i64, AppError> {
+    let tx = conn.unchecked_transaction()?;
+    let activity_id = insert_parsed_activity(&tx, profile_id, activity)?;
+    tx.commit()?;
+
+    Ok(activity_id)
+}
+
+fn insert_parsed_activity(
+    conn: &Connection,
+    profile_id: i64,
+    activity: &ParsedActivity,
+) -> Result<i64, AppError> {
+    // Check for a duplicate in the profile, including files merged into another activity
+    let exists: bool = conn.query_row(
+        r#"SELECT EXISTS(SELECT 1 FROM activities WHERE filename = ?1 AND profile_id = ?2)
+               OR EXISTS(SELECT 1 FROM merged_files m JOIN activities a ON a.id = m.activity_id
+                         WHERE m.filename = ?1 AND a.profile_id = ?2)"#,
+        params![activity.filename, profile_id],
+        |row| row.get(0),
+    )?;
+
+    if exists {
+        return Err(AppError::DuplicateActivity(activity.filename.clone()));
+    }
+
+    // Insert activity
+    conn.execute(
+        r#"INSERT INTO activities (filename, activity_type, activity_date, start_time, location, week_start, month_start, total_duration, total_distance, total_records, elevation_gain, max_altitude, min_altitude,
+                                 avg_grade_adjusted_speed, effort_distance, elevation_loss,
+                                 start_location, end_location, high_point_location, admin1, admin2, country_code, start_end_distance, title,
+                                 profile_id)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+        params![
+            activity.filename,
+            activity.activity_type,
+            activity.activity_date.to_string(),
+            activity.start_time,
+            activity.location,
+            activity.week_start.to_string(),
+            activity.month_start.to_string(),
+            activity.total_duration,
+            activity.total_distance,
+            activity.records.len() as i64,
+            activity.elevation_gain,
+            activity.max_altitude,
+            activity.min_altitude,
+            activity.avg_grade_adjusted_speed,
+            activity.effort_distance,
+            activity.elevation_loss,
+            activity.places.start_location,
+            activity.places.end_location,
+            activity.places.high_point_location,
+            activity.places.admin1,
+            activity.places.admin2,
+            activity.places.country_code,
+            activity.places.start_end_distance,
+            activity.title,
+            profile_id,
+        ],
+    )?;
+
+    let activity_id = conn.last_insert_rowid();
+
+    save_zones(conn, activity_id, &activity.zones)?;
+    save_records(
+        conn,
+        activity_id,
+        &activity
+            .records
+            .iter()
+            .map(stored_record)
+            .collect::<Vec<_>>(),
+    )?;
+    save_source_file(conn, activity_id, &activity.source)?;
+    save_track(conn, activity_id, &activity.simplified_track)?;
+    index_track(conn, activity_id, &activity.simplified_track)?;
+    assign_route(conn, activity_id)?;
+    match_segments(conn, activity_id)?;
+    detect_pois(conn, activity_id)?;
+    apply_default_gear(conn, activity_id)?;
+
+    Ok(activity_id)
+}
+
+/// Store an activity's heart rate zone times, replacing any already stored
+fn save_zones(conn: &Connection, id: i64, zones: &ZoneTimes) -> Result<(), AppError> {
+    conn.execute(
+        r#"INSERT OR REPLACE INTO activity_zones (activity_id, zone1_seconds, zone2_seconds, zone3_seconds, zone4_seconds, zone5_seconds)
+           VALUES (?, ?, ?, ?, ?, ?)"#,
+        params![id, zones.zone1, zones.zone2, zones.zone3, zones.zone4, zones.zone5],
+    )?;
+
+    Ok(())
+}
+
+/// Average and maximum heart rate of a set of records
+fn heart_rate_stats(records: &[TrackRecord]) -> (Option<f64>, Option<i32>) {
+    let heart_rates: Vec<i32> = records.iter().filter_map(|r| r.heart_rate).collect();
+    let avg = (!heart_rates.is_empty())
+        .then(|| heart_rates.iter().map(|&hr| hr as f64).sum::<f64>() / heart_rates.len() as f64);
+    (avg, heart_rates.iter().max().copied())
+}
+
+/// Store the track records of an activity, replacing any already stored. Their
+/// heart rate summary is stored alongside, so listings needn't decode them.
+fn save_records(
+    conn: &Connection,
+    activity_id: i64,
+    records: &[TrackRecord],
+) -> Result<(), AppError> {
+    let keys = serde_json::to_string(&extra_keys(records)).unwrap_or_else(|_| "[]".to_string());
+    let (avg_heart_rate, max_heart_rate) = heart_rate_stats(records);
+    conn.execute(
+        r#"INSERT OR REPLACE INTO record_columns (activity_id, record_count, extra_keys, avg_heart_rate, max_heart_rate, data)
+           VALUES (?, ?, ?, ?, ?, ?)"#,
+        params![
+            activity_id,
+            records.len() as i64,
+            keys,
+            avg_heart_rate,
+            max_heart_rate,
+            encode_records(records)?
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Settings key marking that records stored without a heart rate summary have one
+const HEART_RATES_KEY: &str = "heart_rates_built";
+
+/// Summarize the heart rate of every activity's records once after upgrading
+/// to a database that stores the summary
+pub fn backfill_heart_rates(conn: &Connection) -> Result<(), AppError> {
+    if get_setting::<bool>(conn, HEART_RATES_KEY)?.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let ids: Vec<i64> = conn
+        .prepare("SELECT activity_id FROM record_columns")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tx = conn.unchecked_transaction()?;
+    for id in ids {
+        let (avg_heart_rate, max_heart_rate) = heart_rate_stats(&get_records(&tx, id)?);
+        tx.execute(
+            "UPDATE record_columns SET avg_heart_rate = ?, max_heart_rate = ? WHERE activity_id = ?",
+            params![avg_heart_rate, max_heart_rate, id],
+        )?;
+    }
+    set_setting(&tx, HEART_RATES_KEY, &true)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// A parsed record as it is stored
+fn stored_record(record: &ParsedRecord) -> TrackRecord {
+    TrackRecord {
+        timestamp: record.timestamp.to_rfc3339(),
+        elapsed_time: record.elapsed_time,
+        heart_rate: record.heart_rate.map(|hr| hr as i32),
+        distance: record.distance,
+        altitude: record.altitude,
+        speed: record.speed,
+        temperature: record.temperature.map(|t| t as f64),
+        position_lat: record.position_lat,
+        position_long: record.position_long,
+        clean_position_lat: record.clean_position_lat,
+        clean_position_long: record.clean_position_long,
+        zone: record.zone.clone(),
+        grade_adjusted_speed: record.grade_adjusted_speed,
+        corrected_altitude: None,
+        extras: record.extras.clone(),
+    }
+}
+
+/// Store the compressed original file of an activity
+fn save_source_file(conn: &Connection, id: i64, source: &[u8]) -> Result<(), AppError> {
+    if source.is_empty() {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT OR REPLACE INTO source_files (activity_id, size, data) VALUES (?, ?, ?)",
+        params![id, source.len() as i64, compress(source)?],
+    )?;
+
+    Ok(())
+}
+
+/// The original file an activity was imported from, if it was kept
+pub fn get_source_file(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+) -> Result<Option<Vec<u8>>, AppError> {
+    check_activity_profile(conn, profile_id, id)?;
+    let data: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT data FROM source_files WHERE activity_id = ?",
+            [id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    data.map(|data| decompress(&data)).transpose()
+}
+
+/// Ids of a profile's activities whose original file is stored, and the number without one
+pub fn reparseable_activities(
+    conn: &Connection,
+    profile_id: i64,
+) -> Result<(Vec<i64>, usize), AppError> {
+    let ids: Vec<i64> = conn
+        .prepare(
+            r#"SELECT s.activity_id FROM source_files s
+               JOIN activities a ON a.id = s.activity_id
+               WHERE a.profile_id = ?
+               ORDER BY s.activity_id"#,
+        )?
+        .query_map([profile_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let total: usize = conn.query_row(
+        "SELECT COUNT(*) FROM activities WHERE profile_id = ?",
+        [profile_id],
+        |row| row.get(0),
+    )?;
+
+    let without_source = total - ids.len();
+
+    Ok((ids, without_source))
+}
+
+/// Profile an activity belongs to
+pub fn activity_profile(conn: &Connection, id: i64) -> Result<i64, AppError> {
+    conn.query_row(
+        "SELECT profile_id FROM activities WHERE id = ?",
+        [id],
+        |row| row.get(0),
+    )
+    .optional()?
+    .ok_or(AppError::ActivityNotFound(id))
+}
+
+/// Check that an activity belongs to a profile. Other profiles' activities
+/// are reported as not found, like missing ones.
+pub fn check_activity_profile(conn: &Connection, profile_id: i64, id: i64) -> Result<(), AppError> {
+    if activity_profile(conn, id)? != profile_id {
+        return Err(AppError::ActivityNotFound(id));
+    }
+    Ok(())
+}
+
+/// Replace an activity's records and everything derived from them with a new
+/// parse of its original file. The title, description, notes, tags, type and
+/// gear are kept; a DEM elevation correction is cleared for the caller to re-apply.
+pub fn replace_parsed_activity(
+    conn: &Connection,
+    id: i64,
+    activity: &ParsedActivity,
+) -> Result<(), AppError> {
+    let tx = conn.unchecked_transaction()?;
+    update_parsed_activity(&tx, id, activity)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+fn update_parsed_activity(
+    conn: &Connection,
+    id: i64,
+    activity: &ParsedActivity,
+) -> Result<(), AppError> {
+    let updated = conn.execute(
+        r#"UPDATE activities
+           SET activity_date = ?, start_time = ?, location = ?, week_start = ?, month_start = ?,
+               total_duration = ?, total_distance = ?, total_records = ?, elevation_gain = ?, elevation_loss = ?,
+               max_altitude = ?, min_altitude = ?, avg_grade_adjusted_speed = ?, effort_distance = ?,
+               start_location = ?, end_location = ?, high_point_location = ?, admin1 = ?, admin2 = ?,
+               country_code = ?, start_end_distance = ?, elevation_source = 'device',
+               corrected_elevation_gain = NULL, corrected_elevation_loss = NULL,
+               corrected_max_altitude = NULL, corrected_min_altitude = NULL,
+               corrected_effort_distance = NULL, route_id = NULL
+           WHERE id = ?"#,
+        params![
+            activity.activity_date.to_string(),
+            activity.start_time,
+            activity.location,
+            activity.week_start.to_string(),
+            activity.month_start.to_string(),
+            activity.total_duration,
+            activity.total_distance,
+            activity.records.len() as i64,
+            activity.elevation_gain,
+            activity.elevation_loss,
+            activity.max_altitude,
+            activity.min_altitude,
+            activity.avg_grade_adjusted_speed,
+            activity.effort_distance,
+            activity.places.start_location,
+            activity.places.end_location,
+            activity.places.high_point_location,
+            activity.places.admin1,
+            activity.places.admin2,
+            activity.places.country_code,
+            activity.places.start_end_distance,
+            id,
+        ],
+    )?;
+    if updated == 0 {
+        return Err(AppError::ActivityNotFound(id));
+    }
+
+    save_zones(conn, id, &activity.zones)?;
+    save_records(
+        conn,
+        id,
+        &activity
+            .records
+            .iter()
+            .map(stored_record)
+            .collect::<Vec<_>>(),
+    )?;
+    save_track(conn, id, &activity.simplified_track)?;
+    index_track(conn, id, &activity.simplified_track)?;
+    delete_empty_routes(conn)?;
+    assign_route(conn, id)?;
+    conn.execute("DELETE FROM segment_efforts WHERE activity_id = ?", [id])?;
+    match_segments(conn, id)?;
+    detect_pois(conn, id)?;
+
+    Ok(())
+}
+
+/// Fail if an activity's records were replaced since `records` were read from
+/// it along with its start time, as by an edit or re-parse finishing meanwhile
+pub fn check_activity_unchanged(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+    start_time: &str,
+    records: &[TrackRecord],
+) -> Result<(), AppError> {
+    let current: Option<(String, i64)> = conn
+        .query_row(
+            "SELECT start_time, total_records FROM activities WHERE id = ? AND profile_id = ?",
+            [id, profile_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    match current {
+        None => Err(AppError::ActivityNotFound(id)),
+        Some((current_start, count))
+            if current_start != start_time || count != records.len() as i64 =>
+        {
+            Err(AppError::InvalidArgument(format!(
+                "Activity {} changed while it was being edited",
+                id
+            )))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Store an activity rebuilt from part of its records as `parts[0]`, and any
+/// further parts as new activities of the same profile with its tags, gear
+/// and trip. The original file is dropped, since re-parsing it would undo the
+/// edit. Returns the ids of the parts.
+pub fn save_edited_activity(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+    parts: &[ParsedActivity],
+) -> Result<Vec<i64>, AppError> {
+    let Some((first, rest)) = parts.split_first() else {
+        return Err(AppError::InvalidArgument(
+            "No activity parts to save".to_string(),
+        ));
+    };
+    check_activity_profile(conn, profile_id, id)?;
+
+    let tx = conn.unchecked_transaction()?;
+    update_parsed_activity(&tx, id, first)?;
+    tx.execute("DELETE FROM source_files WHERE activity_id = ?", [id])?;
+
+    let mut ids = vec![id];
+    for part in rest {
+        let part_id = insert_parsed_activity(&tx, profile_id, part)?;
+        tx.execute(
+            "INSERT OR IGNORE INTO activity_tags (activity_id, tag_id) SELECT ?, tag_id FROM activity_tags WHERE activity_id = ?",
+            params![part_id, id],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO activity_gear (activity_id, gear_id) SELECT ?, gear_id FROM activity_gear WHERE activity_id = ?",
+            params![part_id, id],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO trip_activities (activity_id, trip_id) SELECT ?, trip_id FROM trip_activities WHERE activity_id = ?",
+            params![part_id, id],
+        )?;
+        ids.push(part_id);
+    }
+    tx.commit()?;
+
+    Ok(ids)
+}
+
+/// Store an activity's bounding box and its track segments in the R-tree
+fn index_track(conn: &Connection, id: i64, points: &[(f64, f64)]) -> Result<(), AppError> {
+    conn.execute("DELETE FROM track_segments WHERE activity_id = ?", [id])?;
+
+    let bounds = BoundingBox::from_points(points);
+    conn.execute(
+        "UPDATE activities SET min_lat = ?, max_lat = ?, min_lon = ?, max_lon = ? WHERE id = ?",
+        params![
+            bounds.map(|b| b.min_lat),
+            bounds.map(|b| b.max_lat),
+            bounds.map(|b| b.min_lon),
+            bounds.map(|b| b.max_lon),
+            id,
+        ],
+    )?;
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO track_segments (min_lat, max_lat, min_lon, max_lon, activity_id) VALUES (?, ?, ?, ?, ?)",
+    )?;
+    for segment in track_segments(points) {
+        stmt.execute(params![
+            segment.min_lat,
+            segment.max_lat,
+            segment.min_lon,
+            segment.max_lon,
+            id
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Settings key marking that activities imported before the spatial index have been indexed
+const SPATIAL_INDEX_KEY: &str = "spatial_index_built";
+
+/// Index every activity once after upgrading to a database with the spatial index
+pub fn backfill_spatial_index(conn: &Connection) -> Result<(), AppError> {
+    if get_setting::<bool>(conn, SPATIAL_INDEX_KEY)?.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let ids: Vec<i64> = conn
+        .prepare("SELECT id FROM activities WHERE min_lat IS NULL")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tx = conn.unchecked_transaction()?;
+    for id in ids {
+        let points = get_activity_track(&tx, id, CACHED_TOLERANCE)?;
+        index_track(&tx, id, &points)?;
+    }
+    set_setting(&tx, SPATIAL_INDEX_KEY, &true)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Activities with a track segment whose bounding box intersects `bbox`,
+/// optionally only those of one profile
+fn spatial_candidates(
+    conn: &Connection,
+    bbox: &BoundingBox,
+    profile_id: Option<i64>,
+) -> Result<Vec<i64>, AppError> {
+    let ids = conn
+        .prepare(
+            r#"SELECT DISTINCT activity_id FROM track_segments
+               WHERE max_lat >= ?1 AND min_lat <= ?2 AND max_lon >= ?3 AND min_lon <= ?4
+                 AND (?5 IS NULL OR activity_id IN (SELECT id FROM activities WHERE profile_id = ?5))"#,
+        )?
+        .query_map(
+            params![bbox.min_lat, bbox.max_lat, bbox.min_lon, bbox.max_lon, profile_id],
+            |row| row.get(0),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ids)
+}
+
+fn activities_by_ids(
+    conn: &Connection,
+    profile_id: i64,
+    ids: &[i64],
+) -> Result<Vec<Activity>, AppError> {
+    let mut activities = ids
+        .iter()
+        .map(|&id| get_activity_summary(conn, profile_id, id))
+        .collect::<Result<Vec<_>, _>>()?;
+    activities.sort_by(|a, b| b.start_time.cmp(&a.start_time));
+
+    Ok(activities)
+}
+
+/// A profile's activities whose track passes through a bounding box
+pub fn find_activities_in_bbox(
+    conn: &Connection,
+    profile_id: i64,
+    bbox: &BoundingBox,
+) -> Result<Vec<Activity>, AppError> {
+    let mut matches = Vec::new();
+    for id in spatial_candidates(conn, bbox, Some(profile_id))? {
+        if track_crosses(&get_activity_track(conn, id, CACHED_TOLERANCE)?, bbox) {
+            matches.push(id);
+        }
+    }
+
+    activities_by_ids(conn, profile_id, &matches)
+}
+
+/// A profile's activities whose track passes within `radius` metres of a position
+pub fn find_activities_near(
+    conn: &Connection,
+    profile_id: i64,
+    lat: f64,
+    lon: f64,
+    radius: f64,
+) -> Result<Vec<Activity>, AppError> {
+    let mut matches = Vec::new();
+    for id in spatial_candidates(
+        conn,
+        &BoundingBox::around(lat, lon, radius),
+        Some(profile_id),
+    )? {
+        let points = get_activity_track(conn, id, CACHED_TOLERANCE)?;
+        if distance_to_track(lat, lon, &points).is_some_and(|d| d <= radius) {
+            matches.push(id);
+        }
+    }
+
+    activities_by_ids(conn, profile_id, &matches)
+}
+
+/// Match an activity against existing routes, or against unmatched
+/// activities to start a new route. Only activities of the same profile are
+/// considered. Returns the route it was assigned to.
+pub fn assign_route(conn: &Connection, id: i64) -> Result<Option<i64>, AppError> {
+    let track = get_activity_track(conn, id, ROUTE_TRACK_TOLERANCE)?;
+    let Some(bbox) = BoundingBox::from_points(&track) else {
+        return Ok(None);
+    };
+
+    // Overlapping activities that are either unmatched or the first attempt of
+    // their route, checking existing routes first
+    let candidates: Vec<(i64, Option<i64>)> = conn
+        .prepare(
+            r#"SELECT a.id, a.route_id FROM activities a
+               WHERE a.id != ?1
+                 AND a.profile_id IS (SELECT profile_id FROM activities WHERE id = ?1)
+                 AND a.max_lat >= ?2 AND a.min_lat <= ?3 AND a.max_lon >= ?4 AND a.min_lon <= ?5
+                 AND (a.route_id IS NULL OR a.id = (
+                     SELECT b.id FROM activities b WHERE b.route_id = a.route_id ORDER BY b.start_time LIMIT 1))
+               ORDER BY a.route_id IS NULL, a.start_time"#,
+        )?
+        .query_map(
+            params![id, bbox.min_lat, bbox.max_lat, bbox.min_lon, bbox.max_lon],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (candidate, route_id) in candidates {
+        if !same_route(
+            &get_activity_track(conn, candidate, ROUTE_TRACK_TOLERANCE)?,
+            &track,
+        ) {
+            continue;
+        }
+
+        let route_id = match route_id {
+            Some(route_id) => route_id,
+            None => {
+                let location: Option<String> = conn.query_row(
+                    "SELECT location FROM activities WHERE id = ?",
+                    [candidate],
+                    |row| row.get(0),
+                )?;
+                conn.execute(
+                    "INSERT INTO routes (name) VALUES (?)",
+                    [location.unwrap_or_else(|| "Route".to_string())],
+                )?;
+                let route_id = conn.last_insert_rowid();
+                conn.execute(
+                    "UPDATE activities SET route_id = ? WHERE id = ?",
+                    [route_id, candidate],
+                )?;
+                route_id
+            }
+        };
+        conn.execute(
+            "UPDATE activities SET route_id = ? WHERE id = ?",
+            [route_id, id],
+        )?;
+        return Ok(Some(route_id));
+    }
+
+    Ok(None)
+}
+
+/// Settings key marking that activities imported before route matching have been matched
+const ROUTES_KEY: &str = "routes_built";
+
+/// Match every unmatched activity once, oldest first, after upgrading to a
+/// database with routes
+pub fn backfill_routes(conn: &Connection) -> Result<(), AppError> {
+    if get_setting::<bool>(conn, ROUTES_KEY)?.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let ids: Vec<i64> = conn
+        .prepare("SELECT id FROM activities WHERE route_id IS NULL ORDER BY start_time")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tx = conn.unchecked_transaction()?;
+    for id in ids {
+        // An earlier iteration may already have matched this activity
+        let route_id: Option<i64> = tx.query_row(
+            "SELECT route_id FROM activities WHERE id = ?",
+            [id],
+            |row| row.get(0),
+        )?;
+        if route_id.is_none() {
+            assign_route(&tx, id)?;
+        }
+    }
+    set_setting(&tx, ROUTES_KEY, &true)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+fn delete_empty_routes(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "DELETE FROM routes WHERE NOT EXISTS (SELECT 1 FROM activities WHERE route_id = routes.id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// List a profile's routes, most recently attempted first
+pub fn list_routes(conn: &Connection, profile_id: i64) -> Result<Vec<Route>, AppError> {
+    let mut stmt = conn.prepare(
+        r#"SELECT r.id, r.name, COUNT(a.id), MIN(a.total_duration), MAX(a.start_time),
+                  (SELECT b.total_distance FROM activities b WHERE b.route_id = r.id ORDER BY b.start_time LIMIT 1)
+           FROM routes r
+           JOIN activities a ON a.route_id = r.id
+           WHERE a.profile_id = ?
+           GROUP BY r.id
+           ORDER BY MAX(a.start_time) DESC"#,
+    )?;
+
+    let routes = stmt
+        .query_map([profile_id], |row| {
+            Ok(Route {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                attempt_count: row.get(2)?,
+                best_duration: row.get(3)?,
+                last_attempt: row.get(4)?,
+                distance: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(routes)
+}
+
+pub fn rename_route(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+    name: &str,
+) -> Result<(), AppError> {
+    let updated = conn.execute(
+        r#"UPDATE routes SET name = ?
+           WHERE id = ? AND id IN (SELECT route_id FROM activities WHERE profile_id = ?)"#,
+        params![name, id, profile_id],
+    )?;
+    if updated == 0 {
+        return Err(AppError::NotFound(format!("Route {}", id)));
+    }
+
+    Ok(())
+}
+
+/// All attempts of a route in date order, with heart rate and time compared to the fastest
+pub fn get_route_attempts(
+    conn: &Connection,
+    profile_id: i64,
+    route_id: i64,
+) -> Result<Vec<RouteAttempt>, AppError> {
+    let activities = conn
+        .prepare(&format!(
+            r#"SELECT {}
+               FROM activities a
+               JOIN activity_zones z ON z.activity_id = a.id
+               WHERE a.route_id = ? AND a.profile_id = ?
+               ORDER BY a.start_time"#,
+            ACTIVITY_COLUMNS
+        ))?
+        .query_map([route_id, profile_id], activity_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if activities.is_empty() {
+        return Err(AppError::NotFound(format!("Route {}", route_id)));
+    }
+
+    let best = activities
+        .iter()
+        .map(|a| a.total_duration)
+        .fold(f64::INFINITY, f64::min);
+    let mut durations: Vec<f64> = activities.iter().map(|a| a.total_duration).collect();
+    durations.sort_by(f64::total_cmp);
+
+    let mut heart_rates = conn.prepare(
+        "SELECT avg_heart_rate, max_heart_rate FROM record_columns WHERE activity_id = ?",
+    )?;
+    activities
+        .into_iter()
+        .map(|activity| {
+            let (avg_heart_rate, max_heart_rate) = heart_rates
+                .query_row([activity.id], |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()?
+                .unwrap_or((None, None));
+            let rank = durations.partition_point(|d| *d < activity.total_duration) as i32 + 1;
+
+            Ok(RouteAttempt {
+                behind_best: activity.total_duration - best,
+                rank,
+                avg_heart_rate,
+                max_heart_rate,
+                activity,
+            })
+        })
+        .collect()
+}
+
+fn trip_exists(conn: &Connection, profile_id: i64, id: i64) -> Result<(), AppError> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM trips WHERE id = ? AND profile_id = ?)",
+        [id, profile_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(AppError::NotFound(format!("Trip {}", id)));
+    }
+    Ok(())
+}
+
+fn delete_empty_trips(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "DELETE FROM trips WHERE NOT EXISTS (SELECT 1 FROM trip_activities WHERE trip_id = trips.id)",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Create a trip of a profile's activities, or update its name and activities
+/// when `id` is given. Activities in another trip move to this one. Returns the trip id.
+pub fn save_trip(
+    conn: &Connection,
+    profile_id: i64,
+    id: Option<i64>,
+    name: &str,
+    activity_ids: &[i64],
+) -> Result<i64, AppError> {
+    if activity_ids.is_empty() {
+        return Err(AppError::InvalidArgument(
+            "A trip needs at least one activity".to_string(),
+        ));
+    }
+    for &activity_id in activity_ids {
+        check_activity_profile(conn, profile_id, activity_id)?;
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    if let Some(id) = id {
+        trip_exists(&tx, profile_id, id)?;
+    }
+    tx.execute(
+        r#"INSERT INTO trips (id, name, profile_id) VALUES (?, ?, ?)
+           ON CONFLICT (id) DO UPDATE SET name = excluded.name"#,
+        params![id, name, profile_id],
+    )?;
+    let id = id.unwrap_or_else(|| tx.last_insert_rowid());
+
+    tx.execute("DELETE FROM trip_activities WHERE trip_id = ?", [id])?;
+    for &activity_id in activity_ids {
+        tx.execute(
+            "INSERT OR REPLACE INTO trip_activities (activity_id, trip_id) VALUES (?, ?)",
+            params![activity_id, id],
+        )?;
+    }
+    delete_empty_trips(&tx)?;
+    tx.commit()?;
+
+    Ok(id)
+}
+
+/// Delete a trip, keeping its activities
+pub fn delete_trip(conn: &Connection, profile_id: i64, id: i64) -> Result<(), AppError> {
+    trip_exists(conn, profile_id, id)?;
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM trip_activities WHERE trip_id = ?", [id])?;
+    tx.execute("DELETE FROM trips WHERE id = ?", [id])?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Activities of a trip in start time order
+fn get_trip_activities(conn: &Connection, id: i64) -> Result<Vec<Activity>, AppError> {
+    let activities = conn
+        .prepare(&format!(
+            r#"SELECT {}
+               FROM activities a
+               JOIN activity_zones z ON z.activity_id = a.id
+               JOIN trip_activities ta ON ta.activity_id = a.id
+               WHERE ta.trip_id = ?
+               ORDER BY a.start_time"#,
+            ACTIVITY_COLUMNS
+        ))?
+        .query_map([id], activity_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(activities)
+}
+
+/// List a profile's trips with their totals, most recent first
+pub fn list_trips(conn: &Connection, profile_id: i64) -> Result<Vec<Trip>, AppError> {
+    let trips: Vec<(i64, String)> = conn
+        .prepare("SELECT id, name FROM trips WHERE profile_id = ?")?
+        .query_map([profile_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut trips = trips
+        .into_iter()
+        .map(|(id, name)| trip_summary(id, &name, &get_trip_activities(conn, id)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    trips.sort_by(|a, b| b.start_date.cmp(&a.start_date));
+
+    Ok(trips)
+}
+
+/// A trip with its totals, day-by-day breakdown and activities
+pub fn get_trip(conn: &Connection, profile_id: i64, id: i64) -> Result<TripDetail, AppError> {
+    trip_exists(conn, profile_id, id)?;
+    let name: String = conn.query_row("SELECT name FROM trips WHERE id = ?", [id], |row| {
+        row.get(0)
+    })?;
+    let activities = get_trip_activities(conn, id)?;
+
+    Ok(TripDetail {
+        trip: trip_summary(id, &name, &activities)?,
+        days: trip_days(&activities)?,
+        activities,
+    })
+}
+
+/// Simplified tracks of a trip's activities joined in start time order
+pub fn get_trip_track(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+    tolerance: f64,
+) -> Result<Vec<(f64, f64)>, AppError> {
+    trip_exists(conn, profile_id, id)?;
+
+    let mut points = Vec::new();
+    for activity in get_trip_activities(conn, id)? {
+        points.extend(get_activity_track(conn, activity.id, tolerance)?);
+    }
+    Ok(points)
+}
+
+/// Trips suggested from a profile's activities not yet in a trip, where
+/// activities on consecutive days each start where the previous one ended
+pub fn list_trip_suggestions(
+    conn: &Connection,
+    profile_id: i64,
+) -> Result<Vec<TripSuggestion>, AppError> {
+    let activities = conn
+        .prepare(&format!(
+            r#"SELECT {}
+               FROM activities a
+               JOIN activity_zones z ON z.activity_id = a.id
+               WHERE a.profile_id = ?
+                 AND NOT EXISTS (SELECT 1 FROM trip_activities ta WHERE ta.activity_id = a.id)
+               ORDER BY a.start_time"#,
+            ACTIVITY_COLUMNS
+        ))?
+        .query_map([profile_id], activity_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let candidates = activities
+        .iter()
+        .map(|a| {
+            TripCandidate::new(
+                a.id,
+                &a.activity_date,
+                &get_activity_track(conn, a.id, CACHED_TOLERANCE)?,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut by_id: HashMap<i64, Activity> = activities.into_iter().map(|a| (a.id, a)).collect();
+    let suggestions = suggest_trips(&candidates)
+        .into_iter()
+        .map(|ids| {
+            let activities: Vec<Activity> = ids.iter().filter_map(|id| by_id.remove(id)).collect();
+            TripSuggestion {
+                name: suggested_name(&activities),
+                activities,
+            }
+        })
+        .collect();
+
+    Ok(suggestions)
+}
+
+/// Use DEM-corrected altitude in place of the device altitude when that is the activity's source
+pub fn apply_elevation_source(records: &mut [TrackRecord], source: &str) {
+    if source == "dem" {
+        for record in records {
+            record.altitude = record.corrected_altitude;
+        }
+    }
+}
+
+/// Positions of the records between two indices of an activity, for creating a segment
+pub fn get_track_section(
+    conn: &Connection,
+    profile_id: i64,
+    activity_id: i64,
+    start_index: usize,
+    end_index: usize,
+) -> Result<Vec<(f64, f64)>, AppError> {
+    check_activity_profile(conn, profile_id, activity_id)?;
+    let records = get_records(conn, activity_id)?;
+    if start_index >= end_index || end_index >= records.len() {
+        return Err(AppError::InvalidArgument(format!(
+            "Invalid record range {}..{} for activity with {} records",
+            start_index,
+            end_index,
+            records.len()
+        )));
+    }
+
+    Ok(record_positions(&records[start_index..=end_index]))
+}
+
+/// Save a segment of a profile and match it against the profile's activities
+pub fn create_segment(
+    conn: &Connection,
+    profile_id: i64,
+    name: &str,
+    points: &[(f64, f64)],
+) -> Result<i64, AppError> {
+    let points = simplify(points, CACHED_TOLERANCE);
+    let bbox = match BoundingBox::from_points(&points) {
+        Some(bbox) if points.len() >= 2 => bbox,
+        _ => {
+            return Err(AppError::InvalidArgument(
+                "A segment needs at least two positions".to_string(),
+            ))
+        }
+    };
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        r#"INSERT INTO segments (name, points, distance, min_lat, max_lat, min_lon, max_lon, profile_id)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?)"#,
+        params![
+            name,
+            serde_json::to_string(&points).unwrap_or_else(|_| "[]".to_string()),
+            polyline_length(&points),
+            bbox.min_lat,
+            bbox.max_lat,
+            bbox.min_lon,
+            bbox.max_lon,
+            profile_id,
+        ],
+    )?;
+    let segment_id = tx.last_insert_rowid();
+
+    for activity_id in spatial_candidates(&tx, &bbox, Some(profile_id))? {
+        save_segment_efforts(&tx, segment_id, &points, activity_id)?;
+    }
+    tx.commit()?;
+
+    Ok(segment_id)
+}
+
+/// Match an activity against every segment of its profile its bounding box overlaps
+pub fn match_segments(conn: &Connection, activity_id: i64) -> Result<(), AppError> {
+    let segments: Vec<(i64, String)> = conn
+        .prepare(
+            r#"SELECT s.id, s.points FROM segments s
+               JOIN activities a ON a.id = ? AND a.profile_id = s.profile_id
+               WHERE s.max_lat >= a.min_lat AND s.min_lat <= a.max_lat
+                 AND s.max_lon >= a.min_lon AND s.min_lon <= a.max_lon"#,
+        )?
+        .query_map([activity_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (segment_id, json) in segments {
+        let points: Vec<(f64, f64)> = serde_json::from_str(&json).unwrap_or_default();
+        save_segment_efforts(conn, segment_id, &points, activity_id)?;
+    }
+
+    Ok(())
+}
+
+fn save_segment_efforts(
+    conn: &Connection,
+    segment_id: i64,
+    points: &[(f64, f64)],
+    activity_id: i64,
+) -> Result<(), AppError> {
+    let elevation_source: String = conn.query_row(
+        "SELECT elevation_source FROM activities WHERE id = ?",
+        [activity_id],
+        |row| row.get(0),
+    )?;
+    let mut records = get_records(conn, activity_id)?;
+    apply_elevation_source(&mut records, &elevation_source);
+
+    conn.execute(
+        "DELETE FROM segment_efforts WHERE segment_id = ? AND activity_id = ?",
+        [segment_id, activity_id],
+    )?;
+    let mut stmt = conn.prepare(
+        r#"INSERT INTO segment_efforts (segment_id, activity_id, start_time, elapsed_time, avg_heart_rate, vertical_speed)
+           VALUES (?, ?, ?, ?, ?, ?)"#,
+    )?;
+    for effort in match_segment(points, &records) {
+        stmt.execute(params![
+            segment_id,
+            activity_id,
+            records[effort.start_index].timestamp,
+            effort.elapsed_time,
+            effort.avg_heart_rate,
+            effort.vertical_speed,
+        ])?;
+    }
+
+    Ok(())
+}
+
+fn segment_exists(conn: &Connection, profile_id: i64, id: i64) -> Result<(), AppError> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM segments WHERE id = ? AND profile_id = ?)",
+        [id, profile_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(AppError::NotFound(format!("Segment {}", id)));
+    }
+    Ok(())
+}
+
+/// List a profile's segments with effort counts and best times
+pub fn list_segments(conn: &Connection, profile_id: i64) -> Result<Vec<Segment>, AppError> {
+    let mut stmt = conn.prepare(
+        r#"SELECT s.id, s.name, s.points, s.distance, COUNT(e.id), MIN(e.elapsed_time)
+           FROM segments s
+           LEFT JOIN segment_efforts e ON e.segment_id = s.id
+           WHERE s.profile_id = ?
+           GROUP BY s.id
+           ORDER BY s.name"#,
+    )?;
+
+    let segments = stmt
+        .query_map([profile_id], |row| {
+            let json: String = row.get(2)?;
+            let points: Vec<(f64, f64)> = serde_json::from_str(&json).unwrap_or_default();
+            Ok(Segment {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                points: points.into_iter().map(|(lat, lon)| [lat, lon]).collect(),
+                distance: row.get(3)?,
+                effort_count: row.get(4)?,
+                best_time: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(segments)
+}
+
+/// A profile's efforts on a segment, fastest first
+pub fn get_segment_leaderboard(
+    conn: &Connection,
+    profile_id: i64,
+    segment_id: i64,
+) -> Result<Vec<SegmentEffort>, AppError> {
+    segment_exists(conn, profile_id, segment_id)?;
+
+    let mut stmt = conn.prepare(
+        r#"SELECT e.id, e.segment_id, e.activity_id, a.activity_date, e.start_time, e.elapsed_time,
+                  e.avg_heart_rate, e.vertical_speed,
+                  RANK() OVER (ORDER BY e.elapsed_time)
+           FROM segment_efforts e
+           JOIN activities a ON a.id = e.activity_id
+           WHERE e.segment_id = ? AND a.profile_id = ?
+           ORDER BY e.elapsed_time, e.start_time"#,
+    )?;
+
+    let efforts = stmt
+        .query_map([segment_id, profile_id], |row| {
+            Ok(SegmentEffort {
+                id: row.get(0)?,
+                segment_id: row.get(1)?,
+                activity_id: row.get(2)?,
+                activity_date: row.get(3)?,
+                start_time: row.get(4)?,
+                elapsed_time: row.get(5)?,
+                avg_heart_rate: row.get(6)?,
+                vertical_speed: row.get(7)?,
+                rank: row.get(8)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(efforts)
+}
+
+pub fn delete_segment(conn: &Connection, profile_id: i64, id: i64) -> Result<(), AppError> {
+    segment_exists(conn, profile_id, id)?;
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM segment_efforts WHERE segment_id = ?", [id])?;
+    tx.execute("DELETE FROM segments WHERE id = ?", [id])?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Add POIs to the database, skipping ones already present, and detect visits
+/// to them in stored activities. Returns the number of new POIs.
+pub fn import_pois(conn: &Connection, pois: &[NewPoi]) -> Result<usize, AppError> {
+    let tx = conn.unchecked_transaction()?;
+    let mut added = 0;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR IGNORE INTO pois (name, kind, lat, lon, elevation) VALUES (?, ?, ?, ?, ?)",
+        )?;
+        for poi in pois {
+            added += stmt.execute(params![
+                poi.name,
+                poi.kind.as_str(),
+                poi.lat,
+                poi.lon,
+                poi.elevation
+            ])?;
+        }
+    }
+
+    let positions: Vec<(f64, f64)> = pois.iter().map(|p| (p.lat, p.lon)).collect();
+    if let Some(bbox) = BoundingBox::from_points(&positions) {
+        let bbox = bbox.expand(MAX_VISIT_RADIUS);
+        let ids: Vec<i64> = tx
+            .prepare(
+                r#"SELECT id FROM activities
+                   WHERE max_lat >= ? AND min_lat <= ? AND max_lon >= ? AND min_lon <= ?"#,
+            )?
+            .query_map(
+                params![bbox.min_lat, bbox.max_lat, bbox.min_lon, bbox.max_lon],
+                |row| row.get(0),
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        for id in ids {
+            detect_pois(&tx, id)?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(added)
+}
+
+/// Record the POIs an activity's track passed within their visit radius
+pub fn detect_pois(conn: &Connection, activity_id: i64) -> Result<(), AppError> {
+    conn.execute(
+        "DELETE FROM activity_pois WHERE activity_id = ?",
+        [activity_id],
+    )?;
+
+    let bounds: Option<(f64, f64, f64, f64)> = conn.query_row(
+        "SELECT min_lat, max_lat, min_lon, max_lon FROM activities WHERE id = ?",
+        [activity_id],
+        |row| {
+            Ok(match (row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?) {
+                (Some(a), Some(b), Some(c), Some(d)) => Some((a, b, c, d)),
+                _ => None,
+            })
+        },
+    )?;
+    let Some((min_lat, max_lat, min_lon, max_lon)) = bounds else {
+        return Ok(());
+    };
+    let bbox = BoundingBox {
+        min_lat,
+        max_lat,
+        min_lon,
+        max_lon,
+    }
+    .expand(MAX_VISIT_RADIUS);
+
+    let candidates: Vec<(i64, String, f64, f64)> = conn
+        .prepare(
+            r#"SELECT id, kind, lat, lon FROM pois
+               WHERE lat BETWEEN ? AND ? AND lon BETWEEN ? AND ?"#,
+        )?
+        .query_map(
+            params![bbox.min_lat, bbox.max_lat, bbox.min_lon, bbox.max_lon],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+    if candidates.is_empty() {
+        return Ok(());
+    }
+
+    let records = get_records(conn, activity_id)?;
+    let fixes = record_fixes(&records);
+    let mut stmt = conn.prepare(
+        "INSERT INTO activity_pois (activity_id, poi_id, visited_at, distance) VALUES (?, ?, ?, ?)",
+    )?;
+    for (poi_id, kind, lat, lon) in candidates {
+        let Some(kind) = PoiKind::parse(&kind) else {
+            continue;
+        };
+        if let Some((index, distance)) = closest_approach(&fixes, lat, lon) {
+            if distance <= kind.visit_radius() {
+                stmt.execute(params![
+                    activity_id,
+                    poi_id,
+                    records[index].timestamp,
+                    distance
+                ])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const POI_COLUMNS: &str = "p.id, p.name, p.kind, p.lat, p.lon, p.elevation";
+
+fn poi_from_row(row: &Row) -> rusqlite::Result<Poi> {
+    Ok(Poi {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        kind: row.get(2)?,
+        lat: row.get(3)?,
+        lon: row.get(4)?,
+        elevation: row.get(5)?,
+    })
+}
+
+fn poi_visits(
+    conn: &Connection,
+    filter: &str,
+    id: i64,
+    profile_id: Option<i64>,
+    order: &str,
+) -> Result<Vec<PoiVisit>, AppError> {
+    let mut stmt = conn.prepare(&format!(
+        r#"SELECT {}, v.activity_id, a.activity_date, v.visited_at, v.distance
+           FROM activity_pois v
+           JOIN pois p ON p.id = v.poi_id
+           JOIN activities a ON a.id = v.activity_id
+           WHERE {} = ?1 AND (?2 IS NULL OR a.profile_id = ?2)
+           ORDER BY v.visited_at {}"#,
+        POI_COLUMNS, filter, order
+    ))?;
+
+    let visits = stmt
+        .query_map(params![id, profile_id], |row| {
+            Ok(PoiVisit {
+                poi: poi_from_row(row)?,
+                activity_id: row.get(6)?,
+                activity_date: row.get(7)?,
+                visited_at: row.get(8)?,
+                distance: row.get(9)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(visits)
+}
+
+/// POIs visited during an activity, in the order they were passed
+pub fn get_activity_pois(
+    conn: &Connection,
+    profile_id: i64,
+    activity_id: i64,
+) -> Result<Vec<PoiVisit>, AppError> {
+    check_activity_profile(conn, profile_id, activity_id)?;
+    poi_visits(conn, "v.activity_id", activity_id, None, "ASC")
+}
+
+/// A profile's visit history of a POI, most recent first
+pub fn get_poi_visits(
+    conn: &Connection,
+    profile_id: i64,
+    poi_id: i64,
+) -> Result<Vec<PoiVisit>, AppError> {
+    poi_visits(conn, "v.poi_id", poi_id, Some(profile_id), "DESC")
+}
+
+/// Every POI a profile visited at least once, optionally of a single kind,
+/// most recently visited first
+pub fn list_bagged_pois(
+    conn: &Connection,
+    profile_id: i64,
+    kind: Option<PoiKind>,
+) -> Result<Vec<BaggedPoi>, AppError> {
+    let mut stmt = conn.prepare(&format!(
+        r#"SELECT {}, COUNT(*), MIN(v.visited_at), MAX(v.visited_at)
+           FROM activity_pois v
+           JOIN pois p ON p.id = v.poi_id
+           JOIN activities a ON a.id = v.activity_id
+           WHERE a.profile_id = ?2 AND (?1 IS NULL OR p.kind = ?1)
+           GROUP BY p.id
+           ORDER BY MAX(v.visited_at) DESC"#,
+        POI_COLUMNS
+    ))?;
+
+    let pois = stmt
+        .query_map(params![kind.map(PoiKind::as_str), profile_id], |row| {
+            Ok(BaggedPoi {
+                poi: poi_from_row(row)?,
+                visit_count: row.get(6)?,
+                first_visit: row.get(7)?,
+                last_visit: row.get(8)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(pois)
+}
+
+fn save_track(conn: &Connection, id: i64, points: &[(f64, f64)]) -> Result<(), AppError> {
+    let json = serde_json::to_string(points).unwrap_or_else(|_| "[]".to_string());
+    conn.execute(
+        "INSERT OR REPLACE INTO activity_tracks (activity_id, tolerance, points) VALUES (?, ?, ?)",
+        params![id, CACHED_TOLERANCE, json],
+    )?;
+
+    Ok(())
+}
+
+/// Get an activity's track simplified to `tolerance` metres, from the cached
+/// track where possible. Activities imported before the cache existed are
+/// filled in on first use.
+pub fn get_activity_track(
+    conn: &Connection,
+    id: i64,
+    tolerance: f64,
+) -> Result<Vec<(f64, f64)>, AppError> {
+    let cached: Option<(f64, String)> = conn
+        .query_row(
+            "SELECT tolerance, points FROM activity_tracks WHERE activity_id = ?",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    if let Some((cached_tolerance, json)) = &cached {
+        if tolerance >= *cached_tolerance {
+            let points: Vec<(f64, f64)> = serde_json::from_str(json).unwrap_or_default();
+            return Ok(simplify(&points, tolerance));
+        }
+    }
+
+    activity_profile(conn, id)?;
+    let positions = record_positions(&get_records(conn, id)?);
+    if cached.is_none() {
+        save_track(conn, id, &simplify(&positions, CACHED_TOLERANCE))?;
+    }
+
+    Ok(simplify(&positions, tolerance))
+}
+
+/// Activity summary columns, in the order expected by `activity_from_row`
+const ACTIVITY_COLUMNS: &str = r#"a.id, a.filename, a.activity_type, a.activity_date, a.total_duration,
+       z.zone1_seconds, z.zone2_seconds, z.zone3_seconds, z.zone4_seconds, z.zone5_seconds,
+       CASE WHEN a.elevation_source = 'dem' THEN a.corrected_elevation_gain ELSE a.elevation_gain END,
+       CASE WHEN a.elevation_source = 'dem' THEN a.corrected_max_altitude ELSE a.max_altitude END,
+       CASE WHEN a.elevation_source = 'dem' THEN a.corrected_min_altitude ELSE a.min_altitude END,
+       a.start_time, a.total_distance, a.location, a.avg_grade_adjusted_speed,
+       CASE WHEN a.elevation_source = 'dem' THEN a.corrected_effort_distance ELSE a.effort_distance END,
+       CASE WHEN a.elevation_source = 'dem' THEN a.corrected_elevation_loss ELSE a.elevation_loss END,
+       a.elevation_source, a.route_id,
+       a.start_location, a.end_location, a.high_point_location, a.admin1, a.admin2, a.country_code,
+       a.start_end_distance, COALESCE(a.title, a.filename), a.description, a.notes,
+       (SELECT json_group_array(name) FROM (
+            SELECT t.name FROM activity_tags at JOIN tags t ON t.id = at.tag_id
+            WHERE at.activity_id = a.id ORDER BY t.name))"#;
+
+fn activity_from_row(row: &Row) -> rusqlite::Result<Activity> {
+    let tags_json: String = row.get(31)?;
+
+    Ok(Activity {
+        id: row.get(0)?,
+        filename: row.get(1)?,
+        title: row.get(28)?,
+        description: row.get(29)?,
+        notes: row.get(30)?,
+        tags: serde_json::from_str(&tags_json).unwrap_or_default(),
+        activity_type: row.get(2)?,
+        activity_date: row.get(3)?,
+        total_duration: row.get(4)?,
+        zones: ZoneTimes {
+            zone1: row.get(5)?,
+            zone2: row.get(6)?,
+            zone3: row.get(7)?,
+            zone4: row.get(8)?,
+            zone5: row.get(9)?,
+        },
+        elevation_gain: row.get(10)?,
+        max_altitude: row.get(11)?,
+        min_altitude: row.get(12)?,
+        start_time: row.get(13)?,
+        total_distance: row.get(14)?,
+        location: row.get(15)?,
+        avg_grade_adjusted_speed: row.get(16)?,
+        effort_distance: row.get(17)?,
+        elevation_loss: row.get(18)?,
+        elevation_source: row.get(19)?,
+        route_id: row.get(20)?,
+        start_location: row.get(21)?,
+        end_location: row.get(22)?,
+        high_point_location: row.get(23)?,
+        admin1: row.get(24)?,
+        admin2: row.get(25)?,
+        country_code: row.get(26)?,
+        start_end_distance: row.get(27)?,
+    })
+}
+
+/// List activities matching a filter, most recent first
+pub fn list_activities(
+    conn: &Connection,
+    profile_id: i64,
+    filter: &ActivityFilter,
+) -> Result<Vec<Activity>, AppError> {
+    let mut stmt = conn.prepare(&format!(
+        r#"SELECT {}
+           FROM activities a
+           JOIN activity_zones z ON z.activity_id = a.id
+           WHERE a.profile_id = ?7
+             AND (?1 IS NULL OR a.country_code = ?1)
+             AND (?2 IS NULL OR a.admin1 = ?2)
+             AND (?3 IS NULL OR a.admin2 = ?3)
+             AND (?4 IS NULL OR COALESCE(a.start_end_distance > ?5, 0) = ?4)
+             AND (?6 IS NULL OR json_array_length(?6) = (
+                 SELECT COUNT(*) FROM activity_tags at JOIN tags t ON t.id = at.tag_id
+                 WHERE at.activity_id = a.id AND t.name IN (SELECT value FROM json_each(?6))))
+           ORDER BY a.start_time DESC"#,
+        ACTIVITY_COLUMNS
+    ))?;
+
+    let tags = filter.tags.as_ref().map(|tags| {
+        serde_json::to_string(&normalize_tags(tags)).unwrap_or_else(|_| "[]".to_string())
+    });
+    let activities = stmt
+        .query_map(
+            params![
+                filter.country_code,
+                filter.admin1,
+                filter.admin2,
+                filter.traverse,
+                TRAVERSE_DISTANCE,
+                tags,
+                profile_id,
+            ],
+            activity_from_row,
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(activities)
+}
+
+/// Regions a profile's activities start in, with their activity counts
+pub fn list_regions(conn: &Connection, profile_id: i64) -> Result<Vec<Region>, AppError> {
+    let mut stmt = conn.prepare(
+        r#"SELECT country_code, admin1, admin2, COUNT(*)
+           FROM activities
+           WHERE country_code IS NOT NULL AND profile_id = ?
+           GROUP BY country_code, admin1, admin2
+           ORDER BY country_code, admin1, admin2"#,
+    )?;
+
+    let regions = stmt
+        .query_map([profile_id], |row| {
+            Ok(Region {
+                country_code: row.get(0)?,
+                admin1: row.get(1)?,
+                admin2: row.get(2)?,
+                activity_count: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(regions)
+}
+
+/// Update an activity's title, description, notes and tags
+pub fn update_activity_metadata(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+    metadata: &ActivityMetadata,
+) -> Result<(), AppError> {
+    check_activity_profile(conn, profile_id, id)?;
+    let tx = conn.unchecked_transaction()?;
+
+    if let Some(title) = &metadata.title {
+        let title = title.trim();
+        if title.is_empty() {
+            return Err(AppError::InvalidArgument(
+                "Title cannot be empty".to_string(),
+            ));
+        }
+        tx.execute(
+            "UPDATE activities SET title = ? WHERE id = ?",
+            params![title, id],
+        )?;
+    }
+    let text = |value: &str| {
+        Some(value.trim())
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    if let Some(description) = &metadata.description {
+        tx.execute(
+            "UPDATE activities SET description = ? WHERE id = ?",
+            params![text(description), id],
+        )?;
+    }
+    if let Some(notes) = &metadata.notes {
+        tx.execute(
+            "UPDATE activities SET notes = ? WHERE id = ?",
+            params![text(notes), id],
+        )?;
+    }
+
+    if let Some(tags) = &metadata.tags {
+        tx.execute("DELETE FROM activity_tags WHERE activity_id = ?", [id])?;
+        for tag in normalize_tags(tags) {
+            tx.execute("INSERT OR IGNORE INTO tags (name) VALUES (?)", [&tag])?;
+            tx.execute(
+                "INSERT INTO activity_tags (activity_id, tag_id) SELECT ?, id FROM tags WHERE name = ?",
+                params![id, tag],
+            )?;
+        }
+        delete_unused_tags(&tx)?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// All activity types with a profile's activity counts, built-in first
+pub fn list_activity_types(
+    conn: &Connection,
+    profile_id: i64,
+) -> Result<Vec<ActivityTypeInfo>, AppError> {
+    let mut stmt = conn.prepare(
+        r#"SELECT t.name, t.icon, t.color, t.builtin,
+                  (SELECT COUNT(*) FROM activities a WHERE a.activity_type = t.name AND a.profile_id = ?)
+           FROM activity_types t
+           ORDER BY t.builtin DESC, t.name"#,
+    )?;
+
+    let types = stmt
+        .query_map([profile_id], |row| {
+            Ok(ActivityTypeInfo {
+                name: row.get(0)?,
+                icon: row.get(1)?,
+                color: row.get(2)?,
+                builtin: row.get(3)?,
+                activity_count: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(types)
+}
+
+/// Create a custom activity type, or change the icon and colour of an existing one
+pub fn save_activity_type(
+    conn: &Connection,
+    name: &str,
+    icon: &str,
+    color: &str,
+) -> Result<(), AppError> {
+    conn.execute(
+        r#"INSERT INTO activity_types (name, icon, color) VALUES (?1, ?2, ?3)
+           ON CONFLICT (name) DO UPDATE SET icon = ?2, color = ?3"#,
+        params![name, icon, color],
+    )?;
+
+    Ok(())
+}
+
+/// Delete a custom activity type, moving its activities to the default type
+pub fn delete_activity_type(conn: &Connection, name: &str) -> Result<(), AppError> {
+    let builtin: Option<bool> = conn
+        .query_row(
+            "SELECT builtin FROM activity_types WHERE name = ?",
+            [name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match builtin {
+        None => return Err(AppError::NotFound(format!("Activity type {}", name))),
+        Some(true) => {
+            return Err(AppError::InvalidArgument(format!(
+                "Built-in activity type {} cannot be deleted",
+                name
+            )))
+        }
+        Some(false) => {}
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE activities SET activity_type = ? WHERE activity_type = ? COLLATE NOCASE",
+        params![DEFAULT_TYPE, name],
+    )?;
+    tx.execute("DELETE FROM gear_defaults WHERE activity_type = ?", [name])?;
+    tx.execute("DELETE FROM activity_types WHERE name = ?", [name])?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Correct the type of an activity to any built-in or custom type
+pub fn update_activity_type(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+    activity_type: &str,
+) -> Result<(), AppError> {
+    check_activity_profile(conn, profile_id, id)?;
+    // Use the stored spelling of the type name
+    let name: String = conn
+        .query_row(
+            "SELECT name FROM activity_types WHERE name = ?",
+            [activity_type],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or_else(|| {
+            AppError::InvalidArgument(format!("Unknown activity type: {}", activity_type))
+        })?;
+
+    conn.execute(
+        "UPDATE activities SET activity_type = ? WHERE id = ?",
+        params![name, id],
+    )?;
+
+    Ok(())
+}
+
+const GEAR_COLUMNS: &str = r#"g.id, g.name, g.gear_type, g.brand, g.model, g.purchase_date, g.retired_date,
+       g.distance_limit, g.elevation_limit, g.hours_limit, g.notes,
+       (SELECT json_group_array(activity_type) FROM (
+            SELECT d.activity_type FROM gear_defaults d WHERE d.gear_id = g.id ORDER BY d.activity_type))"#;
+
+fn gear_from_row(row: &Row) -> rusqlite::Result<Gear> {
+    let default_types: String = row.get(11)?;
+
+    Ok(Gear {
+        id: row.get(0)?,
+        details: GearDetails {
+            name: row.get(1)?,
+            gear_type: row.get(2)?,
+            brand: row.get(3)?,
+            model: row.get(4)?,
+            purchase_date: row.get(5)?,
+            retired_date: row.get(6)?,
+            distance_limit: row.get(7)?,
+            elevation_limit: row.get(8)?,
+            hours_limit: row.get(9)?,
+            notes: row.get(10)?,
+            default_types: serde_json::from_str(&default_types).unwrap_or_default(),
+        },
+    })
+}
+
+fn gear_exists(conn: &Connection, profile_id: i64, id: i64) -> Result<(), AppError> {
     let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM activities WHERE filename = ?)",
-        [&activity.filename],
+        "SELECT EXISTS(SELECT 1 FROM gear WHERE id = ? AND profile_id = ?)",
+        [id, profile_id],
         |row| row.get(0),
     )?;
-
-    if exists {
-        return Err(AppError::DuplicateActivity(activity.filename.clone()));
+    if !exists {
+        return Err(AppError::NotFound(format!("Gear {}", id)));
     }
+    Ok(())
+}
 
-    // Insert activity
-    conn.execute(
-        r#"INSERT INTO activities (filename, activity_type, activity_date, start_time, location, week_start, month_start, total_duration, total_distance, total_records, elevation_gain, max_altitude, min_altitude)
-           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+/// Create a gear item for a profile, or update it when `id` is given. Returns the gear id.
+pub fn save_gear(
+    conn: &Connection,
+    profile_id: i64,
+    id: Option<i64>,
+    gear: &GearDetails,
+) -> Result<i64, AppError> {
+    let text = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+
+    let tx = conn.unchecked_transaction()?;
+    if let Some(id) = id {
+        gear_exists(&tx, profile_id, id)?;
+    }
+    tx.execute(
+        r#"INSERT INTO gear (id, name, gear_type, brand, model, purchase_date, retired_date,
+                            distance_limit, elevation_limit, hours_limit, notes, profile_id)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+           ON CONFLICT (id) DO UPDATE SET
+               name = excluded.name, gear_type = excluded.gear_type, brand = excluded.brand,
+               model = excluded.model, purchase_date = excluded.purchase_date,
+               retired_date = excluded.retired_date, distance_limit = excluded.distance_limit,
+               elevation_limit = excluded.elevation_limit, hours_limit = excluded.hours_limit,
+               notes = excluded.notes"#,
         params![
-            activity.filename,
-            activity.activity_type,
-            activity.activity_date.to_string(),
-            activity.start_time,
-            activity.location,
-            activity.week_start.to_string(),
-            activity.month_start.to_string(),
-            activity.total_duration,
-            activity.total_distance,
-            activity.records.len() as i64,
-            activity.elevation_gain,
-            activity.max_altitude,
-            activity.min_altitude,
+            id,
+            gear.name.trim(),
+            gear.gear_type.trim(),
+            text(&gear.brand),
+            text(&gear.model),
+            text(&gear.purchase_date),
+            text(&gear.retired_date),
+            gear.distance_limit,
+            gear.elevation_limit,
+            gear.hours_limit,
+            text(&gear.notes),
+            profile_id,
         ],
     )?;
+    let id = id.unwrap_or_else(|| tx.last_insert_rowid());
 
-    let activity_id = conn.last_insert_rowid();
+    tx.execute("DELETE FROM gear_defaults WHERE gear_id = ?", [id])?;
+    for activity_type in &gear.default_types {
+        tx.execute(
+            "INSERT OR IGNORE INTO gear_defaults (activity_type, gear_id) VALUES (?, ?)",
+            params![activity_type.trim(), id],
+        )?;
+    }
+    tx.commit()?;
 
-    // Insert zone times
-    conn.execute(
-        r#"INSERT INTO activity_zones (activity_id, zone1_seconds, zone2_seconds, zone3_seconds, zone4_seconds, zone5_seconds)
-           VALUES (?, ?, ?, ?, ?, ?)"#,
-        params![
-            activity_id,
-            activity.zones.zone1,
-            activity.zones.zone2,
-            activity.zones.zone3,
-            activity.zones.zone4,
-            activity.zones.zone5,
-        ],
+    Ok(id)
+}
+
+/// Delete a gear item and unlink it from its activities
+pub fn delete_gear(conn: &Connection, profile_id: i64, id: i64) -> Result<(), AppError> {
+    gear_exists(conn, profile_id, id)?;
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM activity_gear WHERE gear_id = ?", [id])?;
+    tx.execute("DELETE FROM gear_defaults WHERE gear_id = ?", [id])?;
+    tx.execute("DELETE FROM gear WHERE id = ?", [id])?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// A profile's gear with cumulative distance, elevation gain and hours, and
+/// any limits reached. Active gear comes first.
+pub fn list_gear(conn: &Connection, profile_id: i64) -> Result<Vec<GearUsage>, AppError> {
+    let mut stmt = conn.prepare(&format!(
+        r#"SELECT {},
+                  COUNT(a.id),
+                  COALESCE(SUM(a.total_distance), 0),
+                  COALESCE(SUM(CASE WHEN a.elevation_source = 'dem' THEN a.corrected_elevation_gain
+                                    ELSE a.elevation_gain END), 0),
+                  COALESCE(SUM(a.total_duration), 0) / 3600.0,
+                  MAX(a.activity_date)
+           FROM gear g
+           LEFT JOIN activity_gear ag ON ag.gear_id = g.id
+           LEFT JOIN activities a ON a.id = ag.activity_id
+           WHERE g.profile_id = ?
+           GROUP BY g.id
+           ORDER BY g.retired_date IS NOT NULL, g.gear_type, g.name"#,
+        GEAR_COLUMNS
+    ))?;
+
+    let gear = stmt
+        .query_map([profile_id], |row| {
+            let gear = gear_from_row(row)?;
+            let distance: f64 = row.get(13)?;
+            let elevation_gain: f64 = row.get(14)?;
+            let hours: f64 = row.get(15)?;
+            Ok(GearUsage {
+                alerts: limit_alerts(&gear.details, distance, elevation_gain, hours),
+                gear,
+                activity_count: row.get(12)?,
+                distance,
+                elevation_gain,
+                hours,
+                last_used: row.get(16)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(gear)
+}
+
+/// Gear used on an activity
+pub fn get_activity_gear(
+    conn: &Connection,
+    profile_id: i64,
+    activity_id: i64,
+) -> Result<Vec<Gear>, AppError> {
+    check_activity_profile(conn, profile_id, activity_id)?;
+    let mut stmt = conn.prepare(&format!(
+        r#"SELECT {}
+           FROM gear g
+           JOIN activity_gear ag ON ag.gear_id = g.id
+           WHERE ag.activity_id = ?
+           ORDER BY g.gear_type, g.name"#,
+        GEAR_COLUMNS
+    ))?;
+
+    let gear = stmt
+        .query_map([activity_id], gear_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(gear)
+}
+
+/// Replace the gear linked to an activity with items of the profile's gear
+pub fn set_activity_gear(
+    conn: &Connection,
+    profile_id: i64,
+    activity_id: i64,
+    gear_ids: &[i64],
+) -> Result<(), AppError> {
+    check_activity_profile(conn, profile_id, activity_id)?;
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM activity_gear WHERE activity_id = ?",
+        [activity_id],
     )?;
+    for gear_id in gear_ids {
+        gear_exists(&tx, profile_id, *gear_id)?;
+        tx.execute(
+            "INSERT OR IGNORE INTO activity_gear (activity_id, gear_id) VALUES (?, ?)",
+            params![activity_id, gear_id],
+        )?;
+    }
+    tx.commit()?;
 
-    // Insert records with extended fields
-    let mut stmt = conn.prepare(
-        r#"INSERT INTO records (activity_id, timestamp, elapsed_time, heart_rate, distance, altitude, speed, temperature, position_lat, position_long, zone, extras)
-           VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+    Ok(())
+}
+
+/// Link the default gear for the activity's type, skipping gear not yet
+/// bought or already retired on the activity date
+fn apply_default_gear(conn: &Connection, activity_id: i64) -> Result<(), AppError> {
+    conn.execute(
+        r#"INSERT OR IGNORE INTO activity_gear (activity_id, gear_id)
+           SELECT a.id, g.id
+           FROM activities a
+           JOIN gear_defaults d ON d.activity_type = a.activity_type
+           JOIN gear g ON g.id = d.gear_id AND g.profile_id = a.profile_id
+           WHERE a.id = ?
+             AND (g.purchase_date IS NULL OR g.purchase_date <= a.activity_date)
+             AND (g.retired_date IS NULL OR g.retired_date > a.activity_date)"#,
+        [activity_id],
     )?;
 
-    for record in &activity.records {
-        let extras_json = serde_json::to_string(&record.extras).unwrap_or_else(|_| "{}".to_string());
+    Ok(())
+}
 
-        stmt.execute(params![
-            activity_id,
-            record.timestamp.to_rfc3339(),
-            record.elapsed_time,
-            record.heart_rate.map(|hr| hr as i32),
-            record.distance,
-            record.altitude,
-            record.speed,
-            record.temperature.map(|t| t as f64),
-            record.position_lat,
-            record.position_long,
-            record.zone,
-            extras_json,
-        ])?;
-    }
+fn delete_unused_tags(conn: &Connection) -> Result<(), AppError> {
+    conn.execute(
+        "DELETE FROM tags WHERE NOT EXISTS (SELECT 1 FROM activity_tags WHERE tag_id = tags.id)",
+        [],
+    )?;
 
-    Ok(activity_id)
+    Ok(())
 }
 
-/// List all activities
-pub fn list_activities(conn: &Connection) -> Result<Vec<Activity>, AppError> {
+/// All tags in use, most used first
+pub fn list_tags(conn: &Connection, profile_id: i64) -> Result<Vec<Tag>, AppError> {
     let mut stmt = conn.prepare(
-        r#"SELECT a.id, a.filename, a.activity_type, a.activity_date, a.total_duration,
-                  z.zone1_seconds, z.zone2_seconds, z.zone3_seconds, z.zone4_seconds, z.zone5_seconds,
-                  a.elevation_gain, a.max_altitude, a.min_altitude, a.start_time, a.total_distance, a.location
-           FROM activities a
-           JOIN activity_zones z ON z.activity_id = a.id
-           ORDER BY a.start_time DESC"#,
+        r#"SELECT t.name, COUNT(*)
+           FROM tags t
+           JOIN activity_tags at ON at.tag_id = t.id
+           JOIN activities a ON a.id = at.activity_id
+           WHERE a.profile_id = ?
+           GROUP BY t.id
+           ORDER BY COUNT(*) DESC, t.name"#,
     )?;
 
-    let activities = stmt
-        .query_map([], |row| {
-            Ok(Activity {
-                id: row.get(0)?,
-                filename: row.get(1)?,
-                activity_type: row.get(2)?,
-                activity_date: row.get(3)?,
-                total_duration: row.get(4)?,
-                zones: ZoneTimes {
-                    zone1: row.get(5)?,
-                    zone2: row.get(6)?,
-                    zone3: row.get(7)?,
-                    zone4: row.get(8)?,
-                    zone5: row.get(9)?,
-                },
-                elevation_gain: row.get(10)?,
-                max_altitude: row.get(11)?,
-                min_altitude: row.get(12)?,
-                start_time: row.get(13)?,
-                total_distance: row.get(14)?,
-                location: row.get(15)?,
+    let tags = stmt
+        .query_map([profile_id], |row| {
+            Ok(Tag {
+                name: row.get(0)?,
+                activity_count: row.get(1)?,
             })
         })?
         .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(activities)
+    Ok(tags)
 }
 
-/// Get activity detail with track records
-pub fn get_activity(conn: &Connection, id: i64) -> Result<ActivityDetail, AppError> {
-    let activity = conn
-        .query_row(
-            r#"SELECT a.id, a.filename, a.activity_type, a.activity_date, a.total_duration,
-                      z.zone1_seconds, z.zone2_seconds, z.zone3_seconds, z.zone4_seconds, z.zone5_seconds,
-                      a.elevation_gain, a.max_altitude, a.min_altitude, a.start_time, a.total_distance, a.location
+/// Settings key marking that activities imported before titles existed have default titles
+const TITLES_KEY: &str = "titles_built";
+
+/// Give every untitled activity a default title once. Runs after
+/// `backfill_places` so titles can use the start location.
+pub fn backfill_titles(conn: &Connection) -> Result<(), AppError> {
+    if get_setting::<bool>(conn, TITLES_KEY)?.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let untitled: Vec<(i64, String, String, Option<String>)> = conn
+        .prepare("SELECT id, activity_type, start_time, start_location FROM activities WHERE title IS NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tx = conn.unchecked_transaction()?;
+    for (id, activity_type, start_time, location) in untitled {
+        let Ok(start) = DateTime::parse_from_rfc3339(&start_time) else {
+            continue;
+        };
+        let lon = get_activity_track(&tx, id, CACHED_TOLERANCE)?
+            .first()
+            .map(|(_, lon)| *lon);
+        let title = default_title(
+            &activity_type,
+            start.with_timezone(&Utc),
+            lon,
+            location.as_deref(),
+        );
+        tx.execute(
+            "UPDATE activities SET title = ? WHERE id = ?",
+            params![title, id],
+        )?;
+    }
+    set_setting(&tx, TITLES_KEY, &true)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Settings key marking that activities imported before place geocoding have been geocoded
+const PLACES_KEY: &str = "places_built";
+
+/// Geocode start, end and high point of every activity once after upgrading
+/// to a database with place columns
+pub fn backfill_places(conn: &Connection, geocoder: &ReverseGeocoder) -> Result<(), AppError> {
+    if get_setting::<bool>(conn, PLACES_KEY)?.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let ids: Vec<i64> = conn
+        .prepare("SELECT id FROM activities WHERE start_location IS NULL")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let filter = get_elevation_filter(conn)?;
+
+    let tx = conn.unchecked_transaction()?;
+    for id in ids {
+        let records = get_records(&tx, id)?;
+        let altitudes = filter_altitudes(
+            &records.iter().map(|r| r.elapsed_time).collect::<Vec<_>>(),
+            &records.iter().map(|r| r.altitude).collect::<Vec<_>>(),
+            &filter,
+        );
+        let places = activity_places(geocoder, &record_fixes(&records), &altitudes);
+        tx.execute(
+            r#"UPDATE activities
+               SET start_location = ?, end_location = ?, high_point_location = ?,
+                   admin1 = ?, admin2 = ?, country_code = ?, start_end_distance = ?
+               WHERE id = ?"#,
+            params![
+                places.start_location,
+                places.end_location,
+                places.high_point_location,
+                places.admin1,
+                places.admin2,
+                places.country_code,
+                places.start_end_distance,
+                id,
+            ],
+        )?;
+    }
+    set_setting(&tx, PLACES_KEY, &true)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Get a single activity summary without its track records
+pub fn get_activity_summary(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+) -> Result<Activity, AppError> {
+    conn.query_row(
+        &format!(
+            r#"SELECT {}
                FROM activities a
                JOIN activity_zones z ON z.activity_id = a.id
-               WHERE a.id = ?"#,
+               WHERE a.id = ? AND a.profile_id = ?"#,
+            ACTIVITY_COLUMNS
+        ),
+        [id, profile_id],
+        activity_from_row,
+    )
+    .map_err(|_| AppError::ActivityNotFound(id))
+}
+
+/// Get the track records of an activity in time order
+pub fn get_records(conn: &Connection, id: i64) -> Result<Vec<TrackRecord>, AppError> {
+    let data: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT data FROM record_columns WHERE activity_id = ?",
             [id],
-            |row| {
-                Ok(Activity {
-                    id: row.get(0)?,
-                    filename: row.get(1)?,
-                    activity_type: row.get(2)?,
-                    activity_date: row.get(3)?,
-                    total_duration: row.get(4)?,
-                    zones: ZoneTimes {
-                        zone1: row.get(5)?,
-                        zone2: row.get(6)?,
-                        zone3: row.get(7)?,
-                        zone4: row.get(8)?,
-                        zone5: row.get(9)?,
-                    },
-                    elevation_gain: row.get(10)?,
-                    max_altitude: row.get(11)?,
-                    min_altitude: row.get(12)?,
-                    start_time: row.get(13)?,
-                    total_distance: row.get(14)?,
-                    location: row.get(15)?,
-                })
-            },
+            |row| row.get(0),
         )
-        .map_err(|_| AppError::ActivityNotFound(id))?;
+        .optional()?;
 
-    let mut stmt = conn.prepare(
-        r#"SELECT timestamp, elapsed_time, heart_rate, distance, altitude, speed, temperature, position_lat, position_long, zone, extras
-           FROM records
-           WHERE activity_id = ?
-           ORDER BY timestamp"#,
+    data.map_or(Ok(Vec::new()), |data| decode_records(&data))
+}
+
+/// Get activity detail with track records
+pub fn get_activity(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+) -> Result<ActivityDetail, AppError> {
+    let activity = get_activity_summary(conn, profile_id, id)?;
+    let records = get_records(conn, id)?;
+
+    Ok(ActivityDetail { activity, records })
+}
+
+/// Recompute elevation gain/loss, altitude range and grade-adjusted pace for
+/// every activity of a profile using `filter`. Returns the number of activities updated.
+pub fn recalculate_elevation(
+    conn: &Connection,
+    profile_id: i64,
+    filter: &ElevationFilter,
+) -> Result<usize, AppError> {
+    let ids: Vec<i64> = conn
+        .prepare("SELECT id FROM activities WHERE profile_id = ?")?
+        .query_map([profile_id], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let tx = conn.unchecked_transaction()?;
+    for &id in &ids {
+        let records = get_records(&tx, id)?;
+        let elapsed: Vec<f64> = records.iter().map(|r| r.elapsed_time).collect();
+        let metrics = derive_metrics(
+            &elapsed,
+            &records.iter().map(|r| r.distance).collect::<Vec<_>>(),
+            &records.iter().map(|r| r.altitude).collect::<Vec<_>>(),
+            &records.iter().map(|r| r.speed).collect::<Vec<_>>(),
+            &interval_deltas(&elapsed),
+            filter,
+        );
+        let total_distance = records
+            .iter()
+            .filter_map(|r| r.distance)
+            .fold(0.0, f64::max);
+
+        tx.execute(
+            r#"UPDATE activities
+               SET elevation_gain = ?, elevation_loss = ?, max_altitude = ?, min_altitude = ?,
+                   avg_grade_adjusted_speed = ?, effort_distance = ?
+               WHERE id = ?"#,
+            params![
+                metrics.elevation.gain,
+                metrics.elevation.loss,
+                metrics.elevation.max_altitude,
+                metrics.elevation.min_altitude,
+                metrics.avg_grade_adjusted_speed,
+                effort_distance(total_distance, metrics.elevation.gain),
+                id,
+            ],
+        )?;
+        update_record_values(&tx, id, &metrics.grade_adjusted_speeds, |r, v| {
+            r.grade_adjusted_speed = v
+        })?;
+    }
+    tx.commit()?;
+
+    Ok(ids.len())
+}
+
+/// Overwrite one derived per-record value of an activity, in record order
+fn update_record_values(
+    conn: &Connection,
+    id: i64,
+    values: &[Option<f64>],
+    set: fn(&mut TrackRecord, Option<f64>),
+) -> Result<(), AppError> {
+    let mut records = get_records(conn, id)?;
+    for (record, value) in records.iter_mut().zip(values) {
+        set(record, *value);
+    }
+
+    save_records(conn, id, &records)
+}
+
+/// Store a DEM-corrected altitude series and the totals derived from it
+pub fn save_corrected_elevation(
+    conn: &Connection,
+    id: i64,
+    altitudes: &[Option<f64>],
+) -> Result<(), AppError> {
+    let tx = conn.unchecked_transaction()?;
+    update_record_values(&tx, id, altitudes, |r, v| r.corrected_altitude = v)?;
+    save_corrected_totals(&tx, id)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Derive gain, loss, altitude range and effort distance from an activity's
+/// stored DEM-corrected altitudes, using the saved elevation filter
+fn save_corrected_totals(conn: &Connection, id: i64) -> Result<(), AppError> {
+    let records = get_records(conn, id)?;
+    let filter = get_elevation_filter(conn)?;
+    let stats = elevation_stats(
+        &filter_altitudes(
+            &records.iter().map(|r| r.elapsed_time).collect::<Vec<_>>(),
+            &records
+                .iter()
+                .map(|r| r.corrected_altitude)
+                .collect::<Vec<_>>(),
+            &filter,
+        ),
+        filter.hysteresis,
+    );
+    let total_distance: f64 = conn.query_row(
+        "SELECT COALESCE(total_distance, 0) FROM activities WHERE id = ?",
+        [id],
+        |row| row.get(0),
+    )?;
+
+    conn.execute(
+        r#"UPDATE activities
+           SET corrected_elevation_gain = ?, corrected_elevation_loss = ?,
+               corrected_max_altitude = ?, corrected_min_altitude = ?, corrected_effort_distance = ?
+           WHERE id = ?"#,
+        params![
+            stats.gain,
+            stats.loss,
+            stats.max_altitude,
+            stats.min_altitude,
+            effort_distance(total_distance, stats.gain),
+            id,
+        ],
     )?;
 
-    let records = stmt
-        .query_map([id], |row| {
-            let extras_json: String = row.get(10)?;
-            let extras: HashMap<String, f64> = serde_json::from_str(&extras_json).unwrap_or_default();
+    Ok(())
+}
 
-            Ok(TrackRecord {
-                timestamp: row.get(0)?,
-                elapsed_time: row.get(1)?,
-                heart_rate: row.get(2)?,
-                distance: row.get(3)?,
-                altitude: row.get(4)?,
-                speed: row.get(5)?,
-                temperature: row.get(6)?,
-                position_lat: row.get(7)?,
-                position_long: row.get(8)?,
-                zone: row.get(9)?,
-                extras,
-            })
-        })?
+/// Settings key marking that DEM-corrected activities have their corrected
+/// altitude range and effort distance
+const CORRECTED_TOTALS_KEY: &str = "corrected_totals_built";
+
+/// Derive the corrected altitude range and effort distance of activities
+/// corrected before they were stored, once after upgrading
+pub fn backfill_corrected_totals(conn: &Connection) -> Result<(), AppError> {
+    if get_setting::<bool>(conn, CORRECTED_TOTALS_KEY)?.unwrap_or(false) {
+        return Ok(());
+    }
+
+    let ids: Vec<i64> = conn
+        .prepare(
+            "SELECT id FROM activities WHERE corrected_elevation_gain IS NOT NULL AND corrected_effort_distance IS NULL",
+        )?
+        .query_map([], |row| row.get(0))?
         .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(ActivityDetail {
-        id: activity.id,
-        filename: activity.filename,
-        activity_type: activity.activity_type,
-        activity_date: activity.activity_date,
-        start_time: activity.start_time,
-        location: activity.location,
-        total_duration: activity.total_duration,
-        zones: activity.zones,
-        elevation_gain: activity.elevation_gain,
-        max_altitude: activity.max_altitude,
-        min_altitude: activity.min_altitude,
-        total_distance: activity.total_distance,
-        records,
+    let tx = conn.unchecked_transaction()?;
+    for id in ids {
+        save_corrected_totals(&tx, id)?;
+    }
+    set_setting(&tx, CORRECTED_TOTALS_KEY, &true)?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Choose whether an activity reports device ("device") or DEM-corrected ("dem") elevation
+pub fn set_elevation_source(
+    conn: &Connection,
+    profile_id: i64,
+    id: i64,
+    source: &str,
+) -> Result<(), AppError> {
+    let has_correction: Option<bool> = conn
+        .query_row(
+            "SELECT corrected_elevation_gain IS NOT NULL FROM activities WHERE id = ? AND profile_id = ?",
+            [id, profile_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    match (source, has_correction) {
+        (_, None) => return Err(AppError::ActivityNotFound(id)),
+        ("dem", Some(false)) => {
+            return Err(AppError::InvalidArgument(format!(
+                "Activity {} has no DEM-corrected elevation",
+                id
+            )))
+        }
+        ("device", _) | ("dem", _) => {}
+        (other, _) => {
+            return Err(AppError::InvalidArgument(format!(
+                "Unknown elevation source: {}",
+                other
+            )))
+        }
+    }
+
+    conn.execute(
+        "UPDATE activities SET elevation_source = ? WHERE id = ?",
+        params![source, id],
+    )?;
+    // Segment vertical speeds follow the altitude source
+    match_segments(conn, id)?;
+
+    Ok(())
+}
+
+/// Read a JSON setting, or `None` if it has never been saved
+pub fn get_setting<T: DeserializeOwned>(
+    conn: &Connection,
+    key: &str,
+) -> Result<Option<T>, AppError> {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM settings WHERE key = ?", [key], |row| {
+            row.get(0)
+        })
+        .optional()?;
+
+    Ok(value.and_then(|v| serde_json::from_str(&v).ok()))
+}
+
+/// Save a setting as JSON
+pub fn set_setting<T: Serialize>(conn: &Connection, key: &str, value: &T) -> Result<(), AppError> {
+    let json =
+        serde_json::to_string(value).map_err(|e| AppError::InvalidArgument(e.to_string()))?;
+    conn.execute(
+        "INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, json],
+    )?;
+
+    Ok(())
+}
+
+/// Settings key for the elevation filter
+const ELEVATION_FILTER_KEY: &str = "elevation_filter";
+
+/// Get the configured elevation filter, falling back to the default
+pub fn get_elevation_filter(conn: &Connection) -> Result<ElevationFilter, AppError> {
+    Ok(get_setting(conn, ELEVATION_FILTER_KEY)?.unwrap_or_default())
+}
+
+/// Save the elevation filter used for future imports and recalculation
+pub fn set_elevation_filter(conn: &Connection, filter: &ElevationFilter) -> Result<(), AppError> {
+    set_setting(conn, ELEVATION_FILTER_KEY, filter)
+}
+
+/// Settings key for GPS track cleaning
+const TRACK_CLEANING_KEY: &str = "track_cleaning";
+
+/// Get the configured track cleaning, falling back to the default
+pub fn get_track_cleaning(conn: &Connection) -> Result<TrackCleaning, AppError> {
+    Ok(get_setting(conn, TRACK_CLEANING_KEY)?.unwrap_or_default())
+}
+
+/// Save the track cleaning used for future imports
+pub fn set_track_cleaning(conn: &Connection, cleaning: &TrackCleaning) -> Result<(), AppError> {
+    set_setting(conn, TRACK_CLEANING_KEY, cleaning)
+}
+
+/// Settings key for automatic backups
+const BACKUP_SCHEDULE_KEY: &str = "backup_schedule";
+
+/// Get the automatic backup settings, or the defaults if none are saved
+pub fn get_backup_schedule(conn: &Connection) -> Result<BackupSchedule, AppError> {
+    Ok(get_setting(conn, BACKUP_SCHEDULE_KEY)?.unwrap_or_default())
+}
+
+/// Save the automatic backup settings
+pub fn set_backup_schedule(conn: &Connection, schedule: &BackupSchedule) -> Result<(), AppError> {
+    set_setting(conn, BACKUP_SCHEDULE_KEY, schedule)
+}
+
+/// Collect the saved settings that apply when parsing a new file
+pub fn get_parse_options(conn: &Connection, profile_id: i64) -> Result<ParseOptions, AppError> {
+    let profile = get_profile(conn, profile_id)?.details;
+    let heart_rate = |hr: Option<i32>| hr.and_then(|hr| u8::try_from(hr).ok());
+    let thresholds = zone_thresholds(
+        profile.zone_model,
+        heart_rate(profile.max_hr),
+        heart_rate(profile.resting_hr),
+    );
+
+    Ok(ParseOptions {
+        elevation_filter: get_elevation_filter(conn)?,
+        track_cleaning: get_track_cleaning(conn)?,
+        zone_thresholds: thresholds,
+    })
+}
+
+const PROFILE_COLUMNS: &str = r#"p.id, p.name, p.birthdate, p.sex, p.weight, p.resting_hr, p.max_hr, p.zone_model,
+       (SELECT COUNT(*) FROM activities a WHERE a.profile_id = p.id)"#;
+
+fn profile_from_row(row: &Row) -> rusqlite::Result<Profile> {
+    let zone_model: String = row.get(7)?;
+
+    Ok(Profile {
+        id: row.get(0)?,
+        details: ProfileDetails {
+            name: row.get(1)?,
+            birthdate: row.get(2)?,
+            sex: row.get(3)?,
+            weight: row.get(4)?,
+            resting_hr: row.get(5)?,
+            max_hr: row.get(6)?,
+            zone_model: ZoneModel::parse(&zone_model).unwrap_or_default(),
+        },
+        activity_count: row.get(8)?,
     })
 }
 
+pub fn list_profiles(conn: &Connection) -> Result<Vec<Profile>, AppError> {
+    let profiles = conn
+        .prepare(&format!(
+            "SELECT {} FROM profiles p ORDER BY p.name",
+            PROFILE_COLUMNS
+        ))?
+        .query_map([], profile_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(profiles)
+}
+
+pub fn get_profile(conn: &Connection, id: i64) -> Result<Profile, AppError> {
+    conn.query_row(
+        &format!("SELECT {} FROM profiles p WHERE p.id = ?", PROFILE_COLUMNS),
+        [id],
+        profile_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| AppError::NotFound(format!("Profile {}", id)))
+}
+
+/// Create a profile, or update it when `id` is given. Returns the profile id.
+pub fn save_profile(
+    conn: &Connection,
+    id: Option<i64>,
+    profile: &ProfileDetails,
+) -> Result<i64, AppError> {
+    if let Some(id) = id {
+        get_profile(conn, id)?;
+    }
+    let text = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+
+    conn.execute(
+        r#"INSERT INTO profiles (id, name, birthdate, sex, weight, resting_hr, max_hr, zone_model)
+           VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+           ON CONFLICT (id) DO UPDATE SET
+               name = excluded.name, birthdate = excluded.birthdate, sex = excluded.sex,
+               weight = excluded.weight, resting_hr = excluded.resting_hr, max_hr = excluded.max_hr,
+               zone_model = excluded.zone_model"#,
+        params![
+            id,
+            profile.name.trim(),
+            text(&profile.birthdate),
+            text(&profile.sex),
+            profile.weight,
+            profile.resting_hr,
+            profile.max_hr,
+            profile.zone_model.as_str(),
+        ],
+    )?;
+
+    Ok(id.unwrap_or_else(|| conn.last_insert_rowid()))
+}
+
+/// Delete a profile without activities, along with its gear
+pub fn delete_profile(conn: &Connection, id: i64) -> Result<(), AppError> {
+    let profile = get_profile(conn, id)?;
+    if profile.activity_count > 0 {
+        return Err(AppError::InvalidArgument(format!(
+            "Profile {} still has {} activities",
+            profile.details.name, profile.activity_count
+        )));
+    }
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM profiles", [], |row| row.get(0))?;
+    if count <= 1 {
+        return Err(AppError::InvalidArgument(
+            "The last profile cannot be deleted".to_string(),
+        ));
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM gear_defaults WHERE gear_id IN (SELECT id FROM gear WHERE profile_id = ?)",
+        [id],
+    )?;
+    tx.execute("DELETE FROM gear WHERE profile_id = ?", [id])?;
+    tx.execute("DELETE FROM segments WHERE profile_id = ?", [id])?;
+    tx.execute("DELETE FROM profiles WHERE id = ?", [id])?;
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Settings key holding the id of the profile last switched to
+const ACTIVE_PROFILE_KEY: &str = "active_profile";
+
+/// The profile last switched to, or the first profile if it no longer exists
+pub fn get_active_profile(conn: &Connection) -> Result<i64, AppError> {
+    if let Some(id) = get_setting::<i64>(conn, ACTIVE_PROFILE_KEY)? {
+        if get_profile(conn, id).is_ok() {
+            return Ok(id);
+        }
+    }
+
+    conn.query_row("SELECT MIN(id) FROM profiles", [], |row| {
+        row.get::<_, Option<i64>>(0)
+    })?
+    .ok_or_else(|| AppError::NotFound("Profile".to_string()))
+}
+
+/// Remember the profile to use for all queries, including after a restart
+pub fn set_active_profile(conn: &Connection, id: i64) -> Result<Profile, AppError> {
+    let profile = get_profile(conn, id)?;
+    set_setting(conn, ACTIVE_PROFILE_KEY, &id)?;
+
+    Ok(profile)
+}
+
 /// Get weekly summary
-pub fn get_weekly_summary(conn: &Connection, week_start: &str) -> Result<ZoneSummary, AppError> {
+pub fn get_weekly_summary(
+    conn: &Connection,
+    profile_id: i64,
+    week_start: &str,
+) -> Result<ZoneSummary, AppError> {
     let result = conn.query_row(
         r#"SELECT COUNT(*),
                   COALESCE(SUM(z.zone1_seconds), 0),
@@ -213,8 +2523,8 @@ pub fn get_weekly_summary(conn: &Connection, week_start: &str) -> Result<ZoneSum
                   COALESCE(SUM(z.zone5_seconds), 0)
            FROM activities a
            JOIN activity_zones z ON z.activity_id = a.id
-           WHERE a.week_start = ?"#,
-        [week_start],
+           WHERE a.week_start = ? AND a.profile_id = ?"#,
+        params![week_start, profile_id],
         |row| {
             Ok(ZoneSummary {
                 period_start: week_start.to_string(),
@@ -234,7 +2544,11 @@ pub fn get_weekly_summary(conn: &Connection, week_start: &str) -> Result<ZoneSum
 }
 
 /// Get monthly summary
-pub fn get_monthly_summary(conn: &Connection, month_start: &str) -> Result<ZoneSummary, AppError> {
+pub fn get_monthly_summary(
+    conn: &Connection,
+    profile_id: i64,
+    month_start: &str,
+) -> Result<ZoneSummary, AppError> {
     let result = conn.query_row(
         r#"SELECT COUNT(*),
                   COALESCE(SUM(z.zone1_seconds), 0),
@@ -244,8 +2558,8 @@ pub fn get_monthly_summary(conn: &Connection, month_start: &str) -> Result<ZoneS
                   COALESCE(SUM(z.zone5_seconds), 0)
            FROM activities a
            JOIN activity_zones z ON z.activity_id = a.id
-           WHERE a.month_start = ?"#,
-        [month_start],
+           WHERE a.month_start = ? AND a.profile_id = ?"#,
+        params![month_start, profile_id],
         |row| {
             Ok(ZoneSummary {
                 period_start: month_start.to_string(),
@@ -265,22 +2579,192 @@ pub fn get_monthly_summary(conn: &Connection, month_start: &str) -> Result<ZoneS
 }
 
 /// Delete an activity and all its related records
-pub fn delete_activity(conn: &Connection, id: i64) -> Result<(), AppError> {
-    // Check if activity exists
-    let exists: bool = conn.query_row(
-        "SELECT EXISTS(SELECT 1 FROM activities WHERE id = ?)",
-        [id],
-        |row| row.get(0),
-    )?;
-
-    if !exists {
-        return Err(AppError::ActivityNotFound(id));
-    }
+pub fn delete_activity(conn: &Connection, profile_id: i64, id: i64) -> Result<(), AppError> {
+    check_activity_profile(conn, profile_id, id)?;
 
     // Delete in order: records -> activity_zones -> activities (due to foreign keys)
-    conn.execute("DELETE FROM records WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM record_columns WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM source_files WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM merged_files WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM activity_tracks WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM track_segments WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM segment_efforts WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM activity_pois WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM activity_tags WHERE activity_id = ?", [id])?;
+    delete_unused_tags(conn)?;
+    conn.execute("DELETE FROM activity_gear WHERE activity_id = ?", [id])?;
+    conn.execute("DELETE FROM trip_activities WHERE activity_id = ?", [id])?;
+    delete_empty_trips(conn)?;
     conn.execute("DELETE FROM activity_zones WHERE activity_id = ?", [id])?;
     conn.execute("DELETE FROM activities WHERE id = ?", [id])?;
+    delete_empty_routes(conn)?;
+
+    Ok(())
+}
+
+/// Store activities merged into one as the first of `ids`, moving the tags,
+/// gear and trip of the others to it and deleting them. The original file is
+/// dropped as for other edits. The others' filenames are kept with the merged
+/// activity, so their files count as already imported.
+pub fn save_merged_activity(
+    conn: &Connection,
+    profile_id: i64,
+    ids: &[i64],
+    activity: &ParsedActivity,
+) -> Result<(), AppError> {
+    let Some((&id, others)) = ids.split_first() else {
+        return Err(AppError::InvalidArgument(
+            "No activities to merge".to_string(),
+        ));
+    };
+
+    check_activity_profile(conn, profile_id, id)?;
+
+    let tx = conn.unchecked_transaction()?;
+    for &other in others {
+        tx.execute(
+            "INSERT OR IGNORE INTO activity_tags (activity_id, tag_id) SELECT ?, tag_id FROM activity_tags WHERE activity_id = ?",
+            params![id, other],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO activity_gear (activity_id, gear_id) SELECT ?, gear_id FROM activity_gear WHERE activity_id = ?",
+            params![id, other],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO trip_activities (activity_id, trip_id) SELECT ?, trip_id FROM trip_activities WHERE activity_id = ?",
+            params![id, other],
+        )?;
+        tx.execute(
+            "UPDATE merged_files SET activity_id = ? WHERE activity_id = ?",
+            params![id, other],
+        )?;
+        tx.execute(
+            "INSERT INTO merged_files (filename, activity_id) SELECT filename, ? FROM activities WHERE id = ?",
+            params![id, other],
+        )?;
+        delete_activity(&tx, profile_id, other)?;
+    }
+    update_parsed_activity(&tx, id, activity)?;
+    tx.execute("DELETE FROM source_files WHERE activity_id = ?", [id])?;
+    tx.commit()?;
 
     Ok(())
 }
+
+/// Activities of a profile matching an analytics filter (?1 profile, ?2 and ?3
+/// date range, ?4 JSON array of activity types)
+const ANALYTICS_FILTER: &str = r#"a.profile_id = ?1
+    AND (?2 IS NULL OR a.activity_date >= ?2)
+    AND (?3 IS NULL OR a.activity_date <= ?3)
+    AND (?4 IS NULL OR a.activity_type IN (SELECT value FROM json_each(?4)))"#;
+
+fn analytics_params(
+    profile_id: i64,
+    filter: &AnalyticsFilter,
+) -> (i64, Option<String>, Option<String>, Option<String>) {
+    let types = filter
+        .activity_types
+        .as_ref()
+        .map(|types| serde_json::to_string(types).unwrap_or_else(|_| "[]".to_string()));
+    (
+        profile_id,
+        filter.start_date.clone(),
+        filter.end_date.clone(),
+        types,
+    )
+}
+
+/// Read the leading columns of a row as cells of the given kinds
+fn row_cells(row: &Row, columns: &[(&str, ColumnKind)]) -> rusqlite::Result<Vec<Cell>> {
+    columns
+        .iter()
+        .enumerate()
+        .map(|(i, (_, kind))| {
+            Ok(match kind {
+                ColumnKind::Int => Cell::Int(row.get(i)?),
+                ColumnKind::Float => Cell::Float(row.get(i)?),
+                ColumnKind::Text => Cell::Text(row.get(i)?),
+            })
+        })
+        .collect()
+}
+
+/// Stream the activities matching an analytics filter to `write`, one row of
+/// `ACTIVITY_EXPORT_COLUMNS` at a time. Returns the number of rows.
+pub fn export_activity_rows(
+    conn: &Connection,
+    profile_id: i64,
+    filter: &AnalyticsFilter,
+    mut write: impl FnMut(&[Cell]) -> Result<(), AppError>,
+) -> Result<usize, AppError> {
+    let mut stmt = conn.prepare(&format!(
+        r#"SELECT a.id, COALESCE(a.title, a.filename), a.filename, a.activity_type, a.activity_date, a.start_time,
+                  a.location, a.total_duration, a.total_distance,
+                  z.zone1_seconds, z.zone2_seconds, z.zone3_seconds, z.zone4_seconds, z.zone5_seconds,
+                  CASE WHEN a.elevation_source = 'dem' THEN a.corrected_elevation_gain ELSE a.elevation_gain END,
+                  CASE WHEN a.elevation_source = 'dem' THEN a.corrected_elevation_loss ELSE a.elevation_loss END,
+                  CASE WHEN a.elevation_source = 'dem' THEN a.corrected_max_altitude ELSE a.max_altitude END,
+                  CASE WHEN a.elevation_source = 'dem' THEN a.corrected_min_altitude ELSE a.min_altitude END,
+                  a.avg_grade_adjusted_speed,
+                  CASE WHEN a.elevation_source = 'dem' THEN a.corrected_effort_distance ELSE a.effort_distance END,
+                  a.elevation_source, a.route_id, a.country_code, a.admin1, a.admin2, a.start_end_distance,
+                  (SELECT group_concat(name, ';') FROM (
+                       SELECT t.name FROM activity_tags at JOIN tags t ON t.id = at.tag_id
+                       WHERE at.activity_id = a.id ORDER BY t.name))
+           FROM activities a
+           LEFT JOIN activity_zones z ON z.activity_id = a.id
+           WHERE {}
+           ORDER BY a.id"#,
+        ANALYTICS_FILTER
+    ))?;
+
+    let mut rows = stmt.query(analytics_params(profile_id, filter))?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        write(&row_cells(row, ACTIVITY_EXPORT_COLUMNS)?)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Sorted keys of the `extras` of the records of activities matching an analytics filter
+pub fn record_extra_keys(
+    conn: &Connection,
+    profile_id: i64,
+    filter: &AnalyticsFilter,
+) -> Result<Vec<String>, AppError> {
+    let mut stmt = conn.prepare(&format!(
+        r#"SELECT DISTINCT e.value
+           FROM activities a
+           JOIN record_columns c ON c.activity_id = a.id, json_each(c.extra_keys) e
+           WHERE {}
+           ORDER BY e.value"#,
+        ANALYTICS_FILTER
+    ))?;
+
+    let keys = stmt
+        .query_map(analytics_params(profile_id, filter), |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(keys)
+}
+
+/// Ids of the activities matching an analytics filter, in the order their
+/// rows are exported
+pub fn analytics_activity_ids(
+    conn: &Connection,
+    profile_id: i64,
+    filter: &AnalyticsFilter,
+) -> Result<Vec<i64>, AppError> {
+    let mut stmt = conn.prepare(&format!(
+        r#"SELECT a.id
+           FROM activities a
+           WHERE {}
+           ORDER BY a.id"#,
+        ANALYTICS_FILTER
+    ))?;
+
+    let ids = stmt
+        .query_map(analytics_params(profile_id, filter), |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids)
+}
@@ -0,0 +1,181 @@
+use crate::geo::LocalProjection;
+
+/// Simplification tolerance (m) of the tracks compared for route matching
+pub const ROUTE_TRACK_TOLERANCE: f64 = 10.0;
+
+/// Points each track is resampled to before comparison
+const RESAMPLE_POINTS: usize = 100;
+
+/// Largest discrete Fréchet distance (m) between two attempts of the same route
+pub const MATCH_DISTANCE: f64 = 100.0;
+
+/// Largest relative difference in track length between attempts of the same route
+const LENGTH_TOLERANCE: f64 = 0.1;
+
+/// Points in a local metric frame, as (east, north)
+type Local = Vec<(f64, f64)>;
+
+fn path_length(points: &[(f64, f64)]) -> f64 {
+    points
+        .windows(2)
+        .map(|w| ((w[1].0 - w[0].0).powi(2) + (w[1].1 - w[0].1).powi(2)).sqrt())
+        .sum()
+}
+
+/// Resample a local polyline to `n` points evenly spaced along its length
+fn resample(points: &[(f64, f64)], n: usize) -> Local {
+    let total = path_length(points);
+    if points.len() < 2 || total == 0.0 {
+        return points.first().map(|p| vec![*p; n]).unwrap_or_default();
+    }
+
+    let step = total / (n - 1) as f64;
+    let mut out = Vec::with_capacity(n);
+    let mut segment = 0;
+    let mut segment_start = 0.0;
+
+    for i in 0..n {
+        let target = (i as f64 * step).min(total);
+        loop {
+            let (a, b) = (points[segment], points[segment + 1]);
+            let len = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+            if target <= segment_start + len || segment + 2 == points.len() {
+                let t = if len > 0.0 {
+                    ((target - segment_start) / len).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                out.push((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)));
+                break;
+            }
+            segment_start += len;
+            segment += 1;
+        }
+    }
+
+    out
+}
+
+/// Discrete Fréchet distance between two local polylines
+fn discrete_frechet(a: &[(f64, f64)], b: &[(f64, f64)]) -> f64 {
+    let dist = |p: (f64, f64), q: (f64, f64)| ((p.0 - q.0).powi(2) + (p.1 - q.1).powi(2)).sqrt();
+
+    // Rolling rows of the coupling table
+    let mut prev = vec![0.0_f64; b.len()];
+    let mut row = vec![0.0; b.len()];
+    for (i, &p) in a.iter().enumerate() {
+        for (j, &q) in b.iter().enumerate() {
+            let d = dist(p, q);
+            row[j] = match (i, j) {
+                (0, 0) => d,
+                (0, _) => row[j - 1].max(d),
+                (_, 0) => prev[0].max(d),
+                _ => prev[j].min(prev[j - 1]).min(row[j - 1]).max(d),
+            };
+        }
+        std::mem::swap(&mut prev, &mut row);
+    }
+
+    prev.last().copied().unwrap_or(f64::INFINITY)
+}
+
+/// Discrete Fréchet distance (m) between two (lat, lon) tracks after
+/// resampling both to the same number of evenly spaced points
+pub fn track_distance(a: &[(f64, f64)], b: &[(f64, f64)]) -> Option<f64> {
+    let &(lat, lon) = a.first()?;
+    b.first()?;
+
+    let projection = LocalProjection::new(lat, lon);
+    let project = |points: &[(f64, f64)]| -> Local {
+        points
+            .iter()
+            .map(|(lat, lon)| projection.project(*lat, *lon))
+            .collect()
+    };
+
+    Some(discrete_frechet(
+        &resample(&project(a), RESAMPLE_POINTS),
+        &resample(&project(b), RESAMPLE_POINTS),
+    ))
+}
+
+/// Whether two tracks are attempts of the same route: similar length and a
+/// Fréchet distance within `MATCH_DISTANCE`. Direction matters, so a route
+/// walked in reverse is a different route.
+pub fn same_route(a: &[(f64, f64)], b: &[(f64, f64)]) -> bool {
+    if a.len() < 2 || b.len() < 2 {
+        return false;
+    }
+
+    let projection = LocalProjection::new(a[0].0, a[0].1);
+    let length = |points: &[(f64, f64)]| {
+        path_length(
+            &points
+                .iter()
+                .map(|(lat, lon)| projection.project(*lat, *lon))
+                .collect::<Vec<_>>(),
+        )
+    };
+    let (length_a, length_b) = (length(a), length(b));
+    if (length_a - length_b).abs() > LENGTH_TOLERANCE * length_a.max(length_b) {
+        return false;
+    }
+
+    track_distance(a, b).is_some_and(|d| d <= MATCH_DISTANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rectangular loop, shifted north by `offset` degrees
+    fn loop_track(offset: f64) -> Vec<(f64, f64)> {
+        vec![
+            (46.0 + offset, 7.0),
+            (46.01 + offset, 7.0),
+            (46.01 + offset, 7.01),
+            (46.0 + offset, 7.01),
+            (46.0 + offset, 7.0),
+        ]
+    }
+
+    #[test]
+    fn test_resample_even_spacing() {
+        let points = resample(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)], 5);
+        assert_eq!(
+            points,
+            vec![
+                (0.0, 0.0),
+                (5.0, 0.0),
+                (10.0, 0.0),
+                (10.0, 5.0),
+                (10.0, 10.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_frechet_of_parallel_lines() {
+        let a = [(0.0, 0.0), (10.0, 0.0), (20.0, 0.0)];
+        let b = [(0.0, 3.0), (10.0, 3.0), (20.0, 3.0)];
+        assert!((discrete_frechet(&a, &b) - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_same_route() {
+        // ~20 m offset is well within the match distance
+        assert!(same_route(&loop_track(0.0), &loop_track(0.0002)));
+        // ~500 m offset is a different route
+        assert!(!same_route(&loop_track(0.0), &loop_track(0.005)));
+        // Same loop in the opposite direction
+        let reversed: Vec<_> = loop_track(0.0).into_iter().rev().collect();
+        assert!(!same_route(&loop_track(0.0), &reversed));
+    }
+
+    #[test]
+    fn test_different_length_not_same_route() {
+        let out_and_back = [(46.0, 7.0), (46.01, 7.0), (46.0, 7.0)];
+        let one_way = [(46.0, 7.0), (46.01, 7.0)];
+        assert!(!same_route(&out_and_back, &one_way));
+    }
+}
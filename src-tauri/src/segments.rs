@@ -0,0 +1,210 @@
+use crate::geo::haversine;
+use crate::spatial::distance_to_track;
+use crate::track::{record_fixes, Fix};
+use crate::types::TrackRecord;
+
+/// Distance (m) from the segment's start or end within which a track is considered to pass it
+const ENDPOINT_RADIUS: f64 = 30.0;
+
+/// Largest distance (m) a track may stray from the segment between its start and end
+const FOLLOW_TOLERANCE: f64 = 50.0;
+
+/// Smallest fraction of the segment's length an effort must cover, rejecting
+/// shortcuts across looping segments
+const MIN_COVERAGE: f64 = 0.8;
+
+/// A traversal of a segment within an activity
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentMatch {
+    /// Index of the record closest to the segment start
+    pub start_index: usize,
+    /// Index of the record closest to the segment end
+    pub end_index: usize,
+    pub elapsed_time: f64,
+    pub avg_heart_rate: Option<f64>,
+    /// Net altitude change per hour (m/h); negative on descents
+    pub vertical_speed: Option<f64>,
+}
+
+/// Length (m) of a (lat, lon) polyline
+pub fn polyline_length(points: &[(f64, f64)]) -> f64 {
+    points
+        .windows(2)
+        .map(|w| haversine(w[0].0, w[0].1, w[1].0, w[1].1))
+        .sum()
+}
+
+/// Index of the fix closest to `target` in the run of consecutive fixes within
+/// `ENDPOINT_RADIUS` starting at `from`, and the index just past that run
+fn closest_in_run(fixes: &[Fix], from: usize, target: (f64, f64)) -> (usize, usize) {
+    let mut best = (from, f64::INFINITY);
+    let mut i = from;
+    while let Some(Some((lat, lon))) = fixes.get(i) {
+        let d = haversine(*lat, *lon, target.0, target.1);
+        if d > ENDPOINT_RADIUS {
+            break;
+        }
+        if d < best.1 {
+            best = (i, d);
+        }
+        i += 1;
+    }
+    (best.0, i)
+}
+
+fn near(fix: Fix, target: (f64, f64)) -> bool {
+    fix.is_some_and(|(lat, lon)| haversine(lat, lon, target.0, target.1) <= ENDPOINT_RADIUS)
+}
+
+/// Find every traversal of a segment in a list of fixes, as (start, end) indices.
+///
+/// A traversal passes within `ENDPOINT_RADIUS` of the segment start, stays
+/// within `FOLLOW_TOLERANCE` of the segment, then passes its end. Fixes
+/// missing from the track are skipped.
+pub fn find_traversals(segment: &[(f64, f64)], fixes: &[Fix]) -> Vec<(usize, usize)> {
+    let (Some(&start), Some(&end)) = (segment.first(), segment.last()) else {
+        return Vec::new();
+    };
+    let segment_length = polyline_length(segment);
+
+    let mut traversals = Vec::new();
+    let mut i = 0;
+    while i < fixes.len() {
+        if !near(fixes[i], start) {
+            i += 1;
+            continue;
+        }
+
+        let (start_index, run_end) = closest_in_run(fixes, i, start);
+        let mut end_index = None;
+        let mut j = start_index + 1;
+        while j < fixes.len() {
+            let Some((lat, lon)) = fixes[j] else {
+                j += 1;
+                continue;
+            };
+            if distance_to_track(lat, lon, segment).is_some_and(|d| d > FOLLOW_TOLERANCE) {
+                break;
+            }
+            // Only look for the end once the start area has been left
+            if j >= run_end && near(fixes[j], end) {
+                end_index = Some(closest_in_run(fixes, j, end).0);
+                break;
+            }
+            j += 1;
+        }
+
+        match end_index {
+            Some(end_index) => {
+                let path: Vec<(f64, f64)> = fixes[start_index..=end_index]
+                    .iter()
+                    .flatten()
+                    .copied()
+                    .collect();
+                if polyline_length(&path) >= MIN_COVERAGE * segment_length {
+                    traversals.push((start_index, end_index));
+                    i = end_index + 1;
+                } else {
+                    i = run_end.max(i + 1);
+                }
+            }
+            None => i = run_end.max(i + 1),
+        }
+    }
+
+    traversals
+}
+
+/// Segment efforts within an activity's records, with time, heart rate and vertical speed
+pub fn match_segment(segment: &[(f64, f64)], records: &[TrackRecord]) -> Vec<SegmentMatch> {
+    find_traversals(segment, &record_fixes(records))
+        .into_iter()
+        .map(|(start_index, end_index)| {
+            let efforts = &records[start_index..=end_index];
+            let elapsed_time = records[end_index].elapsed_time - records[start_index].elapsed_time;
+
+            let heart_rates: Vec<f64> = efforts
+                .iter()
+                .filter_map(|r| r.heart_rate)
+                .map(f64::from)
+                .collect();
+            let avg_heart_rate = (!heart_rates.is_empty())
+                .then(|| heart_rates.iter().sum::<f64>() / heart_rates.len() as f64);
+
+            let first_altitude = efforts.iter().find_map(|r| r.altitude);
+            let last_altitude = efforts.iter().rev().find_map(|r| r.altitude);
+            let vertical_speed = first_altitude
+                .zip(last_altitude)
+                .filter(|_| elapsed_time > 0.0)
+                .map(|(a, b)| (b - a) / elapsed_time * 3600.0);
+
+            SegmentMatch {
+                start_index,
+                end_index,
+                elapsed_time,
+                avg_heart_rate,
+                vertical_speed,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Track heading north ~11 m per second, climbing 1 m per second
+    fn records(n: usize) -> Vec<TrackRecord> {
+        (0..n)
+            .map(|i| TrackRecord {
+                elapsed_time: i as f64,
+                heart_rate: Some(120 + (i % 2) as i32 * 10),
+                altitude: Some(1000.0 + i as f64),
+                position_lat: Some(46.0 + i as f64 * 0.0001),
+                position_long: Some(7.0),
+                zone: "zone1".to_string(),
+                ..TrackRecord::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_match_segment_on_track() {
+        let segment = [(46.002, 7.0), (46.006, 7.0)];
+        let matches = match_segment(&segment, &records(100));
+        assert_eq!(matches.len(), 1);
+        let m = &matches[0];
+        assert_eq!((m.start_index, m.end_index), (20, 60));
+        assert_eq!(m.elapsed_time, 40.0);
+        // 21 records at 120 bpm and 20 at 130 bpm
+        assert!((m.avg_heart_rate.unwrap() - 5120.0 / 41.0).abs() < 1e-9);
+        assert_eq!(m.vertical_speed, Some(3600.0));
+    }
+
+    #[test]
+    fn test_wrong_direction_not_matched() {
+        let segment = [(46.006, 7.0), (46.002, 7.0)];
+        assert!(match_segment(&segment, &records(100)).is_empty());
+    }
+
+    #[test]
+    fn test_detour_not_matched() {
+        let mut records = records(100);
+        // Leave the segment by ~150 m halfway along
+        records[40].position_long = Some(7.002);
+        let segment = [(46.002, 7.0), (46.006, 7.0)];
+        assert!(match_segment(&segment, &records).is_empty());
+    }
+
+    #[test]
+    fn test_repeated_traversals() {
+        let segment = [(46.0, 7.0), (46.001, 7.0)];
+        let mut fixes: Vec<Fix> = (0..=10)
+            .map(|i| Some((46.0 + i as f64 * 0.0001, 7.0)))
+            .collect();
+        // Walk back down and repeat
+        fixes.extend((0..10).rev().map(|i| Some((46.0 + i as f64 * 0.0001, 7.0))));
+        fixes.extend((1..=10).map(|i| Some((46.0 + i as f64 * 0.0001, 7.0))));
+        assert_eq!(find_traversals(&segment, &fixes), vec![(0, 10), (20, 30)]);
+    }
+}
@@ -0,0 +1,42 @@
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::error::AppError;
+
+/// Gzip the original file of an activity for storage
+pub fn compress(bytes: &[u8]) -> Result<Vec<u8>, AppError> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(bytes.len() / 2), Compression::best());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+/// Restore an original file stored with `compress`
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let mut bytes = Vec::with_capacity(data.len() * 2);
+    GzDecoder::new(data).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        // FIT records repeat the same field layout, so they compress well
+        let original: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| [0x05, (i % 7) as u8, 0x10, 0x00])
+            .collect();
+        let stored = compress(&original).unwrap();
+        assert!(stored.len() < original.len() / 4);
+        assert_eq!(decompress(&stored).unwrap(), original);
+    }
+
+    #[test]
+    fn test_decompress_rejects_corrupt_data() {
+        assert!(decompress(b"not gzip").is_err());
+    }
+}
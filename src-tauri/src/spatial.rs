@@ -0,0 +1,144 @@
+use crate::geo::{BoundingBox, LocalProjection};
+
+/// Track points per R-tree entry; consecutive chunks share an end point
+pub const SEGMENT_POINTS: usize = 16;
+
+/// Bounding boxes of consecutive chunks of a simplified track, used as R-tree entries
+pub fn track_segments(points: &[(f64, f64)]) -> Vec<BoundingBox> {
+    if points.len() == 1 {
+        return BoundingBox::from_points(points).into_iter().collect();
+    }
+
+    points
+        .windows(2)
+        .collect::<Vec<_>>()
+        .chunks(SEGMENT_POINTS - 1)
+        .filter_map(|chunk| {
+            let start = chunk[0][0];
+            let mut chunk_points = vec![start];
+            chunk_points.extend(chunk.iter().map(|w| w[1]));
+            BoundingBox::from_points(&chunk_points)
+        })
+        .collect()
+}
+
+/// Closest distance (m) from a position to a track polyline
+pub fn distance_to_track(lat: f64, lon: f64, points: &[(f64, f64)]) -> Option<f64> {
+    let projection = LocalProjection::new(lat, lon);
+    let local: Vec<(f64, f64)> = points
+        .iter()
+        .map(|(la, lo)| projection.project(*la, *lo))
+        .collect();
+
+    if local.len() == 1 {
+        let (x, y) = local[0];
+        return Some((x * x + y * y).sqrt());
+    }
+
+    local
+        .windows(2)
+        .map(|w| {
+            let ((ax, ay), (bx, by)) = (w[0], w[1]);
+            let (dx, dy) = (bx - ax, by - ay);
+            let len_sq = dx * dx + dy * dy;
+            let t = if len_sq > 0.0 {
+                (-(ax * dx + ay * dy) / len_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (cx, cy) = (ax + t * dx, ay + t * dy);
+            (cx * cx + cy * cy).sqrt()
+        })
+        .reduce(f64::min)
+}
+
+/// Liang–Barsky test of whether the segment `a`–`b` passes through the box
+fn segment_intersects(a: (f64, f64), b: (f64, f64), bbox: &BoundingBox) -> bool {
+    let (dlat, dlon) = (b.0 - a.0, b.1 - a.1);
+    let mut t0 = 0.0_f64;
+    let mut t1 = 1.0_f64;
+
+    for (p, q) in [
+        (-dlat, a.0 - bbox.min_lat),
+        (dlat, bbox.max_lat - a.0),
+        (-dlon, a.1 - bbox.min_lon),
+        (dlon, bbox.max_lon - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return false;
+            }
+        } else {
+            let r = q / p;
+            if p < 0.0 {
+                t0 = t0.max(r);
+            } else {
+                t1 = t1.min(r);
+            }
+            if t0 > t1 {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Whether a track polyline passes through the box
+pub fn track_crosses(points: &[(f64, f64)], bbox: &BoundingBox) -> bool {
+    match points {
+        [] => false,
+        [(lat, lon)] => bbox.contains(*lat, *lon),
+        _ => points
+            .windows(2)
+            .any(|w| segment_intersects(w[0], w[1], bbox)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(n: usize) -> Vec<(f64, f64)> {
+        (0..n).map(|i| (46.0 + i as f64 * 0.001, 7.0)).collect()
+    }
+
+    #[test]
+    fn test_track_segments_cover_track() {
+        let points = line(40);
+        let segments = track_segments(&points);
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].min_lat, 46.0);
+        // Chunks share their boundary point
+        assert_eq!(segments[0].max_lat, segments[1].min_lat);
+        assert!((segments[2].max_lat - points[39].0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_distance_to_track() {
+        let points = line(10);
+        // ~0.001° of longitude east of the line at 46°N is about 77 m
+        let d = distance_to_track(46.004, 7.001, &points).unwrap();
+        assert!((d - 77.2).abs() < 1.0);
+        assert!(distance_to_track(46.0, 7.0, &[]).is_none());
+    }
+
+    #[test]
+    fn test_track_crosses_without_point_inside() {
+        let points = vec![(46.0, 7.0), (46.0, 7.1)];
+        let bbox = BoundingBox {
+            min_lat: 45.99,
+            max_lat: 46.01,
+            min_lon: 7.04,
+            max_lon: 7.06,
+        };
+        assert!(track_crosses(&points, &bbox));
+
+        let away = BoundingBox {
+            min_lat: 46.05,
+            max_lat: 46.06,
+            ..bbox
+        };
+        assert!(!track_crosses(&points, &away));
+    }
+}
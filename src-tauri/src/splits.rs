@@ -0,0 +1,204 @@
+use crate::effort::MOVING_SPEED;
+use crate::elevation::{filter_altitudes, hysteresis_steps, ElevationFilter};
+use crate::error::AppError;
+use crate::types::{Split, TrackRecord, ZoneTimes};
+
+/// Longest gap (seconds) between records credited to zone time, matching the parser
+const MAX_ZONE_DELTA: f64 = 10.0;
+
+/// Distance unit used to cut splits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitUnit {
+    Kilometre,
+    Mile,
+}
+
+impl SplitUnit {
+    pub fn parse(unit: &str) -> Result<Self, AppError> {
+        match unit {
+            "km" => Ok(SplitUnit::Kilometre),
+            "mi" => Ok(SplitUnit::Mile),
+            other => Err(AppError::InvalidArgument(format!(
+                "Unknown split unit: {}",
+                other
+            ))),
+        }
+    }
+
+    /// Length of one split in metres
+    pub fn metres(self) -> f64 {
+        match self {
+            SplitUnit::Kilometre => 1000.0,
+            SplitUnit::Mile => 1609.344,
+        }
+    }
+}
+
+/// Running totals for the split currently being built
+#[derive(Default)]
+struct SplitAccumulator {
+    distance: f64,
+    elapsed_time: f64,
+    moving_time: f64,
+    adjusted_distance: f64,
+    elevation_gain: f64,
+    elevation_loss: f64,
+    hr_weighted: f64,
+    hr_time: f64,
+    zones: ZoneTimes,
+}
+
+impl SplitAccumulator {
+    fn finish(self, index: u32, unit_metres: f64) -> Split {
+        let moving_pace = (self.distance > 0.0 && self.moving_time > 0.0)
+            .then(|| self.moving_time / self.distance * unit_metres);
+        let grade_adjusted_pace = (self.adjusted_distance > 0.0 && self.moving_time > 0.0)
+            .then(|| self.moving_time / self.adjusted_distance * unit_metres);
+        let avg_heart_rate = (self.hr_time > 0.0).then(|| self.hr_weighted / self.hr_time);
+
+        Split {
+            index,
+            distance: self.distance,
+            elapsed_time: self.elapsed_time,
+            moving_time: self.moving_time,
+            moving_pace,
+            grade_adjusted_pace,
+            elevation_gain: self.elevation_gain,
+            elevation_loss: self.elevation_loss,
+            avg_heart_rate,
+            zones: self.zones,
+        }
+    }
+}
+
+/// Cut an activity into fixed-distance splits from its distance stream.
+///
+/// Each interval between consecutive records is credited to the split in
+/// which it starts. Records without a distance are skipped. Elevation
+/// gain/loss uses the same filtered altitude series as the activity totals.
+pub fn compute_splits(
+    records: &[TrackRecord],
+    unit: SplitUnit,
+    filter: &ElevationFilter,
+) -> Vec<Split> {
+    let unit_metres = unit.metres();
+    let points: Vec<&TrackRecord> = records.iter().filter(|r| r.distance.is_some()).collect();
+    let filtered = filter_altitudes(
+        &points.iter().map(|r| r.elapsed_time).collect::<Vec<_>>(),
+        &points.iter().map(|r| r.altitude).collect::<Vec<_>>(),
+        filter,
+    );
+    let elevation_steps = hysteresis_steps(&filtered, filter.hysteresis);
+
+    let mut splits = Vec::new();
+    let mut current = SplitAccumulator::default();
+    let mut current_index: u32 = 0;
+
+    for (i, pair) in points.windows(2).enumerate() {
+        let (a, b) = (pair[0], pair[1]);
+        let start_distance = a.distance.unwrap_or(0.0);
+        let dd = (b.distance.unwrap_or(0.0) - start_distance).max(0.0);
+        let dt = (b.elapsed_time - a.elapsed_time).max(0.0);
+
+        let index = (start_distance / unit_metres).floor() as u32;
+        while index > current_index {
+            let finished = std::mem::take(&mut current);
+            splits.push(finished.finish(current_index, unit_metres));
+            current_index += 1;
+        }
+
+        current.distance += dd;
+        current.elapsed_time += dt;
+
+        if dt > 0.0 && dd / dt >= MOVING_SPEED {
+            current.moving_time += dt;
+            // Scale the covered distance by the record's grade adjustment
+            let factor = match (a.grade_adjusted_speed, a.speed) {
+                (Some(gas), Some(speed)) if speed > 0.0 => gas / speed,
+                _ => 1.0,
+            };
+            current.adjusted_distance += dd * factor;
+        }
+
+        let (gain, loss) = elevation_steps[i + 1];
+        current.elevation_gain += gain;
+        current.elevation_loss += loss;
+
+        if let Some(hr) = a.heart_rate {
+            let zone_dt = dt.min(MAX_ZONE_DELTA);
+            current.hr_weighted += hr as f64 * zone_dt;
+            current.hr_time += zone_dt;
+            current.zones.add(&a.zone, zone_dt);
+        }
+    }
+
+    if current.distance > 0.0 || current.elapsed_time > 0.0 {
+        splits.push(current.finish(current_index, unit_metres));
+    }
+
+    splits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elevation::Smoothing;
+
+    fn record(elapsed_time: f64, distance: f64, altitude: f64, heart_rate: i32) -> TrackRecord {
+        TrackRecord {
+            elapsed_time,
+            heart_rate: Some(heart_rate),
+            distance: Some(distance),
+            altitude: Some(altitude),
+            speed: Some(1.0),
+            zone: "zone2".to_string(),
+            grade_adjusted_speed: Some(1.0),
+            ..TrackRecord::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_unit() {
+        assert_eq!(SplitUnit::parse("km").unwrap(), SplitUnit::Kilometre);
+        assert_eq!(SplitUnit::parse("mi").unwrap(), SplitUnit::Mile);
+        assert!(SplitUnit::parse("furlong").is_err());
+    }
+
+    #[test]
+    fn test_splits_per_km() {
+        // 2.5 km at 1 m/s with one record every 100 m, climbing 1 m per record
+        let records: Vec<TrackRecord> = (0..=25)
+            .map(|i| record(i as f64 * 100.0, i as f64 * 100.0, i as f64, 120))
+            .collect();
+
+        let filter = ElevationFilter {
+            smoothing: Smoothing::None,
+            ..ElevationFilter::default()
+        };
+        let splits = compute_splits(&records, SplitUnit::Kilometre, &filter);
+
+        assert_eq!(splits.len(), 3);
+        assert!((splits[0].distance - 1000.0).abs() < 1e-9);
+        assert!((splits[0].moving_pace.unwrap() - 1000.0).abs() < 1e-9);
+        assert!((splits[0].elevation_gain - 10.0).abs() < 1e-9);
+        assert!((splits[2].distance - 500.0).abs() < 1e-9);
+        assert_eq!(splits[1].avg_heart_rate, Some(120.0));
+        // Zone time is capped per interval like the parser's
+        assert!((splits[0].zones.zone2 - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stopped_time_not_moving() {
+        let records = vec![
+            record(0.0, 0.0, 0.0, 100),
+            record(100.0, 100.0, 0.0, 100),
+            record(400.0, 100.0, 0.0, 100),
+        ];
+
+        let splits = compute_splits(&records, SplitUnit::Kilometre, &ElevationFilter::default());
+
+        assert_eq!(splits.len(), 1);
+        assert_eq!(splits[0].elapsed_time, 400.0);
+        assert_eq!(splits[0].moving_time, 100.0);
+    }
+}
@@ -0,0 +1,315 @@
+/// Built-in activity types as (name, icon, colour). Icons are lucide icon names.
+pub const BUILTIN_TYPES: &[(&str, &str, &str)] = &[
+    ("Hike", "mountain", "#16a34a"),
+    ("Walk", "footprints", "#65a30d"),
+    ("Run", "activity", "#ea580c"),
+    ("Trail Run", "trees", "#c2410c"),
+    ("Mountaineering", "mountain-snow", "#7c3aed"),
+    ("Climbing", "hand", "#9333ea"),
+    ("Ski Touring", "snowflake", "#0284c7"),
+    ("Alpine Ski", "cable-car", "#0ea5e9"),
+    ("Nordic Ski", "snowflake", "#38bdf8"),
+    ("Snowboard", "snowflake", "#0369a1"),
+    ("Snowshoe", "snowflake", "#0891b2"),
+    ("Ride", "bike", "#ca8a04"),
+    ("Mountain Bike", "bike", "#a16207"),
+    ("E-Bike Ride", "zap", "#eab308"),
+    ("Swimming", "waves", "#2563eb"),
+    ("Paddle", "sailboat", "#0d9488"),
+    ("Rowing", "ship", "#0f766e"),
+    ("Strength", "dumbbell", "#dc2626"),
+    ("Yoga", "flower", "#db2777"),
+    ("Other", "circle", "#6b7280"),
+];
+
+/// Type given to activities whose sport isn't recognised
+pub const DEFAULT_TYPE: &str = "Other";
+
+/// FIT profile `sport` enum names, indexed by value
+const SPORTS: &[&str] = &[
+    "generic",
+    "running",
+    "cycling",
+    "transition",
+    "fitness_equipment",
+    "swimming",
+    "basketball",
+    "soccer",
+    "tennis",
+    "american_football",
+    "training",
+    "walking",
+    "cross_country_skiing",
+    "alpine_skiing",
+    "snowboarding",
+    "rowing",
+    "mountaineering",
+    "hiking",
+    "multisport",
+    "paddling",
+    "flying",
+    "e_biking",
+    "motorcycling",
+    "boating",
+    "driving",
+    "golf",
+    "hang_gliding",
+    "horseback_riding",
+    "hunting",
+    "fishing",
+    "inline_skating",
+    "rock_climbing",
+    "sailing",
+    "ice_skating",
+    "sky_diving",
+    "snowshoeing",
+    "snowmobiling",
+    "stand_up_paddleboarding",
+    "surfing",
+    "wakeboarding",
+    "water_skiing",
+    "kayaking",
+    "rafting",
+    "windsurfing",
+    "kitesurfing",
+    "tactical",
+    "jumpmaster",
+    "boxing",
+    "floor_climbing",
+];
+
+/// FIT profile `sub_sport` enum names, indexed by value
+const SUB_SPORTS: &[&str] = &[
+    "generic",
+    "treadmill",
+    "street",
+    "trail",
+    "track",
+    "spin",
+    "indoor_cycling",
+    "road",
+    "mountain",
+    "downhill",
+    "recumbent",
+    "cyclocross",
+    "hand_cycling",
+    "track_cycling",
+    "indoor_rowing",
+    "elliptical",
+    "stair_climbing",
+    "lap_swimming",
+    "open_water",
+    "flexibility_training",
+    "strength_training",
+    "warm_up",
+    "match",
+    "exercise",
+    "challenge",
+    "indoor_skiing",
+    "cardio_training",
+    "indoor_walking",
+    "e_bike_fitness",
+    "bmx",
+    "casual_walking",
+    "speed_walking",
+    "bike_to_run_transition",
+    "run_to_bike_transition",
+    "swim_to_bike_transition",
+    "atv",
+    "motocross",
+    "backcountry",
+    "resort",
+    "rc_drone",
+    "wingsuit",
+    "whitewater",
+    "skate_skiing",
+    "yoga",
+    "pilates",
+    "indoor_running",
+    "gravel_cycling",
+    "e_bike_mountain",
+    "commuting",
+    "mixed_surface",
+    "navigate",
+    "track_me",
+    "map",
+    "single_gas_diving",
+    "multi_gas_diving",
+    "gauge_diving",
+    "apnea_diving",
+    "apnea_hunting",
+    "virtual_activity",
+    "obstacle",
+    "",
+    "",
+    "breathing",
+    "",
+    "",
+    "sail_race",
+    "",
+    "ultra",
+    "indoor_climbing",
+    "bouldering",
+    "hiit",
+];
+
+/// Name of a FIT `sport` value; 53 (diving) sits past a gap in the profile
+pub fn sport_name(code: u16) -> Option<&'static str> {
+    match code {
+        53 => Some("diving"),
+        _ => SPORTS.get(code as usize).copied(),
+    }
+}
+
+/// Name of a FIT `sub_sport` value
+pub fn sub_sport_name(code: u16) -> Option<&'static str> {
+    SUB_SPORTS
+        .get(code as usize)
+        .copied()
+        .filter(|name| !name.is_empty())
+}
+
+/// Normalise a sport string from a FIT file or device ("Trail Running" -> "trail_running")
+pub fn normalize_sport(raw: &str) -> String {
+    raw.trim().to_ascii_lowercase().replace([' ', '-'], "_")
+}
+
+/// Activity type for a FIT sport and sub-sport, using their profile names
+pub fn activity_type(sport: &str, sub_sport: Option<&str>) -> Option<&'static str> {
+    let activity_type = match (sport, sub_sport.unwrap_or("generic")) {
+        ("running", "trail" | "ultra") => "Trail Run",
+        ("running", _) => "Run",
+        ("walking", _) => "Walk",
+        ("hiking", _) => "Hike",
+        ("mountaineering", _) => "Mountaineering",
+        ("rock_climbing" | "floor_climbing", _)
+        | ("training", "indoor_climbing" | "bouldering") => "Climbing",
+        ("alpine_skiing" | "cross_country_skiing" | "snowboarding", "backcountry") => "Ski Touring",
+        ("alpine_skiing", _) => "Alpine Ski",
+        ("cross_country_skiing", _) => "Nordic Ski",
+        ("snowboarding", _) => "Snowboard",
+        ("snowshoeing", _) => "Snowshoe",
+        ("cycling" | "e_biking", "mountain" | "downhill" | "e_bike_mountain") => "Mountain Bike",
+        ("cycling", _) => "Ride",
+        ("e_biking", _) => "E-Bike Ride",
+        ("swimming", _) => "Swimming",
+        ("paddling" | "kayaking" | "stand_up_paddleboarding" | "rafting", _) => "Paddle",
+        ("rowing", _) => "Rowing",
+        ("training", "yoga" | "pilates" | "flexibility_training" | "breathing") => "Yoga",
+        ("training" | "fitness_equipment", _) => "Strength",
+        // Devices without a sport field sometimes put the activity in the sub-sport
+        ("generic", "trail") => "Trail Run",
+        ("generic", "strength_training" | "hiit") => "Strength",
+        ("generic", "yoga" | "pilates") => "Yoga",
+        _ => return None,
+    };
+    Some(activity_type)
+}
+
+/// FIT sport and sub-sport names written when exporting an activity type;
+/// custom types are exported as generic
+fn fit_sport_names(activity_type: &str) -> (&'static str, &'static str) {
+    match activity_type {
+        "Hike" => ("hiking", "generic"),
+        "Walk" => ("walking", "generic"),
+        "Run" => ("running", "generic"),
+        "Trail Run" => ("running", "trail"),
+        "Mountaineering" => ("mountaineering", "generic"),
+        "Climbing" => ("rock_climbing", "generic"),
+        "Ski Touring" => ("alpine_skiing", "backcountry"),
+        "Alpine Ski" => ("alpine_skiing", "generic"),
+        "Nordic Ski" => ("cross_country_skiing", "generic"),
+        "Snowboard" => ("snowboarding", "generic"),
+        "Snowshoe" => ("snowshoeing", "generic"),
+        "Ride" => ("cycling", "generic"),
+        "Mountain Bike" => ("cycling", "mountain"),
+        "E-Bike Ride" => ("e_biking", "generic"),
+        "Swimming" => ("swimming", "generic"),
+        "Paddle" => ("paddling", "generic"),
+        "Rowing" => ("rowing", "generic"),
+        "Strength" => ("training", "strength_training"),
+        "Yoga" => ("training", "yoga"),
+        _ => ("generic", "generic"),
+    }
+}
+
+/// FIT `sport` and `sub_sport` values for an activity type
+pub fn fit_sport_codes(activity_type: &str) -> (u8, u8) {
+    let (sport, sub_sport) = fit_sport_names(activity_type);
+    let code =
+        |names: &[&str], name: &str| names.iter().position(|n| *n == name).unwrap_or(0) as u8;
+    (code(SPORTS, sport), code(SUB_SPORTS, sub_sport))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enum_names() {
+        assert_eq!(sport_name(17), Some("hiking"));
+        assert_eq!(sport_name(35), Some("snowshoeing"));
+        assert_eq!(sport_name(53), Some("diving"));
+        assert_eq!(sport_name(52), None);
+        assert_eq!(sub_sport_name(37), Some("backcountry"));
+        assert_eq!(sub_sport_name(67), Some("ultra"));
+        assert_eq!(sub_sport_name(61), None);
+    }
+
+    #[test]
+    fn test_activity_type() {
+        assert_eq!(activity_type("running", Some("trail")), Some("Trail Run"));
+        assert_eq!(activity_type("running", None), Some("Run"));
+        assert_eq!(
+            activity_type("alpine_skiing", Some("backcountry")),
+            Some("Ski Touring")
+        );
+        assert_eq!(
+            activity_type("alpine_skiing", Some("resort")),
+            Some("Alpine Ski")
+        );
+        assert_eq!(
+            activity_type("cycling", Some("mountain")),
+            Some("Mountain Bike")
+        );
+        assert_eq!(
+            activity_type("training", Some("strength_training")),
+            Some("Strength")
+        );
+        assert_eq!(activity_type("soccer", None), None);
+        assert_eq!(
+            activity_type(&normalize_sport("Snowshoeing"), None),
+            Some("Snowshoe")
+        );
+    }
+
+    #[test]
+    fn test_mapped_types_are_builtin() {
+        let sports = SPORTS.iter().copied().chain(["diving"]);
+        for sport in sports {
+            for sub_sport in SUB_SPORTS.iter().map(|s| Some(*s)).chain([None]) {
+                if let Some(name) = activity_type(sport, sub_sport) {
+                    assert!(
+                        BUILTIN_TYPES.iter().any(|(n, ..)| *n == name),
+                        "{} is not built in",
+                        name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fit_sport_codes_round_trip() {
+        assert_eq!(fit_sport_codes("Hike"), (17, 0));
+        assert_eq!(fit_sport_codes("My custom type"), (0, 0));
+        for (name, ..) in BUILTIN_TYPES {
+            let (sport, sub_sport) = fit_sport_codes(name);
+            let mapped = activity_type(
+                sport_name(sport.into()).unwrap(),
+                sub_sport_name(sub_sport.into()),
+            );
+            assert_eq!(mapped.unwrap_or(DEFAULT_TYPE), *name);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+
+use crate::error::AppError;
+use crate::types::{ActivityStreams, RecordStream, TrackRecord};
+
+/// Points per stream when the caller doesn't ask for a number
+pub const DEFAULT_MAX_POINTS: usize = 2000;
+
+/// Largest-triangle downsampling keeps the first and last points and at least one per bucket
+const MIN_POINTS: usize = 3;
+
+/// Horizontal axis streams are sampled along
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamAxis {
+    /// Elapsed time (s)
+    Time,
+    /// Distance (m); records without a distance are left out
+    Distance,
+}
+
+impl StreamAxis {
+    pub fn parse(axis: &str) -> Result<Self, AppError> {
+        match axis {
+            "time" => Ok(StreamAxis::Time),
+            "distance" => Ok(StreamAxis::Distance),
+            other => Err(AppError::InvalidArgument(format!(
+                "Unknown stream axis: {}",
+                other
+            ))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StreamAxis::Time => "time",
+            StreamAxis::Distance => "distance",
+        }
+    }
+
+    pub fn value(self, record: &TrackRecord) -> Option<f64> {
+        match self {
+            StreamAxis::Time => Some(record.elapsed_time),
+            StreamAxis::Distance => record.distance,
+        }
+    }
+}
+
+/// Value of a record field by its name in `TrackRecord`'s JSON form, or an
+/// `extras` key for any other name
+fn field_value(record: &TrackRecord, field: &str) -> Option<f64> {
+    match field {
+        "elapsedTime" => Some(record.elapsed_time),
+        "heartRate" => record.heart_rate.map(f64::from),
+        "distance" => record.distance,
+        "altitude" => record.altitude,
+        "speed" => record.speed,
+        "temperature" => record.temperature,
+        "positionLat" => record.position_lat,
+        "positionLong" => record.position_long,
+        "cleanPositionLat" => record.clean_position_lat,
+        "cleanPositionLong" => record.clean_position_long,
+        "gradeAdjustedSpeed" => record.grade_adjusted_speed,
+        "correctedAltitude" => record.corrected_altitude,
+        key => record.extras.get(key).copied(),
+    }
+}
+
+/// Indices of the points kept by Largest-Triangle-Three-Buckets downsampling
+/// to `threshold` points. Points must be ordered by x.
+pub fn lttb(points: &[(f64, f64)], threshold: usize) -> Vec<usize> {
+    let n = points.len();
+    if threshold >= n || threshold < MIN_POINTS {
+        return (0..n).collect();
+    }
+
+    let bucket = (n - 2) as f64 / (threshold - 2) as f64;
+    let bucket_start = |i: usize| ((i as f64 * bucket) as usize + 1).min(n - 1);
+    let mut kept = Vec::with_capacity(threshold);
+    let mut a = 0;
+    kept.push(a);
+
+    for i in 0..threshold - 2 {
+        // The third corner is the average of the next bucket, which is the last point at the end
+        let (start, end) = (bucket_start(i + 1), bucket_start(i + 2));
+        let next = &points[start..end.max(start + 1)];
+        let (avg_x, avg_y) = next.iter().fold((0.0, 0.0), |(x, y), p| (x + p.0, y + p.1));
+        let (avg_x, avg_y) = (avg_x / next.len() as f64, avg_y / next.len() as f64);
+
+        let (ax, ay) = points[a];
+        let mut best = (bucket_start(i), -1.0);
+        for (j, &(x, y)) in points
+            .iter()
+            .enumerate()
+            .take(bucket_start(i + 1))
+            .skip(bucket_start(i))
+        {
+            let area = ((ax - avg_x) * (y - ay) - (ax - x) * (avg_y - ay)).abs();
+            if area > best.1 {
+                best = (j, area);
+            }
+        }
+        a = best.0;
+        kept.push(a);
+    }
+
+    kept.push(n - 1);
+    kept
+}
+
+/// Selected fields of an activity's records as separate series along `axis`,
+/// each downsampled to at most `max_points`. Records missing a field are left
+/// out of its series, and names no record has give empty series.
+pub fn activity_streams(
+    activity_id: i64,
+    records: &[TrackRecord],
+    fields: &[String],
+    max_points: usize,
+    axis: StreamAxis,
+) -> Result<ActivityStreams, AppError> {
+    if max_points < MIN_POINTS {
+        return Err(AppError::InvalidArgument(format!(
+            "Streams need at least {} points",
+            MIN_POINTS
+        )));
+    }
+
+    let streams: BTreeMap<String, RecordStream> = fields
+        .iter()
+        .map(|field| {
+            let points: Vec<(f64, f64)> = records
+                .iter()
+                .filter_map(|r| Some((axis.value(r)?, field_value(r, field)?)))
+                .collect();
+            let kept = lttb(&points, max_points);
+            let stream = RecordStream {
+                x: kept.iter().map(|&i| points[i].0).collect(),
+                values: kept.iter().map(|&i| points[i].1).collect(),
+            };
+            (field.clone(), stream)
+        })
+        .collect();
+
+    Ok(ActivityStreams {
+        activity_id,
+        resolution_by: axis.name().to_string(),
+        record_count: records.len(),
+        streams,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn record(i: usize) -> TrackRecord {
+        TrackRecord {
+            elapsed_time: i as f64,
+            heart_rate: Some(100 + (i % 30) as i32),
+            distance: if i < 10 { None } else { Some(i as f64 * 1.5) },
+            altitude: Some(if i == 500 { 1500.0 } else { 1000.0 }),
+            zone: "zone1".to_string(),
+            extras: HashMap::from([("cadence".to_string(), 50.0)]),
+            ..TrackRecord::default()
+        }
+    }
+
+    #[test]
+    fn test_lttb_keeps_ends_and_peaks() {
+        let points: Vec<(f64, f64)> = (0..1000)
+            .map(|i| (i as f64, if i == 500 { 10.0 } else { 0.0 }))
+            .collect();
+        let kept = lttb(&points, 20);
+        assert_eq!(kept.len(), 20);
+        assert_eq!(kept[0], 0);
+        assert_eq!(kept[19], 999);
+        assert!(kept.contains(&500));
+        assert!(kept.windows(2).all(|w| w[0] < w[1]));
+
+        assert_eq!(lttb(&points[..5], 20), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_activity_streams() {
+        let records: Vec<TrackRecord> = (0..1000).map(record).collect();
+        let fields = ["heartRate", "altitude", "cadence", "power"].map(String::from);
+        let streams = activity_streams(7, &records, &fields, 100, StreamAxis::Time).unwrap();
+
+        assert_eq!(streams.record_count, 1000);
+        assert_eq!(streams.streams["heartRate"].x.len(), 100);
+        assert_eq!(streams.streams["cadence"].values.len(), 100);
+        assert!(streams.streams["altitude"].values.contains(&1500.0));
+        assert!(streams.streams["power"].x.is_empty());
+
+        let by_distance =
+            activity_streams(7, &records, &fields[..1], 5000, StreamAxis::Distance).unwrap();
+        assert_eq!(by_distance.streams["heartRate"].x.len(), 990);
+        assert_eq!(by_distance.streams["heartRate"].x[0], 15.0);
+
+        assert!(activity_streams(7, &records, &fields, 2, StreamAxis::Time).is_err());
+        assert!(StreamAxis::parse("laps").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+
+use crate::elevation::kalman_smooth;
+use crate::geo::{haversine, LocalProjection};
+use crate::types::TrackRecord;
+
+/// Smoothing applied to GPS positions after outlier rejection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "camelCase")]
+pub enum TrackSmoothing {
+    None,
+    /// Quadratic Savitzky–Golay filter over `window` fixes (odd)
+    SavitzkyGolay {
+        window: usize,
+    },
+    /// Random-walk Kalman filter on each horizontal axis
+    #[serde(rename_all = "camelCase")]
+    Kalman {
+        /// Expected position variance per second of travel (m²/s)
+        process_noise: f64,
+        /// Variance of a single GPS fix (m²)
+        measurement_noise: f64,
+    },
+}
+
+/// GPS track cleaning settings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackCleaning {
+    /// Fixes implying a faster speed than this (m/s) from the last good fix are rejected
+    pub max_speed: f64,
+    pub smoothing: TrackSmoothing,
+}
+
+impl Default for TrackCleaning {
+    fn default() -> Self {
+        Self {
+            max_speed: 20.0,
+            smoothing: TrackSmoothing::SavitzkyGolay { window: 7 },
+        }
+    }
+}
+
+/// Position as (lat, lon) in degrees
+pub type Fix = Option<(f64, f64)>;
+
+/// Reject fixes that could only be reached from the last accepted fix by
+/// moving faster than `max_speed`
+pub fn reject_outliers(times: &[f64], fixes: &[Fix], max_speed: f64) -> Vec<Fix> {
+    let mut last: Option<(f64, f64, f64)> = None;
+
+    times
+        .iter()
+        .zip(fixes)
+        .map(|(&t, fix)| {
+            let (lat, lon) = (*fix)?;
+            if let Some((last_t, last_lat, last_lon)) = last {
+                let dt = (t - last_t).max(1.0);
+                if haversine(last_lat, last_lon, lat, lon) / dt > max_speed {
+                    return None;
+                }
+            }
+            last = Some((t, lat, lon));
+            Some((lat, lon))
+        })
+        .collect()
+}
+
+/// Quadratic Savitzky–Golay smoothing of a series, narrowing the window at the ends
+pub fn savitzky_golay(values: &[f64], window: usize) -> Vec<f64> {
+    let half = window / 2;
+
+    (0..values.len())
+        .map(|i| {
+            let m = half.min(i).min(values.len() - 1 - i);
+            if m == 0 {
+                return values[i];
+            }
+            let mf = m as f64;
+            let norm = (2.0 * mf - 1.0) * (2.0 * mf + 1.0) * (2.0 * mf + 3.0);
+            (-(m as isize)..=m as isize)
+                .map(|k| {
+                    let weight = 3.0 * (3.0 * mf * mf + 3.0 * mf - 1.0) - 15.0 * (k * k) as f64;
+                    weight * values[(i as isize + k) as usize]
+                })
+                .sum::<f64>()
+                / norm
+        })
+        .collect()
+}
+
+/// Reject outliers and smooth a GPS track. The result has one entry per
+/// input fix; rejected or missing fixes are `None`.
+pub fn clean_track(times: &[f64], fixes: &[Fix], cleaning: &TrackCleaning) -> Vec<Fix> {
+    let accepted = reject_outliers(times, fixes, cleaning.max_speed);
+
+    // Smooth the accepted fixes in a local metric frame
+    let indices: Vec<usize> = (0..accepted.len())
+        .filter(|&i| accepted[i].is_some())
+        .collect();
+    let Some(&first) = indices.first() else {
+        return accepted;
+    };
+    let (origin_lat, origin_lon) = accepted[first].unwrap_or_default();
+    let projection = LocalProjection::new(origin_lat, origin_lon);
+    let (xs, ys): (Vec<f64>, Vec<f64>) = indices
+        .iter()
+        .filter_map(|&i| accepted[i].map(|(lat, lon)| projection.project(lat, lon)))
+        .unzip();
+
+    let (xs, ys) = match &cleaning.smoothing {
+        TrackSmoothing::None => (xs, ys),
+        TrackSmoothing::SavitzkyGolay { window } => {
+            (savitzky_golay(&xs, *window), savitzky_golay(&ys, *window))
+        }
+        TrackSmoothing::Kalman {
+            process_noise,
+            measurement_noise,
+        } => {
+            let point_times: Vec<f64> = indices.iter().map(|&i| times[i]).collect();
+            let smooth = |values: &[f64]| -> Vec<f64> {
+                let series: Vec<Option<f64>> = values.iter().map(|v| Some(*v)).collect();
+                kalman_smooth(&point_times, &series, *process_noise, *measurement_noise)
+                    .into_iter()
+                    .flatten()
+                    .collect()
+            };
+            (smooth(&xs), smooth(&ys))
+        }
+    };
+
+    let mut cleaned = vec![None; accepted.len()];
+    for ((&i, x), y) in indices.iter().zip(xs).zip(ys) {
+        cleaned[i] = Some(projection.unproject(x, y));
+    }
+    cleaned
+}
+
+/// Cumulative distance (m) along a track; records before the first fix get `None`
+pub fn cumulative_distance(fixes: &[Fix]) -> Vec<Option<f64>> {
+    let mut total: Option<f64> = None;
+    let mut last: Fix = None;
+
+    fixes
+        .iter()
+        .map(|fix| {
+            if let Some((lat, lon)) = fix {
+                total = Some(match last {
+                    Some((last_lat, last_lon)) => {
+                        total.unwrap_or(0.0) + haversine(last_lat, last_lon, *lat, *lon)
+                    }
+                    None => 0.0,
+                });
+                last = Some((*lat, *lon));
+            }
+            total
+        })
+        .collect()
+}
+
+/// Position of each stored record, preferring the cleaned track over raw fixes
+pub fn record_fixes(records: &[TrackRecord]) -> Vec<Fix> {
+    records
+        .iter()
+        .map(|r| {
+            r.clean_position_lat
+                .zip(r.clean_position_long)
+                .or(r.position_lat.zip(r.position_long))
+        })
+        .collect()
+}
+
+/// Positions of stored records, skipping records without a fix
+pub fn record_positions(records: &[TrackRecord]) -> Vec<(f64, f64)> {
+    record_fixes(records).into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(n: usize) -> Vec<f64> {
+        (0..n).map(|i| i as f64).collect()
+    }
+
+    /// Straight track heading north at roughly 1.1 m/s
+    fn straight_track(n: usize) -> Vec<Fix> {
+        (0..n)
+            .map(|i| Some((46.0 + i as f64 * 0.00001, 7.0)))
+            .collect()
+    }
+
+    #[test]
+    fn test_rejects_jump() {
+        let mut fixes = straight_track(5);
+        fixes[2] = Some((46.01, 7.0)); // ~1 km jump in one second
+        let cleaned = reject_outliers(&seconds(5), &fixes, 20.0);
+        assert_eq!(cleaned[2], None);
+        assert!(cleaned[3].is_some());
+    }
+
+    #[test]
+    fn test_savitzky_golay_preserves_line_and_quadratic() {
+        let line: Vec<f64> = (0..20).map(|i| 2.0 * i as f64 + 1.0).collect();
+        let quad: Vec<f64> = (0..20).map(|i| (i * i) as f64).collect();
+        for (a, b) in savitzky_golay(&line, 7).iter().zip(&line) {
+            assert!((a - b).abs() < 1e-9);
+        }
+        for (a, b) in savitzky_golay(&quad, 7).iter().zip(&quad) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_clean_track_keeps_alignment() {
+        let mut fixes = straight_track(10);
+        fixes[4] = None;
+        let cleaned = clean_track(&seconds(10), &fixes, &TrackCleaning::default());
+        assert_eq!(cleaned.len(), 10);
+        assert_eq!(cleaned[4], None);
+        let (lat, lon) = cleaned[9].unwrap();
+        assert!((lat - 46.00009).abs() < 1e-7);
+        assert!((lon - 7.0).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_cumulative_distance() {
+        let mut fixes = vec![None];
+        fixes.extend(straight_track(3));
+        let distance = cumulative_distance(&fixes);
+        assert_eq!(distance[0], None);
+        assert_eq!(distance[1], Some(0.0));
+        assert!((distance[3].unwrap() - 2.224).abs() < 0.01);
+    }
+}
@@ -0,0 +1,197 @@
+use std::ops::Range;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+
+use crate::error::AppError;
+use crate::parser::RawRecord;
+use crate::streams::StreamAxis;
+use crate::types::TrackRecord;
+
+/// Fewest records an edited activity may keep
+const MIN_RECORDS: usize = 2;
+
+/// Index of the first record at or past `value` along `axis`
+fn first_at(records: &[TrackRecord], axis: StreamAxis, value: f64) -> usize {
+    records
+        .iter()
+        .position(|r| axis.value(r).is_some_and(|v| v >= value))
+        .unwrap_or(records.len())
+}
+
+fn check_part(range: &Range<usize>) -> Result<(), AppError> {
+    if range.len() < MIN_RECORDS {
+        return Err(AppError::InvalidArgument(format!(
+            "Edited activities need at least {} records",
+            MIN_RECORDS
+        )));
+    }
+    Ok(())
+}
+
+/// Records of an activity between `start` and `end` (inclusive) along `axis`
+pub fn trim_range(
+    records: &[TrackRecord],
+    axis: StreamAxis,
+    start: f64,
+    end: f64,
+) -> Result<Range<usize>, AppError> {
+    if end < start {
+        return Err(AppError::InvalidArgument(
+            "Trim end is before its start".to_string(),
+        ));
+    }
+    let first = first_at(records, axis, start);
+    let last = records
+        .iter()
+        .rposition(|r| axis.value(r).is_some_and(|v| v <= end));
+
+    let range = match last {
+        Some(last) if last >= first => first..last + 1,
+        _ => 0..0,
+    };
+    check_part(&range)?;
+    Ok(range)
+}
+
+/// Split an activity's records into consecutive parts, starting a new part at
+/// the first record at or past each point along `axis`
+pub fn split_ranges(
+    records: &[TrackRecord],
+    axis: StreamAxis,
+    at: &[f64],
+) -> Result<Vec<Range<usize>>, AppError> {
+    if at.is_empty() {
+        return Err(AppError::InvalidArgument(
+            "No split points given".to_string(),
+        ));
+    }
+    let mut at = at.to_vec();
+    at.sort_by(f64::total_cmp);
+
+    let mut starts = vec![0];
+    starts.extend(at.iter().map(|&point| first_at(records, axis, point)));
+    starts.push(records.len());
+
+    let ranges: Vec<Range<usize>> = starts.windows(2).map(|w| w[0]..w[1]).collect();
+    for range in &ranges {
+        check_part(range)?;
+    }
+    Ok(ranges)
+}
+
+/// Time of a stored record
+pub fn record_time(record: &TrackRecord) -> Result<DateTime<Utc>, AppError> {
+    DateTime::parse_from_rfc3339(&record.timestamp)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| {
+            AppError::InvalidArgument(format!("Invalid record timestamp: {}", record.timestamp))
+        })
+}
+
+/// Stored records as raw records to rebuild an activity from. Distances are
+/// made relative to the first record, and derived values are left to be
+/// recomputed.
+pub fn raw_records(records: &[TrackRecord]) -> Result<Vec<RawRecord>, AppError> {
+    let start_distance = records.iter().find_map(|r| r.distance).unwrap_or(0.0);
+
+    records
+        .iter()
+        .map(|r| {
+            Ok(RawRecord {
+                timestamp: record_time(r)?,
+                heart_rate: r.heart_rate.and_then(|hr| u8::try_from(hr).ok()),
+                distance: r.distance.map(|d| d - start_distance),
+                altitude: r.altitude,
+                speed: r.speed,
+                temperature: r.temperature.map(|t| t.round() as i8),
+                position_lat: r.position_lat,
+                position_long: r.position_long,
+                extras: r.extras.clone(),
+            })
+        })
+        .collect()
+}
+
+/// File name for a part split from an activity, from the time the part starts
+pub fn part_filename(filename: &str, start: DateTime<Utc>) -> String {
+    let path = Path::new(filename);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(filename);
+    let stamp = start.format("%Y%m%d-%H%M%S");
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{}-{}.{}", stem, stamp, extension),
+        None => format!("{}-{}", stem, stamp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn record(i: usize) -> TrackRecord {
+        TrackRecord {
+            timestamp: format!("2024-07-14T08:{:02}:00+02:00", i),
+            elapsed_time: i as f64 * 60.0,
+            heart_rate: Some(120),
+            distance: if i == 0 { None } else { Some(i as f64 * 100.0) },
+            altitude: Some(1000.0),
+            temperature: Some(11.6),
+            zone: "zone2".to_string(),
+            ..TrackRecord::default()
+        }
+    }
+
+    #[test]
+    fn test_trim_range() {
+        let records: Vec<TrackRecord> = (0..10).map(record).collect();
+        assert_eq!(
+            trim_range(&records, StreamAxis::Time, 60.0, 300.0).unwrap(),
+            1..6
+        );
+        assert_eq!(
+            trim_range(&records, StreamAxis::Distance, 0.0, 250.0).unwrap(),
+            1..3
+        );
+        assert_eq!(
+            trim_range(&records, StreamAxis::Time, 0.0, 1e9).unwrap(),
+            0..10
+        );
+        assert!(trim_range(&records, StreamAxis::Time, 100.0, 110.0).is_err());
+        assert!(trim_range(&records, StreamAxis::Time, 300.0, 60.0).is_err());
+    }
+
+    #[test]
+    fn test_split_ranges() {
+        let records: Vec<TrackRecord> = (0..10).map(record).collect();
+        assert_eq!(
+            split_ranges(&records, StreamAxis::Time, &[420.0, 150.0]).unwrap(),
+            vec![0..3, 3..7, 7..10]
+        );
+        assert!(split_ranges(&records, StreamAxis::Time, &[30.0]).is_err());
+        assert!(split_ranges(&records, StreamAxis::Time, &[]).is_err());
+    }
+
+    #[test]
+    fn test_raw_records() {
+        let records: Vec<TrackRecord> = (0..10).map(record).collect();
+        let raw = raw_records(&records[3..]).unwrap();
+        assert_eq!(
+            raw[0].timestamp,
+            Utc.with_ymd_and_hms(2024, 7, 14, 6, 3, 0).unwrap()
+        );
+        assert_eq!(raw[0].distance, Some(0.0));
+        assert_eq!(raw[6].distance, Some(600.0));
+        assert_eq!(raw[0].temperature, Some(12));
+    }
+
+    #[test]
+    fn test_part_filename() {
+        let start = Utc.with_ymd_and_hms(2024, 7, 15, 7, 30, 0).unwrap();
+        assert_eq!(part_filename("trek.fit", start), "trek-20240715-073000.fit");
+        assert_eq!(part_filename("trek", start), "trek-20240715-073000");
+    }
+}
@@ -0,0 +1,255 @@
+use chrono::NaiveDate;
+
+use crate::error::AppError;
+use crate::geo::haversine;
+use crate::types::{Activity, Trip, TripDay, ZoneTimes};
+
+/// Furthest (m) an activity may end from where the next one starts for both
+/// to be suggested as stages of one trip
+const MAX_CHAIN_DISTANCE: f64 = 1_000.0;
+
+/// Most days between chained stages; 0 allows several stages on one day
+const MAX_DAY_GAP: i64 = 1;
+
+/// Fewest days a suggested trip spans
+const MIN_TRIP_DAYS: i64 = 2;
+
+/// Activity considered for trip suggestions, with where its track starts and ends
+#[derive(Debug, Clone)]
+pub struct TripCandidate {
+    pub id: i64,
+    pub date: NaiveDate,
+    pub start: Option<(f64, f64)>,
+    pub end: Option<(f64, f64)>,
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, AppError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| AppError::InvalidArgument(format!("Invalid activity date: {}", date)))
+}
+
+impl TripCandidate {
+    pub fn new(id: i64, date: &str, track: &[(f64, f64)]) -> Result<Self, AppError> {
+        Ok(Self {
+            id,
+            date: parse_date(date)?,
+            start: track.first().copied(),
+            end: track.last().copied(),
+        })
+    }
+
+    /// Whether `next` starts the day of or after this one, where this one ends
+    fn chains_to(&self, next: &TripCandidate) -> bool {
+        let gap = (next.date - self.date).num_days();
+        match (self.end, next.start) {
+            (Some(end), Some(start)) => {
+                (0..=MAX_DAY_GAP).contains(&gap)
+                    && haversine(end.0, end.1, start.0, start.1) <= MAX_CHAIN_DISTANCE
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Chains of activities on consecutive days, each starting where the previous
+/// one ended, like the stages of a hut-to-hut trek. Candidates must be in
+/// start time order; chains spanning a single day are left out.
+pub fn suggest_trips(candidates: &[TripCandidate]) -> Vec<Vec<i64>> {
+    let mut chains: Vec<Vec<&TripCandidate>> = Vec::new();
+    for candidate in candidates {
+        match chains.last_mut() {
+            Some(chain) if chain.last().is_some_and(|last| last.chains_to(candidate)) => {
+                chain.push(candidate)
+            }
+            _ => chains.push(vec![candidate]),
+        }
+    }
+
+    chains
+        .into_iter()
+        .filter(|chain| match (chain.first(), chain.last()) {
+            (Some(first), Some(last)) => (last.date - first.date).num_days() + 1 >= MIN_TRIP_DAYS,
+            _ => false,
+        })
+        .map(|chain| chain.into_iter().map(|c| c.id).collect())
+        .collect()
+}
+
+/// Name for a suggested trip from where it starts and ends
+pub fn suggested_name(activities: &[Activity]) -> String {
+    let start = activities.first().and_then(|a| a.start_location.as_deref());
+    let end = activities.last().and_then(|a| a.end_location.as_deref());
+    match (start, end) {
+        (Some(start), Some(end)) if start != end => format!("{} – {}", start, end),
+        (Some(place), _) | (_, Some(place)) => format!("Trip from {}", place),
+        _ => format!(
+            "Trip of {}",
+            activities
+                .first()
+                .map(|a| a.activity_date.as_str())
+                .unwrap_or_default()
+        ),
+    }
+}
+
+/// Sum of the values present; None when no activity has one
+fn sum(values: impl Iterator<Item = Option<f64>>) -> Option<f64> {
+    values.fold(None, |total, value| match (total, value) {
+        (Some(t), Some(v)) => Some(t + v),
+        (t, v) => t.or(v),
+    })
+}
+
+fn sum_zones(activities: &[&Activity]) -> ZoneTimes {
+    let mut zones = ZoneTimes::default();
+    for a in activities {
+        zones.zone1 += a.zones.zone1;
+        zones.zone2 += a.zones.zone2;
+        zones.zone3 += a.zones.zone3;
+        zones.zone4 += a.zones.zone4;
+        zones.zone5 += a.zones.zone5;
+    }
+    zones
+}
+
+/// Totals of a trip over its activities, which must be in start time order
+pub fn trip_summary(id: i64, name: &str, activities: &[Activity]) -> Result<Trip, AppError> {
+    let (Some(first), Some(last)) = (activities.first(), activities.last()) else {
+        return Err(AppError::NotFound(format!("Trip {}", id)));
+    };
+    let all: Vec<&Activity> = activities.iter().collect();
+
+    Ok(Trip {
+        id,
+        name: name.to_string(),
+        start_date: first.activity_date.clone(),
+        end_date: last.activity_date.clone(),
+        day_count: (parse_date(&last.activity_date)? - parse_date(&first.activity_date)?).num_days()
+            as i32
+            + 1,
+        activity_count: activities.len() as i32,
+        total_distance: sum(activities.iter().map(|a| a.total_distance)),
+        total_duration: activities.iter().map(|a| a.total_duration).sum(),
+        elevation_gain: sum(activities.iter().map(|a| a.elevation_gain)),
+        elevation_loss: sum(activities.iter().map(|a| a.elevation_loss)),
+        zones: sum_zones(&all),
+    })
+}
+
+/// Totals per day of a trip, numbering days from its first date so rest days
+/// leave a gap. Activities must be in start time order.
+pub fn trip_days(activities: &[Activity]) -> Result<Vec<TripDay>, AppError> {
+    let Some(first) = activities.first() else {
+        return Ok(Vec::new());
+    };
+    let start = parse_date(&first.activity_date)?;
+
+    let mut days: Vec<(String, Vec<&Activity>)> = Vec::new();
+    for activity in activities {
+        match days.last_mut() {
+            Some((date, day)) if *date == activity.activity_date => day.push(activity),
+            _ => days.push((activity.activity_date.clone(), vec![activity])),
+        }
+    }
+
+    days.into_iter()
+        .map(|(date, day)| {
+            Ok(TripDay {
+                day: (parse_date(&date)? - start).num_days() as i32 + 1,
+                date,
+                activity_ids: day.iter().map(|a| a.id).collect(),
+                total_distance: sum(day.iter().map(|a| a.total_distance)),
+                total_duration: day.iter().map(|a| a.total_duration).sum(),
+                elevation_gain: sum(day.iter().map(|a| a.elevation_gain)),
+                elevation_loss: sum(day.iter().map(|a| a.elevation_loss)),
+                zones: sum_zones(&day),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn activity(id: i64, date: &str, distance: Option<f64>, gain: f64) -> Activity {
+        Activity {
+            id,
+            filename: "stage.fit".to_string(),
+            activity_type: "Hike".to_string(),
+            activity_date: date.to_string(),
+            total_duration: 3600.0,
+            total_distance: distance,
+            zones: ZoneTimes {
+                zone2: 3600.0,
+                ..ZoneTimes::default()
+            },
+            elevation_gain: Some(gain),
+            start_location: Some(format!("Hut {}", id)),
+            end_location: Some(format!("Hut {}", id + 1)),
+            ..Activity::default()
+        }
+    }
+
+    fn candidate(id: i64, date: &str, start: (f64, f64), end: (f64, f64)) -> TripCandidate {
+        TripCandidate::new(id, date, &[start, (46.5, 7.5), end]).unwrap()
+    }
+
+    #[test]
+    fn test_suggest_trips() {
+        let (a, b, c, d) = ((46.0, 7.0), (46.1, 7.1), (46.2, 7.2), (46.3, 7.3));
+        let candidates = [
+            // A day hike returning to its start
+            candidate(1, "2024-07-01", a, a),
+            // A four-stage trek with two stages on the third day and a rest day after it
+            candidate(2, "2024-07-10", a, b),
+            candidate(3, "2024-07-11", (46.1005, 7.1), c),
+            candidate(4, "2024-07-12", c, d),
+            candidate(5, "2024-07-12", d, c),
+            // Next stage after the rest day is too late
+            candidate(6, "2024-07-14", c, b),
+            // Same day, but starting elsewhere
+            candidate(7, "2024-07-14", a, d),
+            candidate(8, "2024-07-15", d, a),
+        ];
+
+        assert_eq!(
+            suggest_trips(&candidates),
+            vec![vec![2, 3, 4, 5], vec![7, 8]]
+        );
+        assert!(suggest_trips(&[]).is_empty());
+        assert!(TripCandidate::new(1, "14.07.2024", &[]).is_err());
+    }
+
+    #[test]
+    fn test_trip_summary_and_days() {
+        let activities = [
+            activity(1, "2024-07-10", Some(12_000.0), 900.0),
+            activity(2, "2024-07-11", None, 400.0),
+            activity(3, "2024-07-11", Some(3_000.0), 100.0),
+            activity(4, "2024-07-13", Some(15_000.0), 1_100.0),
+        ];
+
+        let trip = trip_summary(9, "Haute Route", &activities).unwrap();
+        assert_eq!(
+            (trip.start_date.as_str(), trip.end_date.as_str()),
+            ("2024-07-10", "2024-07-13")
+        );
+        assert_eq!((trip.day_count, trip.activity_count), (4, 4));
+        assert_eq!(trip.total_distance, Some(30_000.0));
+        assert_eq!(trip.elevation_gain, Some(2_500.0));
+        assert_eq!(trip.elevation_loss, None);
+        assert_eq!(trip.zones.zone2, 4.0 * 3600.0);
+        assert!(trip_summary(9, "Empty", &[]).is_err());
+
+        let days = trip_days(&activities).unwrap();
+        assert_eq!(
+            days.iter().map(|d| d.day).collect::<Vec<_>>(),
+            vec![1, 2, 4]
+        );
+        assert_eq!(days[1].activity_ids, vec![2, 3]);
+        assert_eq!(days[1].total_distance, Some(3_000.0));
+        assert_eq!(days[1].total_duration, 7200.0);
+        assert_eq!(suggested_name(&activities), "Hut 1 – Hut 5");
+    }
+}
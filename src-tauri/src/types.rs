@@ -1,5 +1,6 @@
+use crate::zones::ZoneModel;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Zone time breakdown in seconds
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -12,12 +13,30 @@ pub struct ZoneTimes {
     pub zone5: f64,
 }
 
+impl ZoneTimes {
+    /// Add seconds to the named zone ("zone1".."zone5"); unknown names are ignored
+    pub fn add(&mut self, zone: &str, seconds: f64) {
+        match zone {
+            "zone1" => self.zone1 += seconds,
+            "zone2" => self.zone2 += seconds,
+            "zone3" => self.zone3 += seconds,
+            "zone4" => self.zone4 += seconds,
+            "zone5" => self.zone5 += seconds,
+            _ => {}
+        }
+    }
+}
+
 /// Activity summary for list view
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Activity {
     pub id: i64,
     pub filename: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub notes: Option<String>,
+    pub tags: Vec<String>,
     pub activity_type: String,
     pub activity_date: String,
     pub start_time: String,
@@ -26,13 +45,75 @@ pub struct Activity {
     pub total_distance: Option<f64>,
     pub zones: ZoneTimes,
     pub elevation_gain: Option<f64>,
+    pub elevation_loss: Option<f64>,
     pub max_altitude: Option<f64>,
     pub min_altitude: Option<f64>,
+    /// Average moving grade-adjusted speed (m/s)
+    pub avg_grade_adjusted_speed: Option<f64>,
+    /// Equivalent flat distance including climbing (m)
+    pub effort_distance: Option<f64>,
+    /// Altitude source used for gain/loss: "device" or "dem"
+    pub elevation_source: String,
+    /// Route this activity was matched to, if any
+    pub route_id: Option<i64>,
+    pub start_location: Option<String>,
+    pub end_location: Option<String>,
+    pub high_point_location: Option<String>,
+    /// Administrative regions of the start, from the offline geocoder
+    pub admin1: Option<String>,
+    pub admin2: Option<String>,
+    pub country_code: Option<String>,
+    /// Straight-line distance between start and end (m)
+    pub start_end_distance: Option<f64>,
 }
 
-/// Extended track record with all available data
+/// Criteria for listing activities; unset fields match everything
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityFilter {
+    pub country_code: Option<String>,
+    pub admin1: Option<String>,
+    pub admin2: Option<String>,
+    /// Only point-to-point traverses when true, only loops and out-and-backs when false
+    pub traverse: Option<bool>,
+    /// Activities must carry every one of these tags
+    pub tags: Option<Vec<String>>,
+}
+
+/// Edits to an activity's descriptive fields; unset fields are left unchanged
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityMetadata {
+    pub title: Option<String>,
+    /// An empty string clears the description
+    pub description: Option<String>,
+    /// An empty string clears the notes
+    pub notes: Option<String>,
+    /// Replaces all tags
+    pub tags: Option<Vec<String>>,
+}
+
+/// Tag with the number of activities carrying it
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub name: String,
+    pub activity_count: i32,
+}
+
+/// Administrative region with the number of activities starting in it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Region {
+    pub country_code: Option<String>,
+    pub admin1: Option<String>,
+    pub admin2: Option<String>,
+    pub activity_count: i32,
+}
+
+/// Extended track record with all available data
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TrackRecord {
     pub timestamp: String,
     pub elapsed_time: f64,
@@ -43,7 +124,13 @@ pub struct TrackRecord {
     pub temperature: Option<f64>,
     pub position_lat: Option<f64>,
     pub position_long: Option<f64>,
+    /// Position after outlier rejection and smoothing
+    pub clean_position_lat: Option<f64>,
+    pub clean_position_long: Option<f64>,
     pub zone: String,
+    pub grade_adjusted_speed: Option<f64>,
+    /// Terrain altitude sampled from DEM tiles, when corrected
+    pub corrected_altitude: Option<f64>,
     pub extras: HashMap<String, f64>,
 }
 
@@ -51,18 +138,8 @@ pub struct TrackRecord {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ActivityDetail {
-    pub id: i64,
-    pub filename: String,
-    pub activity_type: String,
-    pub activity_date: String,
-    pub start_time: String,
-    pub location: Option<String>,
-    pub total_duration: f64,
-    pub total_distance: Option<f64>,
-    pub zones: ZoneTimes,
-    pub elevation_gain: Option<f64>,
-    pub max_altitude: Option<f64>,
-    pub min_altitude: Option<f64>,
+    #[serde(flatten)]
+    pub activity: Activity,
     pub records: Vec<TrackRecord>,
 }
 
@@ -74,3 +151,361 @@ pub struct ZoneSummary {
     pub activity_count: i32,
     pub zones: ZoneTimes,
 }
+
+/// One field of an activity's records along the stream axis, as parallel arrays
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordStream {
+    pub x: Vec<f64>,
+    pub values: Vec<f64>,
+}
+
+/// Selected record fields of an activity, downsampled for charts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityStreams {
+    pub activity_id: i64,
+    /// "time" (x is elapsed seconds) or "distance" (x is metres)
+    pub resolution_by: String,
+    /// Records before downsampling
+    pub record_count: usize,
+    pub streams: BTreeMap<String, RecordStream>,
+}
+
+/// Per-distance split of an activity (per km or per mile)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Split {
+    pub index: u32,
+    /// Distance covered in this split (m); the last split may be partial
+    pub distance: f64,
+    pub elapsed_time: f64,
+    pub moving_time: f64,
+    /// Moving pace in seconds per split unit
+    pub moving_pace: Option<f64>,
+    /// Grade-adjusted pace in seconds per split unit
+    pub grade_adjusted_pace: Option<f64>,
+    pub elevation_gain: f64,
+    pub elevation_loss: f64,
+    pub avg_heart_rate: Option<f64>,
+    pub zones: ZoneTimes,
+}
+
+/// Simplified activity track for map display
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityTrack {
+    pub activity_id: i64,
+    /// (lat, lon) pairs, when not encoded
+    pub points: Option<Vec<[f64; 2]>>,
+    /// Google encoded polyline, when requested
+    pub polyline: Option<String>,
+}
+
+/// Group of activities following the same track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Route {
+    pub id: i64,
+    pub name: String,
+    pub attempt_count: i32,
+    /// Distance of the first attempt (m)
+    pub distance: Option<f64>,
+    pub best_duration: f64,
+    pub last_attempt: String,
+}
+
+/// One attempt of a route, compared against the fastest attempt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteAttempt {
+    #[serde(flatten)]
+    pub activity: Activity,
+    pub avg_heart_rate: Option<f64>,
+    pub max_heart_rate: Option<i32>,
+    /// Seconds slower than the fastest attempt; 0 for the fastest
+    pub behind_best: f64,
+    /// 1 for the fastest attempt
+    pub rank: i32,
+}
+
+/// Activities of a multi-day outing, with totals over all of them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trip {
+    pub id: i64,
+    pub name: String,
+    pub start_date: String,
+    pub end_date: String,
+    /// Days from the first to the last date, including rest days
+    pub day_count: i32,
+    pub activity_count: i32,
+    pub total_distance: Option<f64>,
+    pub total_duration: f64,
+    pub elevation_gain: Option<f64>,
+    pub elevation_loss: Option<f64>,
+    pub zones: ZoneTimes,
+}
+
+/// Totals of the activities of one day of a trip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TripDay {
+    pub date: String,
+    /// 1 for the first day of the trip
+    pub day: i32,
+    pub activity_ids: Vec<i64>,
+    pub total_distance: Option<f64>,
+    pub total_duration: f64,
+    pub elevation_gain: Option<f64>,
+    pub elevation_loss: Option<f64>,
+    pub zones: ZoneTimes,
+}
+
+/// Trip with its day-by-day breakdown and activities in date order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TripDetail {
+    #[serde(flatten)]
+    pub trip: Trip,
+    pub days: Vec<TripDay>,
+    pub activities: Vec<Activity>,
+}
+
+/// Activities chaining end-to-start on consecutive days, not yet in a trip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TripSuggestion {
+    pub name: String,
+    pub activities: Vec<Activity>,
+}
+
+/// Simplified tracks of a trip's activities joined in date order for map display
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TripTrack {
+    pub trip_id: i64,
+    /// (lat, lon) pairs, when not encoded
+    pub points: Option<Vec<[f64; 2]>>,
+    /// Google encoded polyline, when requested
+    pub polyline: Option<String>,
+}
+
+/// User-defined section of track with efforts matched on import
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Segment {
+    pub id: i64,
+    pub name: String,
+    /// (lat, lon) pairs from start to end
+    pub points: Vec<[f64; 2]>,
+    /// Length of the segment (m)
+    pub distance: f64,
+    pub effort_count: i32,
+    pub best_time: Option<f64>,
+}
+
+/// One traversal of a segment, ranked by time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentEffort {
+    pub id: i64,
+    pub segment_id: i64,
+    pub activity_id: i64,
+    pub activity_date: String,
+    /// Timestamp of the record closest to the segment start
+    pub start_time: String,
+    pub elapsed_time: f64,
+    pub avg_heart_rate: Option<f64>,
+    /// Net altitude change per hour (m/h)
+    pub vertical_speed: Option<f64>,
+    /// 1 for the fastest effort
+    pub rank: i32,
+}
+
+/// Peak, hut or pass from the imported POI database
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Poi {
+    pub id: i64,
+    pub name: String,
+    /// "peak", "hut" or "pass"
+    pub kind: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub elevation: Option<f64>,
+}
+
+/// A POI passed during an activity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoiVisit {
+    pub poi: Poi,
+    pub activity_id: i64,
+    pub activity_date: String,
+    /// Timestamp of the closest approach
+    pub visited_at: String,
+    /// Closest approach (m)
+    pub distance: f64,
+}
+
+/// A POI visited at least once, with its visit history summarised
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaggedPoi {
+    #[serde(flatten)]
+    pub poi: Poi,
+    pub visit_count: i32,
+    pub first_visit: String,
+    pub last_visit: String,
+}
+
+/// Activity type with its display style
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityTypeInfo {
+    pub name: String,
+    /// Lucide icon name
+    pub icon: String,
+    /// Hex colour such as "#16a34a"
+    pub color: String,
+    /// Built-in types can be restyled but not deleted
+    pub builtin: bool,
+    pub activity_count: i32,
+}
+
+/// Editable fields of a gear item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GearDetails {
+    pub name: String,
+    /// Kind of gear such as "boots", "poles" or "pack"
+    pub gear_type: String,
+    pub brand: Option<String>,
+    pub model: Option<String>,
+    /// YYYY-MM-DD
+    pub purchase_date: Option<String>,
+    /// YYYY-MM-DD; retired gear is no longer added to new activities
+    pub retired_date: Option<String>,
+    /// Distance (m) after which the item should be replaced
+    pub distance_limit: Option<f64>,
+    /// Elevation gain (m) after which the item should be replaced
+    pub elevation_limit: Option<f64>,
+    /// Hours of use after which the item should be replaced
+    pub hours_limit: Option<f64>,
+    pub notes: Option<String>,
+    /// Activity types the item is added to on import
+    #[serde(default)]
+    pub default_types: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Gear {
+    pub id: i64,
+    #[serde(flatten)]
+    pub details: GearDetails,
+}
+
+/// A limit a gear item has reached
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GearAlert {
+    /// "distance", "elevation" or "hours"
+    pub metric: String,
+    pub limit: f64,
+    pub value: f64,
+}
+
+/// Gear item with its cumulative use over all linked activities
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GearUsage {
+    #[serde(flatten)]
+    pub gear: Gear,
+    pub activity_count: i32,
+    /// Total distance (m)
+    pub distance: f64,
+    /// Total elevation gain (m)
+    pub elevation_gain: f64,
+    pub hours: f64,
+    pub last_used: Option<String>,
+    pub alerts: Vec<GearAlert>,
+}
+
+/// Editable fields of an athlete profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileDetails {
+    pub name: String,
+    /// YYYY-MM-DD
+    pub birthdate: Option<String>,
+    pub sex: Option<String>,
+    /// Body weight (kg)
+    pub weight: Option<f64>,
+    pub resting_hr: Option<i32>,
+    pub max_hr: Option<i32>,
+    #[serde(default)]
+    pub zone_model: ZoneModel,
+}
+
+/// Athlete profile owning activities and gear
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub id: i64,
+    #[serde(flatten)]
+    pub details: ProfileDetails,
+    pub activity_count: i32,
+}
+
+/// Activities included in an analytics export; unset fields match everything
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyticsFilter {
+    /// First activity date, YYYY-MM-DD
+    pub start_date: Option<String>,
+    /// Last activity date, YYYY-MM-DD
+    pub end_date: Option<String>,
+    pub activity_types: Option<Vec<String>>,
+}
+
+/// Files written by an analytics export and their row counts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyticsExport {
+    pub activities_path: String,
+    pub records_path: String,
+    pub activity_count: usize,
+    pub record_count: usize,
+}
+
+/// A database backup file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupInfo {
+    pub path: String,
+    /// When the backup was taken (RFC 3339)
+    pub created_at: String,
+    /// File size in bytes
+    pub size: u64,
+}
+
+/// Outcome of re-parsing all activities from their original files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReparseSummary {
+    pub reparsed: usize,
+    /// Activities imported before original files were kept
+    pub without_source: usize,
+    /// Activities whose DEM elevation correction couldn't be re-applied
+    pub elevation_corrections_dropped: Vec<i64>,
+    pub failures: Vec<ReparseFailure>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReparseFailure {
+    pub activity_id: i64,
+    pub error: String,
+}
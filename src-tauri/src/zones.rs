@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 /// Heart rate zone configuration
 #[derive(Debug, Clone, Copy)]
 pub struct HrZone {
@@ -6,7 +8,7 @@ pub struct HrZone {
     pub max: u8,
 }
 
-/// Personalized HR zones based on user profile
+/// Built-in HR zones, used for profiles without their own zone model
 pub const HR_ZONES: [HrZone; 5] = [
     HrZone { name: "zone1", min: 0,   max: 116 },  // Recovery
     HrZone { name: "zone2", min: 117, max: 136 },  // Aerobic
@@ -24,6 +26,69 @@ pub fn get_zone(hr: u8) -> &'static str {
         .unwrap_or("zone1")
 }
 
+/// How a profile's heart rate zones are derived
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneModel {
+    /// The built-in zones in `HR_ZONES`
+    #[default]
+    Fixed,
+    /// Fractions of maximum heart rate
+    MaxHr,
+    /// Fractions of heart rate reserve above resting heart rate (Karvonen)
+    HeartRateReserve,
+}
+
+impl ZoneModel {
+    pub fn parse(model: &str) -> Option<Self> {
+        match model {
+            "fixed" => Some(ZoneModel::Fixed),
+            "max_hr" => Some(ZoneModel::MaxHr),
+            "heart_rate_reserve" => Some(ZoneModel::HeartRateReserve),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ZoneModel::Fixed => "fixed",
+            ZoneModel::MaxHr => "max_hr",
+            ZoneModel::HeartRateReserve => "heart_rate_reserve",
+        }
+    }
+}
+
+/// Fractions of the model's heart rate range at which zones 2 to 5 start
+const ZONE_FRACTIONS: [f64; 4] = [0.6, 0.7, 0.8, 0.9];
+
+/// Lowest heart rate of zones 2 to 5 under a zone model, or None to use the
+/// built-in zones. Falls back to the built-in zones when the heart rates the
+/// model needs are missing.
+pub fn zone_thresholds(
+    model: ZoneModel,
+    max_hr: Option<u8>,
+    resting_hr: Option<u8>,
+) -> Option<[u8; 4]> {
+    let (base, range) = match model {
+        ZoneModel::Fixed => return None,
+        ZoneModel::MaxHr => (0.0, max_hr? as f64),
+        ZoneModel::HeartRateReserve => {
+            let (max_hr, resting_hr) = (max_hr?, resting_hr?);
+            (resting_hr as f64, max_hr.saturating_sub(resting_hr) as f64)
+        }
+    };
+    Some(ZONE_FRACTIONS.map(|fraction| (base + fraction * range).round() as u8))
+}
+
+/// Get zone name for a heart rate given the lowest heart rate of zones 2 to 5
+pub fn zone_from_thresholds(hr: u8, thresholds: &[u8; 4]) -> &'static str {
+    let index = thresholds
+        .iter()
+        .take_while(|threshold| hr >= **threshold)
+        .count();
+    HR_ZONES[index].name
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,4 +122,31 @@ mod tests {
         assert_eq!(get_zone(176), "zone5");
         assert_eq!(get_zone(255), "zone5");
     }
+
+    #[test]
+    fn test_zone_thresholds() {
+        assert_eq!(zone_thresholds(ZoneModel::Fixed, Some(190), Some(50)), None);
+        assert_eq!(
+            zone_thresholds(ZoneModel::MaxHr, Some(190), None),
+            Some([114, 133, 152, 171])
+        );
+        // Karvonen: 50 + fraction * 140
+        assert_eq!(
+            zone_thresholds(ZoneModel::HeartRateReserve, Some(190), Some(50)),
+            Some([134, 148, 162, 176])
+        );
+        assert_eq!(
+            zone_thresholds(ZoneModel::HeartRateReserve, Some(190), None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_zone_from_thresholds() {
+        let thresholds = [114, 133, 152, 171];
+        assert_eq!(zone_from_thresholds(113, &thresholds), "zone1");
+        assert_eq!(zone_from_thresholds(114, &thresholds), "zone2");
+        assert_eq!(zone_from_thresholds(170, &thresholds), "zone4");
+        assert_eq!(zone_from_thresholds(200, &thresholds), "zone5");
+    }
 }
@@ -27,7 +27,7 @@ export function ActivityCard({ activity, onSelect, onDelete, index = 0 }: Activi
 
   const handleDelete = (e: React.MouseEvent) => {
     e.stopPropagation();
-    if (confirm(`Delete "${activity.filename}"?`)) {
+    if (confirm(`Delete "${activity.title}"?`)) {
       onDelete(activity);
     }
   };
@@ -78,14 +78,10 @@ export function ActivityCard({ activity, onSelect, onDelete, index = 0 }: Activi
                 <span className="text-muted-foreground text-sm">{monthDay}, {year}</span>
               </div>
               <div className="text-xs text-muted-foreground mt-0.5 font-medium truncate max-w-[200px]">
-                {activity.location ? (
-                  <div className="flex flex-col">
-                    <span className="text-foreground text-sm font-semibold">{activity.location}</span>
-                    <span className="opacity-70 text-[10px]">{activity.filename.replace('.fit', '').replace('.FIT', '')}</span>
-                  </div>
-                ) : (
-                  activity.filename.replace('.fit', '').replace('.FIT', '')
-                )}
+                <div className="flex flex-col">
+                  <span className="text-foreground text-sm font-semibold">{activity.title}</span>
+                  {activity.location && <span className="opacity-70 text-[10px]">{activity.location}</span>}
+                </div>
               </div>
             </div>
           </div>
@@ -6,7 +6,7 @@ import { HeartRateAnalysisTable } from "@/components/HeartRateAnalysisTable";
 import { HeartRateRecoveryTable } from "@/components/HeartRateRecoveryTable";
 import { PaceHeartRateChart } from "@/components/PaceHeartRateChart";
 import { ElevationAnalysisChart } from "@/components/ElevationAnalysisChart";
-import { MapView } from "@/components/MapView";
+import { MapView, recordsToTrack } from "@/components/MapView";
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card";
 import { api } from "@/lib/api";
 import type { Activity, ActivityDetail } from "@/lib/types";
@@ -209,7 +209,7 @@ export function ActivityDetailView({ activity }: ActivityDetailViewProps) {
               </div>
               <div>
                 <h2 className="metric-medium">
-                  {activity.location || activity.filename.replace('.fit', '').replace('.FIT', '')}
+                  {activity.title}
                 </h2>
                 <div className="flex items-center gap-1.5 text-sm text-muted-foreground mt-1">
                   <Calendar className="w-3.5 h-3.5" />
@@ -303,7 +303,7 @@ export function ActivityDetailView({ activity }: ActivityDetailViewProps) {
             </div>
           </CardHeader>
           <CardContent className="p-0 h-[450px]">
-            <MapView tracks={[detail.records]} />
+            <MapView tracks={[recordsToTrack(detail.records)]} />
           </CardContent>
         </Card>
       )}
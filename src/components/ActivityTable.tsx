@@ -29,7 +29,7 @@ export function ActivityTable({ activities, onSelect, onDelete }: ActivityTableP
 
   const handleDelete = (e: React.MouseEvent, activity: Activity) => {
     e.stopPropagation();
-    if (confirm(`Delete "${activity.filename}"?`)) {
+    if (confirm(`Delete "${activity.title}"?`)) {
       onDelete(activity);
     }
   };
@@ -86,14 +86,10 @@ export function ActivityTable({ activities, onSelect, onDelete }: ActivityTableP
                 </TableCell>
                 <TableCell>
                   <div className="font-medium text-sm truncate max-w-[200px] md:max-w-[300px]">
-                    {activity.location ? (
-                      <div className="flex flex-col">
-                        <span>{activity.location}</span>
-                        <span className="text-[10px] text-muted-foreground">{activity.filename.replace('.fit', '').replace('.FIT', '')}</span>
-                      </div>
-                    ) : (
-                      activity.filename.replace('.fit', '').replace('.FIT', '')
-                    )}
+                    <div className="flex flex-col">
+                      <span>{activity.title}</span>
+                      {activity.location && <span className="text-[10px] text-muted-foreground">{activity.location}</span>}
+                    </div>
                   </div>
                 </TableCell>
                 <TableCell className="text-right font-mono text-xs text-muted-foreground">
@@ -1,9 +1,9 @@
 import { useEffect, useRef } from "react";
 import maplibregl from "maplibre-gl";
-import type { TrackRecord } from "@/lib/types";
+import type { LatLon, TrackRecord } from "@/lib/types";
 
 interface MapViewProps {
-  tracks?: TrackRecord[][];
+  tracks?: LatLon[][];
   center?: [number, number];
   zoom?: number;
 }
@@ -98,21 +98,28 @@ export function MapView({ tracks = [], center, zoom = 10 }: MapViewProps) {
   );
 }
 
-function calculateCenter(tracks: TrackRecord[][]): [number, number] {
-  const allPoints = tracks.flatMap((t) => t).filter((r) => r.positionLat && r.positionLong);
+/** Track coordinates of records, preferring the cleaned GPS track */
+export function recordsToTrack(records: TrackRecord[]): LatLon[] {
+  return records
+    .map((r) => [r.cleanPositionLat ?? r.positionLat, r.cleanPositionLong ?? r.positionLong])
+    .filter((p): p is LatLon => p[0] != null && p[1] != null);
+}
+
+function calculateCenter(tracks: LatLon[][]): [number, number] {
+  const allPoints = tracks.flatMap((t) => t);
 
   if (allPoints.length === 0) {
     // Default to NJ center
     return [-74.4057, 40.0583];
   }
 
-  const sumLat = allPoints.reduce((sum, r) => sum + (r.positionLat || 0), 0);
-  const sumLng = allPoints.reduce((sum, r) => sum + (r.positionLong || 0), 0);
+  const sumLat = allPoints.reduce((sum, [lat]) => sum + lat, 0);
+  const sumLng = allPoints.reduce((sum, [, lng]) => sum + lng, 0);
 
   return [sumLng / allPoints.length, sumLat / allPoints.length];
 }
 
-function addTracksToMap(map: maplibregl.Map, tracks: TrackRecord[][]) {
+function addTracksToMap(map: maplibregl.Map, tracks: LatLon[][]) {
   // Remove existing track layers/sources
   tracks.forEach((_, i) => {
     if (map.getLayer(`track-${i}`)) {
@@ -125,9 +132,7 @@ function addTracksToMap(map: maplibregl.Map, tracks: TrackRecord[][]) {
 
   // Add new tracks
   tracks.forEach((track, i) => {
-    const coordinates = track
-      .filter((r) => r.positionLat && r.positionLong)
-      .map((r) => [r.positionLong!, r.positionLat!]);
+    const coordinates = track.map(([lat, lng]) => [lng, lat]);
 
     if (coordinates.length < 2) return;
 
@@ -163,8 +168,7 @@ function addTracksToMap(map: maplibregl.Map, tracks: TrackRecord[][]) {
   if (tracks.length > 0) {
     const allCoords = tracks
       .flatMap((t) => t)
-      .filter((r) => r.positionLat && r.positionLong)
-      .map((r) => [r.positionLong!, r.positionLat!] as [number, number]);
+      .map(([lat, lng]) => [lng, lat] as [number, number]);
 
     if (allCoords.length > 1) {
       const bounds = allCoords.reduce(
@@ -1,5 +1,47 @@
 import { invoke } from "@tauri-apps/api/core";
-import type { Activity, ActivityDetail, ZoneSummary } from "./types";
+import type {
+  Activity,
+  ActivityDetail,
+  ActivityFilter,
+  ActivityMetadata,
+  ActivityStreams,
+  ActivityTrack,
+  ActivityType,
+  ActivityTypeInfo,
+  AnalyticsExport,
+  AnalyticsFilter,
+  AnalyticsFormat,
+  BackupInfo,
+  BackupSchedule,
+  BaggedPoi,
+  ElevationFilter,
+  ElevationSource,
+  ExportFormat,
+  Gear,
+  GearDetails,
+  GearUsage,
+  LatLon,
+  PoiKind,
+  PoiVisit,
+  Profile,
+  ProfileDetails,
+  Region,
+  ReparseSummary,
+  Route,
+  RouteAttempt,
+  Segment,
+  SegmentEffort,
+  Split,
+  SplitUnit,
+  StreamAxis,
+  Tag,
+  TrackCleaning,
+  Trip,
+  TripDetail,
+  TripSuggestion,
+  TripTrack,
+  ZoneSummary,
+} from "./types";
 
 export interface TileMetadata {
   name: string;
@@ -13,12 +55,144 @@ export const api = {
   importFitFiles: (paths: string[]) =>
     invoke<Activity[]>("import_fit_files", { paths }),
 
-  listActivities: () =>
-    invoke<Activity[]>("list_activities"),
+  listActivities: (filter?: ActivityFilter) =>
+    invoke<Activity[]>("list_activities", { filter }),
+
+  updateActivityMetadata: (id: number, metadata: ActivityMetadata) =>
+    invoke<Activity>("update_activity_metadata", { id, metadata }),
+
+  listTags: () =>
+    invoke<Tag[]>("list_tags"),
+
+  listActivityTypes: () =>
+    invoke<ActivityTypeInfo[]>("list_activity_types"),
+
+  saveActivityType: (name: string, icon: string, color: string) =>
+    invoke<void>("save_activity_type", { name, icon, color }),
+
+  deleteActivityType: (name: string) =>
+    invoke<void>("delete_activity_type", { name }),
+
+  updateActivityType: (id: number, activityType: ActivityType) =>
+    invoke<Activity>("update_activity_type", { id, activityType }),
+
+  listProfiles: () =>
+    invoke<Profile[]>("list_profiles"),
+
+  getActiveProfile: () =>
+    invoke<Profile>("get_active_profile"),
+
+  /** Creates the profile when `id` is omitted; returns its id */
+  saveProfile: (profile: ProfileDetails, id?: number) =>
+    invoke<number>("save_profile", { id, profile }),
+
+  deleteProfile: (id: number) =>
+    invoke<void>("delete_profile", { id }),
+
+  switchProfile: (id: number) =>
+    invoke<Profile>("switch_profile", { id }),
+
+  listGear: () =>
+    invoke<GearUsage[]>("list_gear"),
+
+  /** Creates the item when `id` is omitted; returns its id */
+  saveGear: (gear: GearDetails, id?: number) =>
+    invoke<number>("save_gear", { id, gear }),
+
+  deleteGear: (id: number) =>
+    invoke<void>("delete_gear", { id }),
+
+  getActivityGear: (activityId: number) =>
+    invoke<Gear[]>("get_activity_gear", { activityId }),
+
+  setActivityGear: (activityId: number, gearIds: number[]) =>
+    invoke<void>("set_activity_gear", { activityId, gearIds }),
+
+  listRegions: () =>
+    invoke<Region[]>("list_regions"),
 
   getActivity: (id: number) =>
     invoke<ActivityDetail>("get_activity", { id }),
 
+  getActivitySplits: (id: number, unit: SplitUnit) =>
+    invoke<Split[]>("get_activity_splits", { id, unit }),
+
+  /** Record fields (e.g. "heartRate", "altitude" or an extras key) downsampled for charts */
+  getActivityStreams: (id: number, fields: string[], maxPoints?: number, resolutionBy?: StreamAxis) =>
+    invoke<ActivityStreams>("get_activity_streams", { id, fields, maxPoints, resolutionBy }),
+
+  /** Exports the given activities, or all matching `filter`; returns the number written */
+  exportActivities: (format: ExportFormat, path: string, ids?: number[], filter?: ActivityFilter) =>
+    invoke<number>("export_activities", { ids, filter, format, path }),
+
+  /** Writes activities and records tables for analysis into `directory` */
+  exportAnalytics: (format: AnalyticsFormat, directory: string, filter?: AnalyticsFilter) =>
+    invoke<AnalyticsExport>("export_analytics", { filter, format, directory }),
+
+  getActivityTracks: (ids: number[], tolerance?: number, encoded?: boolean) =>
+    invoke<ActivityTrack[]>("get_activity_tracks", { ids, tolerance, encoded }),
+
+  findActivitiesInBbox: (minLat: number, minLon: number, maxLat: number, maxLon: number) =>
+    invoke<Activity[]>("find_activities_in_bbox", { minLat, minLon, maxLat, maxLon }),
+
+  findActivitiesNear: (lat: number, lon: number, radius: number) =>
+    invoke<Activity[]>("find_activities_near", { lat, lon, radius }),
+
+  listRoutes: () =>
+    invoke<Route[]>("list_routes"),
+
+  renameRoute: (id: number, name: string) =>
+    invoke<void>("rename_route", { id, name }),
+
+  getRouteAttempts: (routeId: number) =>
+    invoke<RouteAttempt[]>("get_route_attempts", { routeId }),
+
+  /** Creates the trip when `id` is omitted; returns its id */
+  saveTrip: (name: string, activityIds: number[], id?: number) =>
+    invoke<number>("save_trip", { id, name, activityIds }),
+
+  deleteTrip: (id: number) =>
+    invoke<void>("delete_trip", { id }),
+
+  listTrips: () =>
+    invoke<Trip[]>("list_trips"),
+
+  getTrip: (id: number) =>
+    invoke<TripDetail>("get_trip", { id }),
+
+  getTripTrack: (id: number, tolerance?: number, encoded?: boolean) =>
+    invoke<TripTrack>("get_trip_track", { id, tolerance, encoded }),
+
+  suggestTrips: () =>
+    invoke<TripSuggestion[]>("suggest_trips"),
+
+  createSegment: (name: string, points: LatLon[]) =>
+    invoke<number>("create_segment", { name, points }),
+
+  createSegmentFromActivity: (activityId: number, startIndex: number, endIndex: number, name: string) =>
+    invoke<number>("create_segment_from_activity", { activityId, startIndex, endIndex, name }),
+
+  listSegments: () =>
+    invoke<Segment[]>("list_segments"),
+
+  getSegmentLeaderboard: (segmentId: number) =>
+    invoke<SegmentEffort[]>("get_segment_leaderboard", { segmentId }),
+
+  deleteSegment: (id: number) =>
+    invoke<void>("delete_segment", { id }),
+
+  importPois: (path: string) =>
+    invoke<number>("import_pois", { path }),
+
+  getActivityPois: (id: number) =>
+    invoke<PoiVisit[]>("get_activity_pois", { id }),
+
+  listBaggedPois: (kind?: PoiKind) =>
+    invoke<BaggedPoi[]>("list_bagged_pois", { kind }),
+
+  getPoiVisits: (poiId: number) =>
+    invoke<PoiVisit[]>("get_poi_visits", { poiId }),
+
   getWeeklySummary: (weekStart: string) =>
     invoke<ZoneSummary>("get_weekly_summary", { weekStart }),
 
@@ -28,6 +202,75 @@ export const api = {
   deleteActivity: (id: number) =>
     invoke<void>("delete_activity", { id }),
 
+  /** Parses an activity's original file again with the current parser and settings */
+  reparseActivity: (id: number) =>
+    invoke<Activity>("reparse_activity", { id }),
+
+  reparseAll: () =>
+    invoke<ReparseSummary>("reparse_all"),
+
+  /** Keeps only the records between `start` and `end` seconds (or metres); the original file is dropped */
+  trimActivity: (id: number, start: number, end: number, by?: StreamAxis) =>
+    invoke<Activity>("trim_activity", { id, start, end, by }),
+
+  /** Merges recordings of one outing into the earliest of them; the others are deleted */
+  mergeActivities: (ids: number[]) =>
+    invoke<Activity>("merge_activities", { ids }),
+
+  /** Splits an activity at each point of `at`; returns it followed by the new parts */
+  splitActivity: (id: number, at: number[], by?: StreamAxis) =>
+    invoke<Activity[]>("split_activity", { id, at, by }),
+
+  /** Writes the original file an activity was imported from to `path` */
+  exportSourceFile: (id: number, path: string) =>
+    invoke<void>("export_source_file", { id, path }),
+
+  getElevationFilter: () =>
+    invoke<ElevationFilter>("get_elevation_filter"),
+
+  setElevationFilter: (filter: ElevationFilter) =>
+    invoke<void>("set_elevation_filter", { filter }),
+
+  recalculateElevation: () =>
+    invoke<number>("recalculate_elevation"),
+
+  getTrackCleaning: () =>
+    invoke<TrackCleaning>("get_track_cleaning"),
+
+  setTrackCleaning: (cleaning: TrackCleaning) =>
+    invoke<void>("set_track_cleaning", { cleaning }),
+
+  // Backups
+  /** Backs up to a timestamped file in `directory`, or the app's backup directory */
+  backupDatabase: (directory?: string) =>
+    invoke<BackupInfo>("backup_database", { directory }),
+
+  listBackups: () =>
+    invoke<BackupInfo[]>("list_backups"),
+
+  /** Replaces all data with a backup; the current data is backed up first */
+  restoreDatabase: (path: string) =>
+    invoke<void>("restore_database", { path }),
+
+  getBackupSchedule: () =>
+    invoke<BackupSchedule>("get_backup_schedule"),
+
+  setBackupSchedule: (schedule: BackupSchedule) =>
+    invoke<void>("set_backup_schedule", { schedule }),
+
+  // DEM elevation correction
+  listDemTiles: () =>
+    invoke<string[]>("list_dem_tiles"),
+
+  getDemPath: () =>
+    invoke<string>("get_dem_path"),
+
+  correctElevation: (id: number) =>
+    invoke<Activity>("correct_elevation", { id }),
+
+  setElevationSource: (id: number, source: ElevationSource) =>
+    invoke<Activity>("set_elevation_source", { id, source }),
+
   // Tile server API
   listTileFiles: () =>
     invoke<string[]>("list_tile_files"),
@@ -9,6 +9,10 @@ export interface ZoneTimes {
 export interface Activity {
   id: number;
   filename: string;
+  title: string;
+  description?: string;
+  notes?: string;
+  tags: string[];
   activityType: ActivityType;
   activityDate: string;
   startTime: string;
@@ -17,10 +21,62 @@ export interface Activity {
   totalDistance?: number;
   zones: ZoneTimes;
   elevationGain?: number;
+  elevationLoss?: number;
   maxAltitude?: number;
   minAltitude?: number;
+  /** Average moving grade-adjusted speed in m/s */
+  avgGradeAdjustedSpeed?: number;
+  /** Equivalent flat distance including climbing, in metres */
+  effortDistance?: number;
+  /** Altitude source used for gain/loss */
+  elevationSource: ElevationSource;
+  /** Route this activity was matched to */
+  routeId?: number;
+  startLocation?: string;
+  endLocation?: string;
+  highPointLocation?: string;
+  /** Administrative regions of the start */
+  admin1?: string;
+  admin2?: string;
+  countryCode?: string;
+  /** Straight-line distance between start and end in metres */
+  startEndDistance?: number;
 }
 
+/** Unset fields match every activity */
+export interface ActivityFilter {
+  countryCode?: string;
+  admin1?: string;
+  admin2?: string;
+  /** true for point-to-point traverses, false for loops and out-and-backs */
+  traverse?: boolean;
+  /** Activities must carry every one of these tags */
+  tags?: string[];
+}
+
+/** Unset fields are left unchanged; empty description or notes clear them */
+export interface ActivityMetadata {
+  title?: string;
+  description?: string;
+  notes?: string;
+  /** Replaces all tags */
+  tags?: string[];
+}
+
+export interface Tag {
+  name: string;
+  activityCount: number;
+}
+
+export interface Region {
+  countryCode?: string;
+  admin1?: string;
+  admin2?: string;
+  activityCount: number;
+}
+
+export type ElevationSource = "device" | "dem";
+
 export type Zone = 'zone1' | 'zone2' | 'zone3' | 'zone4' | 'zone5';
 
 export interface TrackRecord {
@@ -33,7 +89,13 @@ export interface TrackRecord {
   temperature: number | null;
   positionLat: number | null;
   positionLong: number | null;
+  /** Position after outlier rejection and smoothing */
+  cleanPositionLat: number | null;
+  cleanPositionLong: number | null;
   zone: Zone;
+  gradeAdjustedSpeed: number | null;
+  /** Terrain altitude sampled from DEM tiles, when corrected */
+  correctedAltitude: number | null;
   extras: Record<string, number>;
 }
 
@@ -41,10 +103,321 @@ export interface ActivityDetail extends Activity {
   records: TrackRecord[];
 }
 
-export type ActivityType = "Hike" | "Walk" | "Swimming" | "Run" | "Strength" | "Other";
+/** Name of a built-in or user-defined activity type, such as "Hike" */
+export type ActivityType = string;
+
+export interface ActivityTypeInfo {
+  name: ActivityType;
+  /** Lucide icon name */
+  icon: string;
+  /** Hex colour such as "#16a34a" */
+  color: string;
+  /** Built-in types can be restyled but not deleted */
+  builtin: boolean;
+  activityCount: number;
+}
 
 export interface ZoneSummary {
   periodStart: string;
   activityCount: number;
   zones: ZoneTimes;
 }
+
+export type StreamAxis = "time" | "distance";
+
+/** One record field along the stream axis; records missing it are left out */
+export interface RecordStream {
+  x: number[];
+  values: number[];
+}
+
+export interface ActivityStreams {
+  activityId: number;
+  resolutionBy: StreamAxis;
+  /** Records before downsampling */
+  recordCount: number;
+  streams: Record<string, RecordStream>;
+}
+
+export type SplitUnit = "km" | "mi";
+
+export interface Split {
+  index: number;
+  /** Distance covered in metres; the last split may be partial */
+  distance: number;
+  elapsedTime: number;
+  movingTime: number;
+  /** Seconds per split unit */
+  movingPace: number | null;
+  /** Seconds per split unit */
+  gradeAdjustedPace: number | null;
+  elevationGain: number;
+  elevationLoss: number;
+  avgHeartRate: number | null;
+  zones: ZoneTimes;
+}
+
+export type ElevationSmoothing =
+  | { method: "none" }
+  | { method: "median"; window: number }
+  | { method: "kalman"; processNoise: number; measurementNoise: number };
+
+export interface ElevationFilter {
+  smoothing: ElevationSmoothing;
+  /** Readings implying a faster vertical speed (m/s) are dropped as spikes */
+  maxVerticalSpeed: number | null;
+  /** Minimum climb or descent in metres before it counts */
+  hysteresis: number;
+}
+
+export type TrackSmoothing =
+  | { method: "none" }
+  | { method: "savitzkyGolay"; window: number }
+  | { method: "kalman"; processNoise: number; measurementNoise: number };
+
+export interface TrackCleaning {
+  /** Fixes implying a faster speed (m/s) from the last good fix are rejected */
+  maxSpeed: number;
+  smoothing: TrackSmoothing;
+}
+
+/** [lat, lon] in degrees */
+export type LatLon = [number, number];
+
+export interface ActivityTrack {
+  activityId: number;
+  /** Present unless an encoded polyline was requested */
+  points: LatLon[] | null;
+  /** Google encoded polyline, when requested */
+  polyline: string | null;
+}
+
+export interface Route {
+  id: number;
+  name: string;
+  attemptCount: number;
+  /** Distance of the first attempt in metres */
+  distance?: number;
+  bestDuration: number;
+  lastAttempt: string;
+}
+
+export interface RouteAttempt extends Activity {
+  avgHeartRate?: number;
+  maxHeartRate?: number;
+  /** Seconds slower than the fastest attempt */
+  behindBest: number;
+  /** 1 for the fastest attempt */
+  rank: number;
+}
+
+export interface Trip {
+  id: number;
+  name: string;
+  startDate: string;
+  endDate: string;
+  /** Days from the first to the last date, including rest days */
+  dayCount: number;
+  activityCount: number;
+  totalDistance?: number;
+  totalDuration: number;
+  elevationGain?: number;
+  elevationLoss?: number;
+  zones: ZoneTimes;
+}
+
+export interface TripDay {
+  date: string;
+  /** 1 for the first day of the trip */
+  day: number;
+  activityIds: number[];
+  totalDistance?: number;
+  totalDuration: number;
+  elevationGain?: number;
+  elevationLoss?: number;
+  zones: ZoneTimes;
+}
+
+export interface TripDetail extends Trip {
+  days: TripDay[];
+  /** In date order */
+  activities: Activity[];
+}
+
+export interface TripSuggestion {
+  name: string;
+  activities: Activity[];
+}
+
+export interface TripTrack {
+  tripId: number;
+  /** Present unless an encoded polyline was requested */
+  points: LatLon[] | null;
+  /** Google encoded polyline, when requested */
+  polyline: string | null;
+}
+
+export interface Segment {
+  id: number;
+  name: string;
+  /** Positions from start to end */
+  points: LatLon[];
+  /** Length in metres */
+  distance: number;
+  effortCount: number;
+  bestTime?: number;
+}
+
+export interface SegmentEffort {
+  id: number;
+  segmentId: number;
+  activityId: number;
+  activityDate: string;
+  /** Timestamp of the record closest to the segment start */
+  startTime: string;
+  elapsedTime: number;
+  avgHeartRate?: number;
+  /** Net altitude change per hour in m/h */
+  verticalSpeed?: number;
+  /** 1 for the fastest effort */
+  rank: number;
+}
+
+export type PoiKind = "peak" | "hut" | "pass";
+
+export interface Poi {
+  id: number;
+  name: string;
+  kind: PoiKind;
+  lat: number;
+  lon: number;
+  elevation?: number;
+}
+
+export interface PoiVisit {
+  poi: Poi;
+  activityId: number;
+  activityDate: string;
+  /** Timestamp of the closest approach */
+  visitedAt: string;
+  /** Closest approach in metres */
+  distance: number;
+}
+
+export interface BaggedPoi extends Poi {
+  visitCount: number;
+  firstVisit: string;
+  lastVisit: string;
+}
+
+/** Editable fields of a gear item */
+export interface GearDetails {
+  name: string;
+  /** Kind of gear such as "boots", "poles" or "pack" */
+  gearType: string;
+  brand?: string;
+  model?: string;
+  /** YYYY-MM-DD */
+  purchaseDate?: string;
+  /** YYYY-MM-DD; retired gear is no longer added to new activities */
+  retiredDate?: string;
+  /** Metres */
+  distanceLimit?: number;
+  /** Metres of elevation gain */
+  elevationLimit?: number;
+  hoursLimit?: number;
+  notes?: string;
+  /** Activity types the item is added to on import */
+  defaultTypes: ActivityType[];
+}
+
+export interface Gear extends GearDetails {
+  id: number;
+}
+
+export interface GearAlert {
+  metric: "distance" | "elevation" | "hours";
+  limit: number;
+  value: number;
+}
+
+export interface GearUsage extends Gear {
+  activityCount: number;
+  /** Metres */
+  distance: number;
+  /** Metres */
+  elevationGain: number;
+  hours: number;
+  lastUsed?: string;
+  alerts: GearAlert[];
+}
+
+/** How a profile's heart rate zones are derived */
+export type ZoneModel = "fixed" | "max_hr" | "heart_rate_reserve";
+
+/** Editable fields of an athlete profile */
+export interface ProfileDetails {
+  name: string;
+  /** YYYY-MM-DD */
+  birthdate?: string;
+  sex?: string;
+  /** Kilograms */
+  weight?: number;
+  restingHr?: number;
+  maxHr?: number;
+  zoneModel: ZoneModel;
+}
+
+export interface Profile extends ProfileDetails {
+  id: number;
+  activityCount: number;
+}
+
+export type ExportFormat = "gpx" | "tcx" | "geojson" | "fit";
+
+export type AnalyticsFormat = "csv" | "parquet";
+
+export interface AnalyticsFilter {
+  /** YYYY-MM-DD */
+  startDate?: string;
+  /** YYYY-MM-DD */
+  endDate?: string;
+  activityTypes?: string[];
+}
+
+export interface AnalyticsExport {
+  activitiesPath: string;
+  recordsPath: string;
+  activityCount: number;
+  recordCount: number;
+}
+
+export interface BackupInfo {
+  path: string;
+  createdAt: string;
+  /** Bytes */
+  size: number;
+}
+
+export interface BackupSchedule {
+  enabled: boolean;
+  intervalHours: number;
+  /** Number of backups kept */
+  keep: number;
+  /** Why the last scheduled backup failed; set by the backend only */
+  lastError?: string | null;
+}
+
+export interface ReparseFailure {
+  activityId: number;
+  error: string;
+}
+
+export interface ReparseSummary {
+  reparsed: number;
+  /** Activities imported before original files were kept */
+  withoutSource: number;
+  /** Activities whose DEM elevation correction couldn't be re-applied */
+  elevationCorrectionsDropped: number[];
+  failures: ReparseFailure[];
+}
@@ -3,12 +3,12 @@ import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card";
 import { PageHeader } from "@/components/PageHeader";
 import { MapView } from "@/components/MapView";
 import { api } from "@/lib/api";
-import type { Activity, TrackRecord } from "@/lib/types";
+import type { Activity, LatLon } from "@/lib/types";
 
 export function MapPage() {
   const [activities, setActivities] = useState<Activity[]>([]);
   const [selectedIds, setSelectedIds] = useState<Set<number>>(new Set());
-  const [tracks, setTracks] = useState<TrackRecord[][]>([]);
+  const [tracks, setTracks] = useState<LatLon[][]>([]);
   const [loading, setLoading] = useState(true);
 
   useEffect(() => {
@@ -33,17 +33,17 @@ export function MapPage() {
   // Load track data for selected activities
   useEffect(() => {
     async function loadTracks() {
-      const trackPromises = Array.from(selectedIds).map(async (id) => {
-        try {
-          const detail = await api.getActivity(id);
-          return detail.records;
-        } catch {
-          return [];
-        }
-      });
-
-      const loadedTracks = await Promise.all(trackPromises);
-      setTracks(loadedTracks.filter((t) => t.length > 0));
+      try {
+        const loadedTracks = await api.getActivityTracks(Array.from(selectedIds));
+        setTracks(
+          loadedTracks
+            .map((t) => t.points ?? [])
+            .filter((t) => t.length > 0)
+        );
+      } catch (error) {
+        console.error("Failed to load tracks:", error);
+        setTracks([]);
+      }
     }
 
     if (selectedIds.size > 0) {
@@ -100,7 +100,7 @@ export function MapPage() {
                     }`}
                   >
                     <div className="font-medium truncate">
-                      {activity.filename}
+                      {activity.title}
                     </div>
                     <div
                       className={`text-xs ${